@@ -7,7 +7,7 @@ baz
 
 extern int printf(const char*, ...);
 int main(int argc, const char* argv[]) {
-	//printf("Hello, %s! float %f", "world", 1.23f);
+	printf("Hello, %s! float %f", "world", 1.23f);
 	printf("Hello, %s!", "world");
 
 	for(int i = 0; i < 10; i ++)
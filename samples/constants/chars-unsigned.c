@@ -0,0 +1,3 @@
+/* Character constants on a target where plain char is unsigned */
+int c1 = 'A', c2 = '\0', c3 = '\x7f', c4 = '\x80', c5 = '\377';
+int w1 = L'\xffffffff', w2 = u'\xffff', w3 = U'\xffffffff';
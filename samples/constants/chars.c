@@ -0,0 +1,3 @@
+/* Character constants, printed as their values (plain char is signed on the default target) */
+int c1 = 'A', c2 = '\0', c3 = '\x7f', c4 = '\x80', c5 = '\377';
+int w1 = L'\xffffffff', w2 = u'\xffff', w3 = U'\xffffffff';
@@ -0,0 +1,20 @@
+#!/bin/sh
+# Regression check for constant values: compile each sample and compare the printed source against the `.expected`
+# output (integer constants are printed as their values, so a wrongly converted constant shows up as a difference;
+# floating constants keep their spelling), with any extra options from the sample's `.flags` file
+#
+# Usage: samples/constants/check.sh [path/to/cc]
+CC=${1:-"cargo run -q --"}
+cd "$(dirname "$0")" || exit 1
+status=0
+for src in *.c
+do
+	flags=$(cat "${src%.c}.flags" 2>/dev/null)
+	if $CC $flags "$src" | diff -u "${src%.c}.expected" - ; then
+		echo "ok   $src"
+	else
+		echo "FAIL $src"
+		status=1
+	fi
+done
+exit $status
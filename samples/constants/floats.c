@@ -0,0 +1,14 @@
+/* Floating constants, printed as written (the spelling carries the type and keeps division floating) */
+/* Forms of decimal constant */
+double d1 = 1.5, d2 = 1., d3 = .5, d4 = .5e1, d5 = .5e+1, d6 = 5e-1, d7 = 1.5E2, d8 = 0.000;
+/* Rounding to double (halfway cases round to even) */
+double r1 = 0.1, r2 = 0.30000000000000004, r3 = 9007199254740993.0, r4 = 9007199254740995.0;
+double r5 = 1.00000000000000011102230246251565404236316680908203125;
+double r6 = 1.00000000000000011102230246251565404236316680908203126;
+/* Rounding to float */
+float f1 = 1.1f, f2 = 16777217.f, f3 = 3.4028234e38F, f4 = 1e-45f;
+/* Hexadecimal constants */
+double h1 = 0x1p0, h2 = 0x1.8p1, h3 = 0x.8p1, h4 = 0xA.Bp-4, h5 = 0x1P+10;
+float h8 = 0x1.fffffep127f;
+/* Constant expressions */
+double e1 = 1./2, e2 = 1.f / 3, e3 = -.5L * 2;
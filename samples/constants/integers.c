@@ -0,0 +1,11 @@
+/* Integer constants, printed as their values */
+/* Bases (binary is a GNU extension) */
+int b1 = 42, b2 = 052, b3 = 0x2a, b4 = 0X2A, b5 = 0b101010, b6 = 0B101010, b7 = 0;
+/* Suffixes, in any order and case */
+long s1 = 10l, s2 = 10L, s3 = 10ul, s4 = 10LU, s5 = 10uL, s6 = 10Ul;
+long long s7 = 10ll, s8 = 10LL, s9 = 10ull, s10 = 10LLU, s11 = 10uLL, s12 = 10Ull;
+/* Limits of each type */
+int l1 = 2147483647, l2 = 0x7fffffff;
+unsigned int l3 = 0xffffffff, l4 = 4294967295u;
+long l5 = 9223372036854775807, l6 = 0x7fffffffffffffffl;
+unsigned long l7 = 0xffffffffffffffff, l8 = 18446744073709551615ul, l9 = 01777777777777777777777;
@@ -0,0 +1,19 @@
+#!/bin/sh
+# Regression check for the lexer: pre-process each sample and compare against the `.expected` output (with any extra
+# options from the sample's `.flags` file)
+#
+# Usage: samples/lexer/check.sh [path/to/cc]
+CC=${1:-"cargo run -q --"}
+cd "$(dirname "$0")" || exit 1
+status=0
+for src in *.c
+do
+	flags=$(cat "${src%.c}.flags" 2>/dev/null)
+	if $CC $flags -E -P "$src" | diff -u "${src%.c}.expected" - ; then
+		echo "ok   $src"
+	else
+		echo "FAIL $src"
+		status=1
+	fi
+done
+exit $status
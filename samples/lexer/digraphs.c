@@ -0,0 +1,12 @@
+/* Digraphs are the punctuators they spell, but keep their spelling in the output */
+%:define str(x) %:x
+%:define xstr(x) str(x)
+%:define cat(a, b) a %:%: b
+int a<:2:> = <% 1, 2 %>;
+str(<:) str(:>) str(<%) str(%>) str(%:) str(%:%:)
+xstr(cat(x, y)) cat(<, :) cat(%, :)
+%:if 1
+directives: ok
+%:endif
+/* `..` is two `.` tokens, and `%:%` is `%:` then `%` */
+a..b ..5 ... %:%
@@ -0,0 +1,15 @@
+/* Escape sequences in character constants and string literals, written back as spelled */
+#define str(x) # x
+#define xstr(x) str(x)
+/* Simple escapes */
+"\' \" \? \\ \a \b \f \n \r \t \v"
+'\'' '\"' '\?' '\\' '\a' '\b' '\f' '\n' '\r' '\t' '\v'
+/* Octal (up to three digits) and hexadecimal (any number of digits) */
+"\0 \7 \77 \101 \1011" '\0' '\377'
+"\x41 \x7f \x0000041" '\x41' '\xff'
+/* Universal character names */
+"é \U0001F600" L'é' u"\u20ac" U'\U0001F600'
+"é \u20AC" u8"é"
+/* Stringification escapes the quotes and backslashes of literals */
+str("\x41\n") str('\'') str(L"\\")
+xstr(str("a\tb"))
@@ -0,0 +1,18 @@
+/* Encoding prefixes and multi-character constants */
+#define str(x) # x
+/* A prefix directly before the quote, otherwise an identifier */
+L"wide" u"utf-16" U"utf-32" u8"utf-8"
+L'w' u'x' U'y' u8 "z" L u U
+#define L "not a prefix"
+L"wide" L 'w'
+str(u8"a" U'b')
+/* Character constant values in #if (char is signed, wchar_t is a 32-bit int) */
+#if 'AB' == 0x4142 && 'ABCD' == 0x41424344
+multi-character constants: ok
+#endif
+#if '\377' < 0 && L'\xffffffff' < 0 && u'\xffff' > 0 && U'\xffffffff' > 0
+signedness: ok
+#endif
+#if u'é' == 0xe9 && U'\U0001F600' == 0x1F600 && L'é' == 233
+unicode values: ok
+#endif
@@ -0,0 +1,8 @@
+??=define str(x) ??=x
+/* Every trigraph, in code and in literals */
+int a??(2??) = ??< 1 ??' 2, ??-3 ??! 4 ??>;
+"??= ??( ??/?? ??) ??' ??< ??! ??> ??-"
+str(??=) str('??/n')
+/* Not trigraphs */
+"??? ?? ?=? ??"
+????=
@@ -0,0 +1,10 @@
+/* Argument counts: invocations with the wrong number of arguments are reported and dropped */
+#define f(a, b) [a|b]
+#define g() G
+#define h(x) <x>
+#define v(a, ...) {a|__VA_ARGS__}
+f(1, 2) f(1) f(1, 2, 3) f((1, 2), 3)
+g() g(x) g(,)
+h() h(1) h(1, 2)
+v() v(1) v(1, 2, 3)
+end
@@ -0,0 +1,21 @@
+#!/bin/sh
+# Regression check for macro expansion: pre-process each sample and compare against the `.expected` output (and the
+# error lines against the sample's `.errors` file, if it has one)
+#
+# Usage: samples/macros/check.sh [path/to/cc]
+CC=${1:-"cargo run -q --"}
+cd "$(dirname "$0")" || exit 1
+status=0
+for src in *.c
+do
+	if $CC -E -P "$src" 2>"${src%.c}.stderr" | diff -u "${src%.c}.expected" - &&
+		{ [ ! -f "${src%.c}.errors" ] || grep -E '^[^ ]+: error: ' "${src%.c}.stderr" | diff -u "${src%.c}.errors" - ; }
+	then
+		echo "ok   $src"
+	else
+		echo "FAIL $src"
+		status=1
+	fi
+	rm -f "${src%.c}.stderr"
+done
+exit $status
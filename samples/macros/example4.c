@@ -0,0 +1,18 @@
+/*
+ * C11 6.10.3.5 EXAMPLE 4
+ * - The `fputs` line is omitted (needs octal escapes and `@`)
+ * - The computed `#include` is replaced by just the expansion
+ */
+#define str(s) # s
+#define xstr(s) str(s)
+#define debug(s, t) printf("x" # s "= %d, x" # t "= %s", \
+ x ## s, x ## t)
+#define INCFILE(n) vers ## n
+#define glue(a, b) a ## b
+#define xglue(a, b) glue(a, b)
+#define HIGHLOW "hello"
+#define LOW LOW ", world"
+debug(1, 2);
+xstr(INCFILE(2).h)
+glue(HIGH, LOW);
+xglue(HIGH, LOW)
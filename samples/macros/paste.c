@@ -0,0 +1,7 @@
+/* Pasting (`##`) re-lexes the spellings of both operands as a single token */
+#define CAT(a, b) a ## b
+#define str(x) # x
+#define xstr(x) str(x)
+CAT(x, 1) CAT(_, y) CAT(1, e5) CAT(0x1, p3) CAT(., 5) CAT(L, "wide") CAT(u, 'c')
+CAT(+, =) CAT(<<, =) CAT(-, >) CAT(<, :) CAT(%, >)
+CAT(#, #) CAT(%:, %:) xstr(CAT(%:, %:))
@@ -0,0 +1,23 @@
+/*
+ * Rescanning and hide sets (C11 6.10.3.4)
+ */
+// Self-referential and mutually recursive macros aren't re-expanded
+#define foo foo
+#define a b
+#define b a
+foo a b
+// EXAMPLE from 6.10.3.4p4 (GCC gives `2*9*g`)
+#define f(a) a*g
+#define g(a) f(a)
+f(2)(9)
+// The name is painted even when it appears in an argument
+#define self(x) x self(x)
+self(self(1))
+// Arguments are fully expanded before substitution
+#define ONE 1
+#define id(x) x
+#define call(m, x) m(x)
+call(id, ONE) id(id)(ONE)
+#if defined(foo) && a == 0 && id(ONE)
+expanded in #if
+#endif
@@ -0,0 +1,976 @@
+/* Every pair of binary operators, with and without parens (generated) */
+void f(int a, int b, int c, int x)
+{
+	x = a * b * c;
+	x = (a * b) * c;
+	x = a * (b * c);
+	x = a * b / c;
+	x = (a * b) / c;
+	x = a * (b / c);
+	x = a * b % c;
+	x = (a * b) % c;
+	x = a * (b % c);
+	x = a * b + c;
+	x = (a * b) + c;
+	x = a * (b + c);
+	x = a * b - c;
+	x = (a * b) - c;
+	x = a * (b - c);
+	x = a * b << c;
+	x = (a * b) << c;
+	x = a * (b << c);
+	x = a * b >> c;
+	x = (a * b) >> c;
+	x = a * (b >> c);
+	x = a * b < c;
+	x = (a * b) < c;
+	x = a * (b < c);
+	x = a * b > c;
+	x = (a * b) > c;
+	x = a * (b > c);
+	x = a * b <= c;
+	x = (a * b) <= c;
+	x = a * (b <= c);
+	x = a * b >= c;
+	x = (a * b) >= c;
+	x = a * (b >= c);
+	x = a * b == c;
+	x = (a * b) == c;
+	x = a * (b == c);
+	x = a * b != c;
+	x = (a * b) != c;
+	x = a * (b != c);
+	x = a * b & c;
+	x = (a * b) & c;
+	x = a * (b & c);
+	x = a * b ^ c;
+	x = (a * b) ^ c;
+	x = a * (b ^ c);
+	x = a * b | c;
+	x = (a * b) | c;
+	x = a * (b | c);
+	x = a * b && c;
+	x = (a * b) && c;
+	x = a * (b && c);
+	x = a * b || c;
+	x = (a * b) || c;
+	x = a * (b || c);
+	x = a / b * c;
+	x = (a / b) * c;
+	x = a / (b * c);
+	x = a / b / c;
+	x = (a / b) / c;
+	x = a / (b / c);
+	x = a / b % c;
+	x = (a / b) % c;
+	x = a / (b % c);
+	x = a / b + c;
+	x = (a / b) + c;
+	x = a / (b + c);
+	x = a / b - c;
+	x = (a / b) - c;
+	x = a / (b - c);
+	x = a / b << c;
+	x = (a / b) << c;
+	x = a / (b << c);
+	x = a / b >> c;
+	x = (a / b) >> c;
+	x = a / (b >> c);
+	x = a / b < c;
+	x = (a / b) < c;
+	x = a / (b < c);
+	x = a / b > c;
+	x = (a / b) > c;
+	x = a / (b > c);
+	x = a / b <= c;
+	x = (a / b) <= c;
+	x = a / (b <= c);
+	x = a / b >= c;
+	x = (a / b) >= c;
+	x = a / (b >= c);
+	x = a / b == c;
+	x = (a / b) == c;
+	x = a / (b == c);
+	x = a / b != c;
+	x = (a / b) != c;
+	x = a / (b != c);
+	x = a / b & c;
+	x = (a / b) & c;
+	x = a / (b & c);
+	x = a / b ^ c;
+	x = (a / b) ^ c;
+	x = a / (b ^ c);
+	x = a / b | c;
+	x = (a / b) | c;
+	x = a / (b | c);
+	x = a / b && c;
+	x = (a / b) && c;
+	x = a / (b && c);
+	x = a / b || c;
+	x = (a / b) || c;
+	x = a / (b || c);
+	x = a % b * c;
+	x = (a % b) * c;
+	x = a % (b * c);
+	x = a % b / c;
+	x = (a % b) / c;
+	x = a % (b / c);
+	x = a % b % c;
+	x = (a % b) % c;
+	x = a % (b % c);
+	x = a % b + c;
+	x = (a % b) + c;
+	x = a % (b + c);
+	x = a % b - c;
+	x = (a % b) - c;
+	x = a % (b - c);
+	x = a % b << c;
+	x = (a % b) << c;
+	x = a % (b << c);
+	x = a % b >> c;
+	x = (a % b) >> c;
+	x = a % (b >> c);
+	x = a % b < c;
+	x = (a % b) < c;
+	x = a % (b < c);
+	x = a % b > c;
+	x = (a % b) > c;
+	x = a % (b > c);
+	x = a % b <= c;
+	x = (a % b) <= c;
+	x = a % (b <= c);
+	x = a % b >= c;
+	x = (a % b) >= c;
+	x = a % (b >= c);
+	x = a % b == c;
+	x = (a % b) == c;
+	x = a % (b == c);
+	x = a % b != c;
+	x = (a % b) != c;
+	x = a % (b != c);
+	x = a % b & c;
+	x = (a % b) & c;
+	x = a % (b & c);
+	x = a % b ^ c;
+	x = (a % b) ^ c;
+	x = a % (b ^ c);
+	x = a % b | c;
+	x = (a % b) | c;
+	x = a % (b | c);
+	x = a % b && c;
+	x = (a % b) && c;
+	x = a % (b && c);
+	x = a % b || c;
+	x = (a % b) || c;
+	x = a % (b || c);
+	x = a + b * c;
+	x = (a + b) * c;
+	x = a + (b * c);
+	x = a + b / c;
+	x = (a + b) / c;
+	x = a + (b / c);
+	x = a + b % c;
+	x = (a + b) % c;
+	x = a + (b % c);
+	x = a + b + c;
+	x = (a + b) + c;
+	x = a + (b + c);
+	x = a + b - c;
+	x = (a + b) - c;
+	x = a + (b - c);
+	x = a + b << c;
+	x = (a + b) << c;
+	x = a + (b << c);
+	x = a + b >> c;
+	x = (a + b) >> c;
+	x = a + (b >> c);
+	x = a + b < c;
+	x = (a + b) < c;
+	x = a + (b < c);
+	x = a + b > c;
+	x = (a + b) > c;
+	x = a + (b > c);
+	x = a + b <= c;
+	x = (a + b) <= c;
+	x = a + (b <= c);
+	x = a + b >= c;
+	x = (a + b) >= c;
+	x = a + (b >= c);
+	x = a + b == c;
+	x = (a + b) == c;
+	x = a + (b == c);
+	x = a + b != c;
+	x = (a + b) != c;
+	x = a + (b != c);
+	x = a + b & c;
+	x = (a + b) & c;
+	x = a + (b & c);
+	x = a + b ^ c;
+	x = (a + b) ^ c;
+	x = a + (b ^ c);
+	x = a + b | c;
+	x = (a + b) | c;
+	x = a + (b | c);
+	x = a + b && c;
+	x = (a + b) && c;
+	x = a + (b && c);
+	x = a + b || c;
+	x = (a + b) || c;
+	x = a + (b || c);
+	x = a - b * c;
+	x = (a - b) * c;
+	x = a - (b * c);
+	x = a - b / c;
+	x = (a - b) / c;
+	x = a - (b / c);
+	x = a - b % c;
+	x = (a - b) % c;
+	x = a - (b % c);
+	x = a - b + c;
+	x = (a - b) + c;
+	x = a - (b + c);
+	x = a - b - c;
+	x = (a - b) - c;
+	x = a - (b - c);
+	x = a - b << c;
+	x = (a - b) << c;
+	x = a - (b << c);
+	x = a - b >> c;
+	x = (a - b) >> c;
+	x = a - (b >> c);
+	x = a - b < c;
+	x = (a - b) < c;
+	x = a - (b < c);
+	x = a - b > c;
+	x = (a - b) > c;
+	x = a - (b > c);
+	x = a - b <= c;
+	x = (a - b) <= c;
+	x = a - (b <= c);
+	x = a - b >= c;
+	x = (a - b) >= c;
+	x = a - (b >= c);
+	x = a - b == c;
+	x = (a - b) == c;
+	x = a - (b == c);
+	x = a - b != c;
+	x = (a - b) != c;
+	x = a - (b != c);
+	x = a - b & c;
+	x = (a - b) & c;
+	x = a - (b & c);
+	x = a - b ^ c;
+	x = (a - b) ^ c;
+	x = a - (b ^ c);
+	x = a - b | c;
+	x = (a - b) | c;
+	x = a - (b | c);
+	x = a - b && c;
+	x = (a - b) && c;
+	x = a - (b && c);
+	x = a - b || c;
+	x = (a - b) || c;
+	x = a - (b || c);
+	x = a << b * c;
+	x = (a << b) * c;
+	x = a << (b * c);
+	x = a << b / c;
+	x = (a << b) / c;
+	x = a << (b / c);
+	x = a << b % c;
+	x = (a << b) % c;
+	x = a << (b % c);
+	x = a << b + c;
+	x = (a << b) + c;
+	x = a << (b + c);
+	x = a << b - c;
+	x = (a << b) - c;
+	x = a << (b - c);
+	x = a << b << c;
+	x = (a << b) << c;
+	x = a << (b << c);
+	x = a << b >> c;
+	x = (a << b) >> c;
+	x = a << (b >> c);
+	x = a << b < c;
+	x = (a << b) < c;
+	x = a << (b < c);
+	x = a << b > c;
+	x = (a << b) > c;
+	x = a << (b > c);
+	x = a << b <= c;
+	x = (a << b) <= c;
+	x = a << (b <= c);
+	x = a << b >= c;
+	x = (a << b) >= c;
+	x = a << (b >= c);
+	x = a << b == c;
+	x = (a << b) == c;
+	x = a << (b == c);
+	x = a << b != c;
+	x = (a << b) != c;
+	x = a << (b != c);
+	x = a << b & c;
+	x = (a << b) & c;
+	x = a << (b & c);
+	x = a << b ^ c;
+	x = (a << b) ^ c;
+	x = a << (b ^ c);
+	x = a << b | c;
+	x = (a << b) | c;
+	x = a << (b | c);
+	x = a << b && c;
+	x = (a << b) && c;
+	x = a << (b && c);
+	x = a << b || c;
+	x = (a << b) || c;
+	x = a << (b || c);
+	x = a >> b * c;
+	x = (a >> b) * c;
+	x = a >> (b * c);
+	x = a >> b / c;
+	x = (a >> b) / c;
+	x = a >> (b / c);
+	x = a >> b % c;
+	x = (a >> b) % c;
+	x = a >> (b % c);
+	x = a >> b + c;
+	x = (a >> b) + c;
+	x = a >> (b + c);
+	x = a >> b - c;
+	x = (a >> b) - c;
+	x = a >> (b - c);
+	x = a >> b << c;
+	x = (a >> b) << c;
+	x = a >> (b << c);
+	x = a >> b >> c;
+	x = (a >> b) >> c;
+	x = a >> (b >> c);
+	x = a >> b < c;
+	x = (a >> b) < c;
+	x = a >> (b < c);
+	x = a >> b > c;
+	x = (a >> b) > c;
+	x = a >> (b > c);
+	x = a >> b <= c;
+	x = (a >> b) <= c;
+	x = a >> (b <= c);
+	x = a >> b >= c;
+	x = (a >> b) >= c;
+	x = a >> (b >= c);
+	x = a >> b == c;
+	x = (a >> b) == c;
+	x = a >> (b == c);
+	x = a >> b != c;
+	x = (a >> b) != c;
+	x = a >> (b != c);
+	x = a >> b & c;
+	x = (a >> b) & c;
+	x = a >> (b & c);
+	x = a >> b ^ c;
+	x = (a >> b) ^ c;
+	x = a >> (b ^ c);
+	x = a >> b | c;
+	x = (a >> b) | c;
+	x = a >> (b | c);
+	x = a >> b && c;
+	x = (a >> b) && c;
+	x = a >> (b && c);
+	x = a >> b || c;
+	x = (a >> b) || c;
+	x = a >> (b || c);
+	x = a < b * c;
+	x = (a < b) * c;
+	x = a < (b * c);
+	x = a < b / c;
+	x = (a < b) / c;
+	x = a < (b / c);
+	x = a < b % c;
+	x = (a < b) % c;
+	x = a < (b % c);
+	x = a < b + c;
+	x = (a < b) + c;
+	x = a < (b + c);
+	x = a < b - c;
+	x = (a < b) - c;
+	x = a < (b - c);
+	x = a < b << c;
+	x = (a < b) << c;
+	x = a < (b << c);
+	x = a < b >> c;
+	x = (a < b) >> c;
+	x = a < (b >> c);
+	x = a < b < c;
+	x = (a < b) < c;
+	x = a < (b < c);
+	x = a < b > c;
+	x = (a < b) > c;
+	x = a < (b > c);
+	x = a < b <= c;
+	x = (a < b) <= c;
+	x = a < (b <= c);
+	x = a < b >= c;
+	x = (a < b) >= c;
+	x = a < (b >= c);
+	x = a < b == c;
+	x = (a < b) == c;
+	x = a < (b == c);
+	x = a < b != c;
+	x = (a < b) != c;
+	x = a < (b != c);
+	x = a < b & c;
+	x = (a < b) & c;
+	x = a < (b & c);
+	x = a < b ^ c;
+	x = (a < b) ^ c;
+	x = a < (b ^ c);
+	x = a < b | c;
+	x = (a < b) | c;
+	x = a < (b | c);
+	x = a < b && c;
+	x = (a < b) && c;
+	x = a < (b && c);
+	x = a < b || c;
+	x = (a < b) || c;
+	x = a < (b || c);
+	x = a > b * c;
+	x = (a > b) * c;
+	x = a > (b * c);
+	x = a > b / c;
+	x = (a > b) / c;
+	x = a > (b / c);
+	x = a > b % c;
+	x = (a > b) % c;
+	x = a > (b % c);
+	x = a > b + c;
+	x = (a > b) + c;
+	x = a > (b + c);
+	x = a > b - c;
+	x = (a > b) - c;
+	x = a > (b - c);
+	x = a > b << c;
+	x = (a > b) << c;
+	x = a > (b << c);
+	x = a > b >> c;
+	x = (a > b) >> c;
+	x = a > (b >> c);
+	x = a > b < c;
+	x = (a > b) < c;
+	x = a > (b < c);
+	x = a > b > c;
+	x = (a > b) > c;
+	x = a > (b > c);
+	x = a > b <= c;
+	x = (a > b) <= c;
+	x = a > (b <= c);
+	x = a > b >= c;
+	x = (a > b) >= c;
+	x = a > (b >= c);
+	x = a > b == c;
+	x = (a > b) == c;
+	x = a > (b == c);
+	x = a > b != c;
+	x = (a > b) != c;
+	x = a > (b != c);
+	x = a > b & c;
+	x = (a > b) & c;
+	x = a > (b & c);
+	x = a > b ^ c;
+	x = (a > b) ^ c;
+	x = a > (b ^ c);
+	x = a > b | c;
+	x = (a > b) | c;
+	x = a > (b | c);
+	x = a > b && c;
+	x = (a > b) && c;
+	x = a > (b && c);
+	x = a > b || c;
+	x = (a > b) || c;
+	x = a > (b || c);
+	x = a <= b * c;
+	x = (a <= b) * c;
+	x = a <= (b * c);
+	x = a <= b / c;
+	x = (a <= b) / c;
+	x = a <= (b / c);
+	x = a <= b % c;
+	x = (a <= b) % c;
+	x = a <= (b % c);
+	x = a <= b + c;
+	x = (a <= b) + c;
+	x = a <= (b + c);
+	x = a <= b - c;
+	x = (a <= b) - c;
+	x = a <= (b - c);
+	x = a <= b << c;
+	x = (a <= b) << c;
+	x = a <= (b << c);
+	x = a <= b >> c;
+	x = (a <= b) >> c;
+	x = a <= (b >> c);
+	x = a <= b < c;
+	x = (a <= b) < c;
+	x = a <= (b < c);
+	x = a <= b > c;
+	x = (a <= b) > c;
+	x = a <= (b > c);
+	x = a <= b <= c;
+	x = (a <= b) <= c;
+	x = a <= (b <= c);
+	x = a <= b >= c;
+	x = (a <= b) >= c;
+	x = a <= (b >= c);
+	x = a <= b == c;
+	x = (a <= b) == c;
+	x = a <= (b == c);
+	x = a <= b != c;
+	x = (a <= b) != c;
+	x = a <= (b != c);
+	x = a <= b & c;
+	x = (a <= b) & c;
+	x = a <= (b & c);
+	x = a <= b ^ c;
+	x = (a <= b) ^ c;
+	x = a <= (b ^ c);
+	x = a <= b | c;
+	x = (a <= b) | c;
+	x = a <= (b | c);
+	x = a <= b && c;
+	x = (a <= b) && c;
+	x = a <= (b && c);
+	x = a <= b || c;
+	x = (a <= b) || c;
+	x = a <= (b || c);
+	x = a >= b * c;
+	x = (a >= b) * c;
+	x = a >= (b * c);
+	x = a >= b / c;
+	x = (a >= b) / c;
+	x = a >= (b / c);
+	x = a >= b % c;
+	x = (a >= b) % c;
+	x = a >= (b % c);
+	x = a >= b + c;
+	x = (a >= b) + c;
+	x = a >= (b + c);
+	x = a >= b - c;
+	x = (a >= b) - c;
+	x = a >= (b - c);
+	x = a >= b << c;
+	x = (a >= b) << c;
+	x = a >= (b << c);
+	x = a >= b >> c;
+	x = (a >= b) >> c;
+	x = a >= (b >> c);
+	x = a >= b < c;
+	x = (a >= b) < c;
+	x = a >= (b < c);
+	x = a >= b > c;
+	x = (a >= b) > c;
+	x = a >= (b > c);
+	x = a >= b <= c;
+	x = (a >= b) <= c;
+	x = a >= (b <= c);
+	x = a >= b >= c;
+	x = (a >= b) >= c;
+	x = a >= (b >= c);
+	x = a >= b == c;
+	x = (a >= b) == c;
+	x = a >= (b == c);
+	x = a >= b != c;
+	x = (a >= b) != c;
+	x = a >= (b != c);
+	x = a >= b & c;
+	x = (a >= b) & c;
+	x = a >= (b & c);
+	x = a >= b ^ c;
+	x = (a >= b) ^ c;
+	x = a >= (b ^ c);
+	x = a >= b | c;
+	x = (a >= b) | c;
+	x = a >= (b | c);
+	x = a >= b && c;
+	x = (a >= b) && c;
+	x = a >= (b && c);
+	x = a >= b || c;
+	x = (a >= b) || c;
+	x = a >= (b || c);
+	x = a == b * c;
+	x = (a == b) * c;
+	x = a == (b * c);
+	x = a == b / c;
+	x = (a == b) / c;
+	x = a == (b / c);
+	x = a == b % c;
+	x = (a == b) % c;
+	x = a == (b % c);
+	x = a == b + c;
+	x = (a == b) + c;
+	x = a == (b + c);
+	x = a == b - c;
+	x = (a == b) - c;
+	x = a == (b - c);
+	x = a == b << c;
+	x = (a == b) << c;
+	x = a == (b << c);
+	x = a == b >> c;
+	x = (a == b) >> c;
+	x = a == (b >> c);
+	x = a == b < c;
+	x = (a == b) < c;
+	x = a == (b < c);
+	x = a == b > c;
+	x = (a == b) > c;
+	x = a == (b > c);
+	x = a == b <= c;
+	x = (a == b) <= c;
+	x = a == (b <= c);
+	x = a == b >= c;
+	x = (a == b) >= c;
+	x = a == (b >= c);
+	x = a == b == c;
+	x = (a == b) == c;
+	x = a == (b == c);
+	x = a == b != c;
+	x = (a == b) != c;
+	x = a == (b != c);
+	x = a == b & c;
+	x = (a == b) & c;
+	x = a == (b & c);
+	x = a == b ^ c;
+	x = (a == b) ^ c;
+	x = a == (b ^ c);
+	x = a == b | c;
+	x = (a == b) | c;
+	x = a == (b | c);
+	x = a == b && c;
+	x = (a == b) && c;
+	x = a == (b && c);
+	x = a == b || c;
+	x = (a == b) || c;
+	x = a == (b || c);
+	x = a != b * c;
+	x = (a != b) * c;
+	x = a != (b * c);
+	x = a != b / c;
+	x = (a != b) / c;
+	x = a != (b / c);
+	x = a != b % c;
+	x = (a != b) % c;
+	x = a != (b % c);
+	x = a != b + c;
+	x = (a != b) + c;
+	x = a != (b + c);
+	x = a != b - c;
+	x = (a != b) - c;
+	x = a != (b - c);
+	x = a != b << c;
+	x = (a != b) << c;
+	x = a != (b << c);
+	x = a != b >> c;
+	x = (a != b) >> c;
+	x = a != (b >> c);
+	x = a != b < c;
+	x = (a != b) < c;
+	x = a != (b < c);
+	x = a != b > c;
+	x = (a != b) > c;
+	x = a != (b > c);
+	x = a != b <= c;
+	x = (a != b) <= c;
+	x = a != (b <= c);
+	x = a != b >= c;
+	x = (a != b) >= c;
+	x = a != (b >= c);
+	x = a != b == c;
+	x = (a != b) == c;
+	x = a != (b == c);
+	x = a != b != c;
+	x = (a != b) != c;
+	x = a != (b != c);
+	x = a != b & c;
+	x = (a != b) & c;
+	x = a != (b & c);
+	x = a != b ^ c;
+	x = (a != b) ^ c;
+	x = a != (b ^ c);
+	x = a != b | c;
+	x = (a != b) | c;
+	x = a != (b | c);
+	x = a != b && c;
+	x = (a != b) && c;
+	x = a != (b && c);
+	x = a != b || c;
+	x = (a != b) || c;
+	x = a != (b || c);
+	x = a & b * c;
+	x = (a & b) * c;
+	x = a & (b * c);
+	x = a & b / c;
+	x = (a & b) / c;
+	x = a & (b / c);
+	x = a & b % c;
+	x = (a & b) % c;
+	x = a & (b % c);
+	x = a & b + c;
+	x = (a & b) + c;
+	x = a & (b + c);
+	x = a & b - c;
+	x = (a & b) - c;
+	x = a & (b - c);
+	x = a & b << c;
+	x = (a & b) << c;
+	x = a & (b << c);
+	x = a & b >> c;
+	x = (a & b) >> c;
+	x = a & (b >> c);
+	x = a & b < c;
+	x = (a & b) < c;
+	x = a & (b < c);
+	x = a & b > c;
+	x = (a & b) > c;
+	x = a & (b > c);
+	x = a & b <= c;
+	x = (a & b) <= c;
+	x = a & (b <= c);
+	x = a & b >= c;
+	x = (a & b) >= c;
+	x = a & (b >= c);
+	x = a & b == c;
+	x = (a & b) == c;
+	x = a & (b == c);
+	x = a & b != c;
+	x = (a & b) != c;
+	x = a & (b != c);
+	x = a & b & c;
+	x = (a & b) & c;
+	x = a & (b & c);
+	x = a & b ^ c;
+	x = (a & b) ^ c;
+	x = a & (b ^ c);
+	x = a & b | c;
+	x = (a & b) | c;
+	x = a & (b | c);
+	x = a & b && c;
+	x = (a & b) && c;
+	x = a & (b && c);
+	x = a & b || c;
+	x = (a & b) || c;
+	x = a & (b || c);
+	x = a ^ b * c;
+	x = (a ^ b) * c;
+	x = a ^ (b * c);
+	x = a ^ b / c;
+	x = (a ^ b) / c;
+	x = a ^ (b / c);
+	x = a ^ b % c;
+	x = (a ^ b) % c;
+	x = a ^ (b % c);
+	x = a ^ b + c;
+	x = (a ^ b) + c;
+	x = a ^ (b + c);
+	x = a ^ b - c;
+	x = (a ^ b) - c;
+	x = a ^ (b - c);
+	x = a ^ b << c;
+	x = (a ^ b) << c;
+	x = a ^ (b << c);
+	x = a ^ b >> c;
+	x = (a ^ b) >> c;
+	x = a ^ (b >> c);
+	x = a ^ b < c;
+	x = (a ^ b) < c;
+	x = a ^ (b < c);
+	x = a ^ b > c;
+	x = (a ^ b) > c;
+	x = a ^ (b > c);
+	x = a ^ b <= c;
+	x = (a ^ b) <= c;
+	x = a ^ (b <= c);
+	x = a ^ b >= c;
+	x = (a ^ b) >= c;
+	x = a ^ (b >= c);
+	x = a ^ b == c;
+	x = (a ^ b) == c;
+	x = a ^ (b == c);
+	x = a ^ b != c;
+	x = (a ^ b) != c;
+	x = a ^ (b != c);
+	x = a ^ b & c;
+	x = (a ^ b) & c;
+	x = a ^ (b & c);
+	x = a ^ b ^ c;
+	x = (a ^ b) ^ c;
+	x = a ^ (b ^ c);
+	x = a ^ b | c;
+	x = (a ^ b) | c;
+	x = a ^ (b | c);
+	x = a ^ b && c;
+	x = (a ^ b) && c;
+	x = a ^ (b && c);
+	x = a ^ b || c;
+	x = (a ^ b) || c;
+	x = a ^ (b || c);
+	x = a | b * c;
+	x = (a | b) * c;
+	x = a | (b * c);
+	x = a | b / c;
+	x = (a | b) / c;
+	x = a | (b / c);
+	x = a | b % c;
+	x = (a | b) % c;
+	x = a | (b % c);
+	x = a | b + c;
+	x = (a | b) + c;
+	x = a | (b + c);
+	x = a | b - c;
+	x = (a | b) - c;
+	x = a | (b - c);
+	x = a | b << c;
+	x = (a | b) << c;
+	x = a | (b << c);
+	x = a | b >> c;
+	x = (a | b) >> c;
+	x = a | (b >> c);
+	x = a | b < c;
+	x = (a | b) < c;
+	x = a | (b < c);
+	x = a | b > c;
+	x = (a | b) > c;
+	x = a | (b > c);
+	x = a | b <= c;
+	x = (a | b) <= c;
+	x = a | (b <= c);
+	x = a | b >= c;
+	x = (a | b) >= c;
+	x = a | (b >= c);
+	x = a | b == c;
+	x = (a | b) == c;
+	x = a | (b == c);
+	x = a | b != c;
+	x = (a | b) != c;
+	x = a | (b != c);
+	x = a | b & c;
+	x = (a | b) & c;
+	x = a | (b & c);
+	x = a | b ^ c;
+	x = (a | b) ^ c;
+	x = a | (b ^ c);
+	x = a | b | c;
+	x = (a | b) | c;
+	x = a | (b | c);
+	x = a | b && c;
+	x = (a | b) && c;
+	x = a | (b && c);
+	x = a | b || c;
+	x = (a | b) || c;
+	x = a | (b || c);
+	x = a && b * c;
+	x = (a && b) * c;
+	x = a && (b * c);
+	x = a && b / c;
+	x = (a && b) / c;
+	x = a && (b / c);
+	x = a && b % c;
+	x = (a && b) % c;
+	x = a && (b % c);
+	x = a && b + c;
+	x = (a && b) + c;
+	x = a && (b + c);
+	x = a && b - c;
+	x = (a && b) - c;
+	x = a && (b - c);
+	x = a && b << c;
+	x = (a && b) << c;
+	x = a && (b << c);
+	x = a && b >> c;
+	x = (a && b) >> c;
+	x = a && (b >> c);
+	x = a && b < c;
+	x = (a && b) < c;
+	x = a && (b < c);
+	x = a && b > c;
+	x = (a && b) > c;
+	x = a && (b > c);
+	x = a && b <= c;
+	x = (a && b) <= c;
+	x = a && (b <= c);
+	x = a && b >= c;
+	x = (a && b) >= c;
+	x = a && (b >= c);
+	x = a && b == c;
+	x = (a && b) == c;
+	x = a && (b == c);
+	x = a && b != c;
+	x = (a && b) != c;
+	x = a && (b != c);
+	x = a && b & c;
+	x = (a && b) & c;
+	x = a && (b & c);
+	x = a && b ^ c;
+	x = (a && b) ^ c;
+	x = a && (b ^ c);
+	x = a && b | c;
+	x = (a && b) | c;
+	x = a && (b | c);
+	x = a && b && c;
+	x = (a && b) && c;
+	x = a && (b && c);
+	x = a && b || c;
+	x = (a && b) || c;
+	x = a && (b || c);
+	x = a || b * c;
+	x = (a || b) * c;
+	x = a || (b * c);
+	x = a || b / c;
+	x = (a || b) / c;
+	x = a || (b / c);
+	x = a || b % c;
+	x = (a || b) % c;
+	x = a || (b % c);
+	x = a || b + c;
+	x = (a || b) + c;
+	x = a || (b + c);
+	x = a || b - c;
+	x = (a || b) - c;
+	x = a || (b - c);
+	x = a || b << c;
+	x = (a || b) << c;
+	x = a || (b << c);
+	x = a || b >> c;
+	x = (a || b) >> c;
+	x = a || (b >> c);
+	x = a || b < c;
+	x = (a || b) < c;
+	x = a || (b < c);
+	x = a || b > c;
+	x = (a || b) > c;
+	x = a || (b > c);
+	x = a || b <= c;
+	x = (a || b) <= c;
+	x = a || (b <= c);
+	x = a || b >= c;
+	x = (a || b) >= c;
+	x = a || (b >= c);
+	x = a || b == c;
+	x = (a || b) == c;
+	x = a || (b == c);
+	x = a || b != c;
+	x = (a || b) != c;
+	x = a || (b != c);
+	x = a || b & c;
+	x = (a || b) & c;
+	x = a || (b & c);
+	x = a || b ^ c;
+	x = (a || b) ^ c;
+	x = a || (b ^ c);
+	x = a || b | c;
+	x = (a || b) | c;
+	x = a || (b | c);
+	x = a || b && c;
+	x = (a || b) && c;
+	x = a || (b && c);
+	x = a || b || c;
+	x = (a || b) || c;
+	x = a || (b || c);
+}
@@ -0,0 +1,18 @@
+#!/bin/sh
+# Regression check for expression parsing: compile each sample and compare the printed source against the `.expected`
+# output (parens are only printed where they're needed, so a wrongly nested tree shows up as a difference)
+#
+# Usage: samples/precedence/check.sh [path/to/cc]
+CC=${1:-"cargo run -q --"}
+cd "$(dirname "$0")" || exit 1
+status=0
+for src in *.c
+do
+	if $CC "$src" | diff -u "${src%.c}.expected" - ; then
+		echo "ok   $src"
+	else
+		echo "FAIL $src"
+		status=1
+	fi
+done
+exit $status
@@ -0,0 +1,57 @@
+/* Unary, postfix, cast, conditional, assignment and comma operators */
+struct s { int m; int *p; };
+void f(int a, int b, int c, int x, int *p, struct s v, struct s *sp)
+{
+	x = -a * b;
+	x = -(a * b);
+	x = - -a;
+	x = + +a;
+	x = -a++;
+	x = (-a)++;
+	x = *p++;
+	x = (*p)++;
+	x = ++*p;
+	x = *++p;
+	x = !a && b;
+	x = !(a && b);
+	x = ~a | b;
+	x = &v.m == p;
+	x = *sp->p;
+	x = (*sp).m;
+	x = sp->p[0];
+	x = (*sp->p)++;
+	x = p[a + b];
+	x = p[a, b];
+	x = (int)a + b;
+	x = (int)(a + b);
+	x = (int)-a;
+	x = (int)p[0];
+	x = -(int)a;
+	x = (char)(int)a;
+	x = sizeof a + b;
+	x = sizeof(a + b);
+	x = sizeof(int) * a;
+	x = sizeof(int *);
+	x = sizeof -a;
+	x = a ? b : c;
+	x = a || b ? b : c;
+	x = (a ? b : c) ? a : b;
+	x = a ? b : c ? a : b;
+	x = a ? b, c : a;
+	x = a ? b = c : a;
+	x = a ? b : (c = a);
+	x = a = b = c;
+	(x = a) = b;
+	x += a += b;
+	x <<= a >> b;
+	x ^= a | b;
+	x = (a, b);
+	x = a, b = c;
+	x = a ? b : c, a;
+	f(a, (b, c), x, p, v, sp);
+	x = (a + b) * c;
+	x = a - (b - c);
+	x = a - b - c;
+	x = a < b == c < a;
+	x = a < (b == c) < a;
+}
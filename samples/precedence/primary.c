@@ -0,0 +1,27 @@
+/* Compound literals, generic selections and statement expressions */
+struct foo { int a; int b; };
+int g(struct foo *p);
+int f(int x, int *p)
+{
+	struct foo v = (struct foo){ .a = 1, .b = 2 };
+	int *q = (int[]){ 1, 2, 3 };
+	int *r = (int[3]){ [0] = 1, [2] = x };
+	x = (struct foo){ 1, 2 }.a + sizeof (struct foo){ 0 }.b;
+	x = g(&(struct foo){ .a = x });
+	x = (int)(struct foo){ 1 }.b;
+	x = _Generic(x, int: 1, char *: 2, default: 3);
+	x = _Generic(x + 1, float: 1, double: 2) * 2;
+	x = ({ int y = x * 2; y + 1; });
+	if( x )
+	{
+		x = ({
+			int z = 0;
+			if( z )
+			{
+				z = 1;
+			}
+			z;
+			}) + 1;
+	}
+	return x;
+}
@@ -3,6 +3,8 @@
  */
 use std::collections::HashMap;
 use std::collections::hash_map::Entry;
+use preproc::Span;
+use preproc::Token;
 
 pub mod pretty_print;
 
@@ -12,6 +14,8 @@ pub struct Program
 {
 	/// Item definition order
 	item_order: Vec<ItemRef>,
+	/// Definitions aren't added to `item_order` (see `set_listing`)
+	unlisted: bool,
 
 	typedefs: HashMap<String, ::types::TypeRef>,
 	structs: HashMap<String, ::types::StructRef>,
@@ -19,6 +23,33 @@ pub struct Program
 	enums: HashMap<String, ::types::EnumRef>,
 	// Aka global variables/functions
 	symbols: HashMap<String, Symbol>,
+
+	/// Number of open checkpoints (changes are only logged while there's one)
+	checkpoints: usize,
+	/// Changes to the definitions since the outermost open checkpoint, most recent last
+	undo_log: Vec<Undo>,
+}
+/// Position to return to with `Program::rollback`
+pub struct Checkpoint
+{
+	undo_len: usize,
+	item_len: usize,
+}
+/// A change to the definitions, as recorded for `Program::rollback`
+enum Undo
+{
+	/// Symbol defined or updated (with the previous version)
+	Symbol(String, Option<Symbol>),
+	/// Typedef defined (with the previous type)
+	Typedef(String, Option<::types::TypeRef>),
+	/// First use of a struct/union/enum name
+	StructName(String),
+	UnionName(String),
+	EnumName(String),
+	/// Struct/union/enum populated
+	StructItems(::types::StructRef),
+	UnionItems(::types::UnionRef),
+	EnumItems(::types::EnumRef),
 }
 /// Referece to a defined item (typedef/struct/value/...)
 enum ItemRef
@@ -31,12 +62,24 @@ enum ItemRef
 	Union(String),
 	Enum(String),
 
-	//CppDefine {
-	//	name: String,
-	//	args: Vec<String>,
-	//	tokens: Vec<Token>,
-	//},
-	//CppInclude(String),
+	// - Round-trip mode (only present if the pre-processor propagates them)
+	Comment(String),
+	BlankLine,
+	CppDefine {
+		name: String,
+		args: Option<Vec<String>>,
+		variadic: Option<String>,
+		tokens: Vec<Token>,
+	},
+	CppUndef(String),
+	CppInclude {
+		angle_brackets: bool,
+		path: String,
+	},
+	/// Conditional directives and the text of the groups they skip (ending with a newline)
+	CppConditional(String),
+	/// A macro invocation that expands to complete top-level items (the invocation's tokens)
+	MacroUse(Vec<Token>),
 }
 
 // TODO: Have a disinction between functions and globals?
@@ -63,6 +106,107 @@ impl Program
 		}
 	}
 	
+	/// Control if definitions are listed in the output (they're still recorded while not listed)
+	///
+	/// Used in round-trip mode, where the contents of an `#include`d file are represented by the `#include` itself.
+	pub fn set_listing(&mut self, listed: bool)
+	{
+		self.unlisted = !listed;
+	}
+	fn push_item(&mut self, item: ItemRef)
+	{
+		if !self.unlisted {
+			self.item_order.push(item);
+		}
+	}
+
+	/// Start recording changes, so they can be reverted by `rollback` (e.g. for a speculative parse)
+	///
+	/// Checkpoints can be nested, each must be passed to either `commit` or `rollback`.
+	pub fn checkpoint(&mut self) -> Checkpoint
+	{
+		self.checkpoints += 1;
+		Checkpoint {
+			undo_len: self.undo_log.len(),
+			item_len: self.item_order.len(),
+			}
+	}
+	/// Keep the changes made since a checkpoint
+	pub fn commit(&mut self, _cp: Checkpoint)
+	{
+		self.checkpoints -= 1;
+		if self.checkpoints == 0 {
+			self.undo_log.clear();
+		}
+	}
+	/// Revert the changes (definitions and listed items) made since a checkpoint
+	pub fn rollback(&mut self, cp: Checkpoint)
+	{
+		while self.undo_log.len() > cp.undo_len
+		{
+			match self.undo_log.pop().unwrap()
+			{
+			Undo::Symbol(name, Some(prev)) => { self.symbols.insert(name, prev); },
+			Undo::Symbol(name, None) => { self.symbols.remove(&name); },
+			Undo::Typedef(name, Some(prev)) => { self.typedefs.insert(name, prev); },
+			Undo::Typedef(name, None) => { self.typedefs.remove(&name); },
+			Undo::StructName(name) => { self.structs.remove(&name); },
+			Undo::UnionName(name) => { self.unions.remove(&name); },
+			Undo::EnumName(name) => { self.enums.remove(&name); },
+			Undo::StructItems(r) => r.borrow_mut().clear_items(),
+			Undo::UnionItems(r) => r.borrow_mut().clear_items(),
+			Undo::EnumItems(r) => r.borrow_mut().clear_items(),
+			}
+		}
+		self.item_order.truncate(cp.item_len);
+		self.checkpoints -= 1;
+		if self.checkpoints == 0 {
+			self.undo_log.clear();
+		}
+	}
+	fn log_change(&mut self, change: Undo)
+	{
+		if self.checkpoints > 0 {
+			self.undo_log.push(change);
+		}
+	}
+
+	/// Add a top-level comment (text including the delimiters)
+	///
+	/// NOTE: Comments, blank lines, and directives are always listed (they only come from the main file)
+	pub fn add_comment(&mut self, text: String)
+	{
+		self.item_order.push(ItemRef::Comment(text));
+	}
+	/// Add a blank line between top-level items
+	pub fn add_blank_line(&mut self)
+	{
+		self.item_order.push(ItemRef::BlankLine);
+	}
+	/// Add a top-level pre-processor directive (other than `#include`, `#define`, `#undef` and conditionals are ignored)
+	pub fn add_directive(&mut self, directive: ::preproc::token::Preprocessor)
+	{
+		use preproc::token::Preprocessor;
+		match directive
+		{
+		Preprocessor::Include { angle_brackets, path } => self.item_order.push(ItemRef::CppInclude { angle_brackets, path }),
+		Preprocessor::MacroDefine { name, arg_names, variadic, expansion } => self.item_order.push(ItemRef::CppDefine {
+			name: name,
+			args: arg_names,
+			variadic: variadic,
+			tokens: expansion,
+			}),
+		Preprocessor::MacroUndefine { name } => self.item_order.push(ItemRef::CppUndef(name)),
+		Preprocessor::Conditional(text) => self.item_order.push(ItemRef::CppConditional(text)),
+		_ => {},
+		}
+	}
+	/// Add a top-level macro invocation (the items it expands to aren't listed)
+	pub fn add_macro_use(&mut self, input: Vec<Token>)
+	{
+		self.item_order.push(ItemRef::MacroUse(input));
+	}
+
 	pub fn define_function(&mut self, typeid: ::types::TypeRef, name: String, value: Option<Block>)
 	{
 		self.define_symbol(typeid, name, value.map(SymbolValue::Code))
@@ -74,12 +218,17 @@ impl Program
 	fn define_symbol(&mut self, typeid: ::types::TypeRef, name: String, value: Option<SymbolValue>)
 	{
 		if value.is_some() {
-			self.item_order.push(ItemRef::Value(name.clone()));
+			self.push_item(ItemRef::Value(name.clone()));
 		}
 		else {
-			self.item_order.push(ItemRef::ValueDecl(name.clone()));
+			self.push_item(ItemRef::ValueDecl(name.clone()));
 		}
 		info!("Define variable '{}': '{:?}' = {:?}", name, typeid, value);
+		if self.checkpoints > 0 {
+			// NOTE: An existing symbol is only changed if it has no value
+			let prev = self.symbols.get(&name).map(|s| Symbol { name: s.name.clone(), symtype: s.symtype.clone(), value: None });
+			self.log_change(Undo::Symbol(name.clone(), prev));
+		}
 		match self.symbols.entry(name.clone())
 		{
 		Entry::Occupied(mut e) => {
@@ -114,8 +263,11 @@ impl Program
 	
 	pub fn set_typedef(&mut self, name: String, typeid: ::types::TypeRef) -> bool
 	{
-		self.item_order.push(ItemRef::Typedef(name.clone()));
-		self.typedefs.insert(name, typeid).is_none()
+		self.push_item(ItemRef::Typedef(name.clone()));
+		let prev = self.typedefs.insert(name.clone(), typeid);
+		let rv = prev.is_none();
+		self.log_change(Undo::Typedef(name, prev));
+		rv
 	}
 	pub fn get_typedef(&self, name: &str) -> Option<::types::TypeRef>
 	{
@@ -139,6 +291,9 @@ impl Program
 			::types::Struct::new_ref("")
 		}
 		else {
+			if !self.structs.contains_key(name) {
+				self.log_change(Undo::StructName(name.to_string()));
+			}
 			self.structs.entry(name.to_string())
 				.or_insert_with(|| ::types::Struct::new_ref(name))
 				.clone()
@@ -150,6 +305,9 @@ impl Program
 			::types::Union::new_ref("")
 		}
 		else {
+			if !self.unions.contains_key(name) {
+				self.log_change(Undo::UnionName(name.to_string()));
+			}
 			self.unions.entry(name.to_string())
 				.or_insert_with(|| ::types::Union::new_ref(name))
 				.clone()
@@ -161,6 +319,9 @@ impl Program
 			::types::Enum::new_ref("")
 		}
 		else {
+			if !self.enums.contains_key(name) {
+				self.log_change(Undo::EnumName(name.to_string()));
+			}
 			self.enums.entry(name.to_string())
 				.or_insert_with(|| ::types::Enum::new_ref(name))
 				.clone()
@@ -169,7 +330,7 @@ impl Program
 
 	pub fn make_struct(&mut self, name: &str, items: Vec<(::types::TypeRef,String)>) -> Result<::types::StructRef,()> {
 		if name != "" {
-			self.item_order.push(ItemRef::Struct(name.to_owned()));
+			self.push_item(ItemRef::Struct(name.to_owned()));
 		}
 		let sr = self.get_struct(name);
 		let ispop = sr.borrow().is_populated();
@@ -180,12 +341,13 @@ impl Program
 		else {
 			// Set items in enum
 			sr.borrow_mut().set_items(items);
+			self.log_change(Undo::StructItems(sr.clone()));
 			Ok( sr )
 		}
 	}
 	pub fn make_union(&mut self, name: &str, items: Vec<(::types::TypeRef,String)>) -> Result<::types::UnionRef,()> {
 		if name != "" {
-			self.item_order.push(ItemRef::Union(name.to_owned()));
+			self.push_item(ItemRef::Union(name.to_owned()));
 		}
 		let ur = self.get_union(name);
 		let ispop = ur.borrow().is_populated();
@@ -196,12 +358,13 @@ impl Program
 		else {
 			// Set items in enum
 			ur.borrow_mut().set_items(items);
+			self.log_change(Undo::UnionItems(ur.clone()));
 			Ok( ur )
 		}
 	}
 	pub fn make_enum(&mut self, name: &str, items: Vec<(u64,String)>) -> Result<::types::EnumRef,Option<String>> {
 		if name != "" {
-			self.item_order.push(ItemRef::Enum(name.to_owned()));
+			self.push_item(ItemRef::Enum(name.to_owned()));
 		}
 		let er = self.get_enum(name);
 		let ispop = er.borrow().is_populated();
@@ -212,6 +375,7 @@ impl Program
 		else {
 			// Set items in enum
 			er.borrow_mut().set_items(items);
+			self.log_change(Undo::EnumItems(er.clone()));
 			Ok( er )
 		}
 	}
@@ -222,7 +386,14 @@ pub type StatementList = Vec<Statement>;
 pub type VarDefList = Vec<VariableDefinition>;
 /// Block statement
 #[derive(Debug)]
-pub enum Statement
+pub struct Statement
+{
+	pub kind: StatementKind,
+	/// Location of the first token of the statement
+	pub span: Span,
+}
+#[derive(Debug)]
+pub enum StatementKind
 {
 	Empty,
 	VarDef(VarDefList),
@@ -260,6 +431,11 @@ pub enum Statement
 
 	Goto(String),
 	Label(String),
+
+	/// Comment (round-trip mode, text including the delimiters)
+	Comment(String),
+	/// Pre-processor directive within a function (round-trip mode)
+	Directive(::preproc::token::Preprocessor),
 }
 #[derive(Debug)]
 pub struct VariableDefinition
@@ -290,15 +466,30 @@ pub enum ExprOrDef
 	Expr(Node),
 	Definition(VarDefList),
 }
+/// Expression node
+#[derive(Debug)]
+pub struct Node
+{
+	pub kind: NodeKind,
+	/// Location of the first token of the expression
+	pub span: Span,
+}
 #[derive(Debug)]
-pub enum Node
+pub enum NodeKind
 {
 	StmtList(Vec<Node>),	// Comma operator
 	
 	Identifier(String),
-	String(String),
+	String(::preproc::token::StringLit),
 	Integer(u64),
-	Float(f64),
+	/// Floating constant: value, type (from the suffix), and spelling as written
+	Float(::preproc::token::FloatValue, ::types::FloatClass, String),
+	/// Unexpanded macro invocation (round-trip mode), only used when the expansion is a single operand
+	Macro {
+		/// Tokens of the invocation (name and arguments)
+		input: Vec<Token>,
+		expansion: Box<Node>,
+	},
 
 	// TODO: Specialise this for expression/literal calls?
 	FcnCall(Box<Node>, Vec<Node>),
@@ -307,6 +498,8 @@ pub enum Node
 	AssignOp(BinOp, Box<Node>, Box<Node>),
 	
 	Cast(::types::TypeRef,Box<Node>),
+	/// Compound literal `(type){ ... }`
+	CompoundLiteral(::types::TypeRef, Box<Initialiser>),
 	SizeofType(::types::TypeRef),
 	SizeofExpr(Box<Node>),
 	
@@ -317,6 +510,11 @@ pub enum Node
 	Index(Box<Node>, Box<Node>),
 	DerefMember(Box<Node>, String),
 	Member(Box<Node>, String),
+
+	/// Generic selection `_Generic(e, type: value, ..., default: value)` (`None` for the `default` association)
+	Generic(Box<Node>, Vec<(Option<::types::TypeRef>, Node)>),
+	/// GNU statement expression `({ ... })`, the value is that of the final expression statement
+	StmtExpr(Block),
 }
 // Lower precedence is weaker binding
 #[derive(Debug,PartialOrd,PartialEq,Copy,Clone)]
@@ -327,17 +525,24 @@ pub enum NodePrecedence
 	Lowest,	// NOTE: has to be first
 	CommaOperator,
 	Assignment,
-	Bitwise,
-	Logic,	// TODO: Is this where it is?
 	Ternary,
-	Comparison,
+	LogicOr,
+	LogicAnd,
+	BitOr,
+	BitXor,
+	BitAnd,
+	/// `==` and `!=`
+	Equality,
+	/// `<`, `<=`, `>` and `>=`
+	Relational,
 	BitShift,
-	MulDivMod,
 	AddSub,
-	Unary,	// TODO: Are there more in here?
-	UnarySuffix,
-	DeRef,
-	MemberAccess,
+	MulDivMod,
+	Cast,
+	/// Prefix operators (including `sizeof`)
+	Unary,
+	/// Postfix operators, calls, indexing and member access
+	Postfix,
 	Value,
 	/// Parens are always applied
 	Highest,
@@ -401,6 +606,8 @@ pub enum BinOp
 #[derive(Debug)]
 pub enum UniOp
 {
+	/// Unary `+`
+	Plus,
 	Neg,
 	BitNot,
 	LogicNot,
@@ -412,89 +619,97 @@ pub enum UniOp
 	Deref,
 }
 
+impl Statement
+{
+	pub fn new(span: Span, kind: StatementKind) -> Statement
+	{
+		Statement { kind: kind, span: span }
+	}
+}
+
 impl Node
 {
+	pub fn new(span: Span, kind: NodeKind) -> Node
+	{
+		Node { kind: kind, span: span }
+	}
+
 	/// Attempt to interpret the node as a trivally constant integer
 	pub fn literal_integer(&self) -> Option<u64>
 	{
-		match self
+		match self.kind
 		{
-		&Node::Integer(v) => Some(v),
-		&Node::UniOp(ref op,ref a) => match (op,a.literal_integer())
+		NodeKind::Integer(v) => Some(v),
+		NodeKind::UniOp(ref op,ref a) => match (op,a.literal_integer())
 			{
 			(&UniOp::Neg,Some(a)) => Some(!a + 1),
 			_ => None,
 			},
-		&Node::BinOp(ref op,ref a,ref b) => match (op,a.literal_integer(), b.literal_integer())
+		NodeKind::BinOp(ref op,ref a,ref b) => match (op,a.literal_integer(), b.literal_integer())
 			{
 			(&BinOp::Sub,Some(a),Some(b)) => Some(a-b),
 			_ => None,
 			},
-		&Node::Identifier(_) => None,	// TODO: Look up ident in the global/constant scope
+		NodeKind::Identifier(_) => None,	// TODO: Look up ident in the global/constant scope
+		NodeKind::Macro { ref expansion, .. } => expansion.literal_integer(),
 		_ => None,
 		}
 	}
 
 	pub fn get_precedence(&self) -> NodePrecedence
 	{
-		match *self
+		match self.kind
 		{
-		Node::StmtList(_) => NodePrecedence::CommaOperator,
+		NodeKind::StmtList(_) => NodePrecedence::CommaOperator,
 
-		Node::Identifier(_)
-		| Node::String(_)
-		| Node::Integer(_)
-		| Node::Float(_)
+		NodeKind::Identifier(_)
+		| NodeKind::String(_)
+		| NodeKind::Integer(_)
+		| NodeKind::Float(..)
+		| NodeKind::Macro { .. }
+		| NodeKind::Generic(..)
+		| NodeKind::StmtExpr(..)
 			=> NodePrecedence::Value,
 
-		Node::FcnCall(_, _) => NodePrecedence::MemberAccess,
+		NodeKind::FcnCall(_, _) => NodePrecedence::Postfix,
 
-		Node::Assign(_, _)
-		| Node::AssignOp(_, _, _)
+		NodeKind::Assign(_, _)
+		| NodeKind::AssignOp(_, _, _)
 			=> NodePrecedence::Assignment,
 
-		Node::Cast(_, _) => NodePrecedence::Unary,	// TODO: Double-check
-		Node::SizeofType(_) => NodePrecedence::Value,
-		Node::SizeofExpr(_) => NodePrecedence::Value,
+		NodeKind::Cast(_, _) => NodePrecedence::Cast,
+		NodeKind::CompoundLiteral(_, _) => NodePrecedence::Postfix,
+		NodeKind::SizeofType(_) => NodePrecedence::Unary,
+		NodeKind::SizeofExpr(_) => NodePrecedence::Unary,
 
-		Node::Ternary(_,_,_) => NodePrecedence::Ternary,
-		Node::UniOp(ref op, _) => match *op
+		NodeKind::Ternary(_,_,_) => NodePrecedence::Ternary,
+		NodeKind::UniOp(ref op, _) => match *op
 			{
-			UniOp::Neg => NodePrecedence::Unary,
-			UniOp::BitNot   => NodePrecedence::Unary,
-			UniOp::LogicNot => NodePrecedence::Unary,
-			UniOp::PreInc
-			| UniOp::PreDec
-				=> NodePrecedence::Unary,
 			UniOp::PostInc
 			| UniOp::PostDec
-				=> NodePrecedence::UnarySuffix,
-			UniOp::Address
-			| UniOp::Deref
-				=> NodePrecedence::DeRef,
+				=> NodePrecedence::Postfix,
+			_ => NodePrecedence::Unary,
 			},
-		Node::BinOp(ref op, _, _) => match *op
+		NodeKind::BinOp(ref op, _, _) => match *op
 			{
-			BinOp::LogicAnd
-			| BinOp::LogicOr
-				=> NodePrecedence::Logic,
-
-			BinOp::BitAnd
-			| BinOp::BitOr
-			| BinOp::BitXor
-				=> NodePrecedence::Bitwise,
-
-			BinOp::ShiftLeft
-			| BinOp::ShiftRight
-				=> NodePrecedence::BitShift,
+			BinOp::LogicOr => NodePrecedence::LogicOr,
+			BinOp::LogicAnd => NodePrecedence::LogicAnd,
+			BinOp::BitOr  => NodePrecedence::BitOr,
+			BinOp::BitXor => NodePrecedence::BitXor,
+			BinOp::BitAnd => NodePrecedence::BitAnd,
 
 			BinOp::CmpEqu
 			| BinOp::CmpNEqu
-			| BinOp::CmpLt
+				=> NodePrecedence::Equality,
+			BinOp::CmpLt
 			| BinOp::CmpLtE
 			| BinOp::CmpGt
 			| BinOp::CmpGtE
-				=> NodePrecedence::Comparison,
+				=> NodePrecedence::Relational,
+
+			BinOp::ShiftLeft
+			| BinOp::ShiftRight
+				=> NodePrecedence::BitShift,
 
 			BinOp::Add
 			| BinOp::Sub
@@ -505,10 +720,9 @@ impl Node
 			| BinOp::Mod
 				=> NodePrecedence::MulDivMod,
 			},
-
-		Node::Index(_, _) => NodePrecedence::MemberAccess,
-		Node::DerefMember(_, _) => NodePrecedence::MemberAccess,
-		Node::Member(_, _) => NodePrecedence::MemberAccess,
+		NodeKind::Index(_, _) => NodePrecedence::Postfix,
+		NodeKind::DerefMember(_, _) => NodePrecedence::Postfix,
+		NodeKind::Member(_, _) => NodePrecedence::Postfix,
 		}
 	}
 }
@@ -1,14 +1,17 @@
 
 use super::ItemRef;
+use preproc::Token;
 
 pub fn write(mut sink: impl ::std::io::Write, prog: &super::Program)
 {
-	PrettyPrinter { sink: &mut sink, prog: prog }.write_program();
+	PrettyPrinter { sink: &mut sink, prog: prog, indent: 0 }.write_program();
 }
 
 struct PrettyPrinter<'a, 'b> {
 	sink: &'a mut ::std::io::Write,
 	prog: &'b super::Program,
+	/// Indent level of the statement being written (for blocks within expressions)
+	indent: usize,
 }
 
 impl<'a, 'b> PrettyPrinter<'a, 'b>
@@ -34,11 +37,70 @@ impl<'a, 'b> PrettyPrinter<'a, 'b>
 				self.write_struct_def(&self.prog.structs[name].borrow(), true);
 				self.write_str(";\n");
 				},
+			&ItemRef::Comment(ref text) => { self.write_str(text); self.write_str("\n"); },
+			&ItemRef::BlankLine => self.write_str("\n"),
+			&ItemRef::CppDefine { ref name, ref args, ref variadic, ref tokens } => self.write_define(name, args.as_ref(), variadic.as_ref(), tokens),
+			&ItemRef::CppUndef(ref name) => write!(self, "#undef {}\n", name),
+			&ItemRef::CppInclude { angle_brackets, ref path } => self.write_include(angle_brackets, path),
+			&ItemRef::CppConditional(ref text) => self.write_str(text),
+			&ItemRef::MacroUse(ref tokens) => { self.write_tokens(tokens, true); self.write_str("\n"); },
 			_ => {},
 			}
 		}
 	}
 
+	fn write_define(&mut self, name: &str, args: Option<&Vec<String>>, variadic: Option<&String>, tokens: &[Token])
+	{
+		write!(self, "#define {}", name);
+		if let Some(args) = args
+		{
+			self.write_str("(");
+			self.write_str(&args.join(","));
+			if let Some(v) = variadic {
+				if args.len() > 0 {
+					self.write_str(",");
+				}
+				if v != "__VA_ARGS__" {
+					self.write_str(v);
+				}
+				self.write_str("...");
+			}
+			self.write_str(")");
+		}
+		if tokens.len() > 0 {
+			self.write_str(" ");
+			self.write_tokens(tokens, false);
+		}
+		self.write_str("\n");
+	}
+	fn write_include(&mut self, angle_brackets: bool, path: &str)
+	{
+		if angle_brackets {
+			write!(self, "#include <{}>\n", path);
+		}
+		else {
+			write!(self, "#include \"{}\"\n", path);
+		}
+	}
+	/// Write source tokens, separating tokens that would otherwise lex differently
+	fn write_tokens(&mut self, tokens: &[Token], space_after_comma: bool)
+	{
+		let mut prev = String::new();
+		for t in tokens
+		{
+			let text = match *t
+				{
+				Token::Whitespace => " ".to_owned(),
+				ref t => t.to_string(),
+				};
+			if needs_space(&prev, &text) || (space_after_comma && prev == ",") {
+				self.write_str(" ");
+			}
+			self.write_str(&text);
+			prev = text;
+		}
+	}
+
 	fn find_typedef(&self, ty: &::types::TypeRef) -> Option<&'b str>
 	{
 		for (k,v) in &self.prog.typedefs {
@@ -81,11 +143,21 @@ impl<'a, 'b> PrettyPrinter<'a, 'b>
 		if let Some(ref fields) = s.items
 		{
 			self.write_str("{"); self.write_str(nl);
-			for &(ref ty, ref name) in fields
+			for (i, &(ref ty, ref name)) in fields.iter().enumerate()
 			{
+				for c in s.comments.iter().filter(|c| c.index == i && !c.trailing) {
+					self.write_str(indent); self.write_str(&c.text); self.write_str(nl);
+				}
 				self.write_str(indent);
 				self.write_type(ty, |s| s.write_str(name));
-				self.write_str(";"); self.write_str(nl);
+				self.write_str(";");
+				for c in s.comments.iter().filter(|c| c.index == i && c.trailing) {
+					self.write_str(" "); self.write_str(&c.text);
+				}
+				self.write_str(nl);
+			}
+			for c in s.comments.iter().filter(|c| c.index == fields.len()) {
+				self.write_str(indent); self.write_str(&c.text); self.write_str(nl);
 			}
 			self.write_str("}");
 		}
@@ -96,27 +168,49 @@ impl<'a, 'b> PrettyPrinter<'a, 'b>
 		match init
 		{
 		&::ast::Initialiser::None => {},
-		&::ast::Initialiser::Value(ref v) => {
+		_ => {
 			self.write_str(" = ");
+			self.write_initialiser_value(init);
+			},
+		}
+	}
+	/// Write an initialiser's value (a braced list for composite literals)
+	fn write_initialiser_value(&mut self, init: &::ast::Initialiser)
+	{
+		match init
+		{
+		&::ast::Initialiser::None => {},
+		&::ast::Initialiser::Value(ref v) => {
 			self.write_node(v, super::NodePrecedence::CommaOperator.up());
 			},
 		&::ast::Initialiser::ListLiteral(ref vs) => {
-			self.write_str(" = {");
-			let mut is_first = true;
-			for v in vs {
-				if !is_first {
-					self.write_str(", ");
-				}
-				else {
-					self.write_str(" ");
-				}
-				is_first = false;
+			self.write_str("{");
+			for (i, v) in vs.iter().enumerate() {
+				self.write_str(if i == 0 { " " } else { ", " });
+				self.write_node(v, super::NodePrecedence::CommaOperator.up());
+			}
+			self.write_str(" }");
+			},
+		&::ast::Initialiser::ArrayLiteral(ref vs) => {
+			self.write_str("{");
+			for (i, &(ref idx, ref v)) in vs.iter().enumerate() {
+				self.write_str(if i == 0 { " [" } else { ", [" });
+				self.write_node(idx, super::NodePrecedence::CommaOperator.up());
+				self.write_str("] = ");
+				self.write_node(v, super::NodePrecedence::CommaOperator.up());
+			}
+			self.write_str(" }");
+			},
+		&::ast::Initialiser::StructLiteral(ref vs) => {
+			self.write_str("{");
+			for (i, &(ref name, ref v)) in vs.iter().enumerate() {
+				self.write_str(if i == 0 { " ." } else { ", ." });
+				self.write_str(name);
+				self.write_str(" = ");
 				self.write_node(v, super::NodePrecedence::CommaOperator.up());
 			}
 			self.write_str(" }");
 			},
-		&::ast::Initialiser::ArrayLiteral(_) => panic!("TODO: Pretty-print ArrayLiteral"),
-		&::ast::Initialiser::StructLiteral(_) => panic!("TODO: Pretty-print StructLiteral"),
 		}
 	}
 
@@ -290,6 +384,13 @@ impl<'a, 'b> PrettyPrinter<'a, 'b>
 		for _ in 0 .. indent {
 			self.write_str("\t");
 		}
+		self.write_block_inner(block, indent);
+		self.write_str("\n");
+	}
+	/// Write a block's braces and statements (the opening brace is at the current position)
+	fn write_block_inner(&mut self, block: &super::Block, indent: usize)
+	{
+		let saved_indent = self.indent;
 		self.write_str("{\n");
 		for sn in block
 		{
@@ -297,14 +398,15 @@ impl<'a, 'b> PrettyPrinter<'a, 'b>
 				self.write_str("\t");
 			}
 			// Only indent again if the statement is NOT a label
-			match sn
+			match sn.kind
 			{
-			&super::Statement::Label(..) => { },
-			&super::Statement::CaseDefault => { },
-			&super::Statement::CaseSingle(..) => { },
-			&super::Statement::CaseRange(..) => { },
+			super::StatementKind::Label(..) => { },
+			super::StatementKind::CaseDefault => { },
+			super::StatementKind::CaseSingle(..) => { },
+			super::StatementKind::CaseRange(..) => { },
 			_ => self.write_str("\t"),
 			}
+			self.indent = indent + 1;
 			if self.write_stmt(sn, indent) {
 				// No semicolon+newline needed
 			}
@@ -315,18 +417,19 @@ impl<'a, 'b> PrettyPrinter<'a, 'b>
 		for _ in 0 .. indent {
 			self.write_str("\t");
 		}
-		self.write_str("}\n");
+		self.write_str("}");
+		self.indent = saved_indent;
 	}
 	fn write_stmt(&mut self, stmt: &super::Statement, indent: usize) -> bool
 	{
-		use super::Statement;
-		match stmt
+		use super::StatementKind as Statement;
+		match stmt.kind
 		{
-		&Statement::Empty => { false },
-		&Statement::VarDef(ref vd) => { self.write_vardef(vd); false },
-		&Statement::Expr(ref e) => { self.write_node(e, super::NodePrecedence::Lowest); false },
-		&Statement::Block(ref b) => { self.write_block(b, indent+1); true },
-		&Statement::IfStatement { ref cond, ref true_arm, ref else_arm } => {
+		Statement::Empty => { false },
+		Statement::VarDef(ref vd) => { self.write_vardef(vd); false },
+		Statement::Expr(ref e) => { self.write_node(e, super::NodePrecedence::Lowest); false },
+		Statement::Block(ref b) => { self.write_block(b, indent+1); true },
+		Statement::IfStatement { ref cond, ref true_arm, ref else_arm } => {
 			self.write_str("if( ");
 			self.write_defexpr(cond);
 			self.write_str(" )\n");
@@ -340,14 +443,14 @@ impl<'a, 'b> PrettyPrinter<'a, 'b>
 			}
 			true
 			},
-		&Statement::WhileLoop { ref cond, ref body } => {
+		Statement::WhileLoop { ref cond, ref body } => {
 			self.write_str("while( ");
 			self.write_defexpr(cond);
 			self.write_str(" )\n");
 			self.write_block(body, indent+1);
 			true
 			},
-		&Statement::DoWhileLoop { ref body, ref cond } => {
+		Statement::DoWhileLoop { ref body, ref cond } => {
 			self.write_str("do\n");
 			self.write_block(body, indent+1);
 			for _ in 0 .. indent+1 {
@@ -358,7 +461,7 @@ impl<'a, 'b> PrettyPrinter<'a, 'b>
 			self.write_str(" )");
 			false
 			},
-		&Statement::ForLoop { ref init, ref cond, ref inc, ref body } => {
+		Statement::ForLoop { ref init, ref cond, ref inc, ref body } => {
 			self.write_str("for( ");
 			if let Some(init) = init {
 				self.write_defexpr(init);
@@ -375,9 +478,9 @@ impl<'a, 'b> PrettyPrinter<'a, 'b>
 			self.write_block(body, indent+1);
 			true
 			},
-		&Statement::Continue => { self.write_str("continue"); false }
-		&Statement::Break => { self.write_str("continue"); false }
-		&Statement::Return(ref v) => {
+		Statement::Continue => { self.write_str("continue"); false }
+		Statement::Break => { self.write_str("continue"); false }
+		Statement::Return(ref v) => {
 			self.write_str("return");
 			if let Some(ref e) = v {
 				self.write_str(" ");
@@ -385,19 +488,33 @@ impl<'a, 'b> PrettyPrinter<'a, 'b>
 			}
 			false
 			},
-		&Statement::Goto(ref n) => { write!(self, "goto {}", n); false },
+		Statement::Goto(ref n) => { write!(self, "goto {}", n); false },
 
-		&Statement::Switch(ref v, ref stmts) => {
+		Statement::Switch(ref v, ref stmts) => {
 			self.write_str("switch "); self.write_node(v, super::NodePrecedence::CommaOperator.up()); self.write_str("\n");
 			self.write_block(stmts, indent+1);
 			true
 			},
 
 		// NOTE: Labels have negative indentation (handled by caller)
-		&Statement::Label(ref n) => { write!(self, "{}:\n", n); true },
-		&Statement::CaseDefault => { self.write_str("default:\n"); true },
-		&Statement::CaseSingle(v) => { write!(self, "case {}:\n", v); true },
-		&Statement::CaseRange(v1, v2) => { write!(self, "case {} ... {}:\n", v1, v2); true },
+		Statement::Label(ref n) => { write!(self, "{}:\n", n); true },
+		Statement::CaseDefault => { self.write_str("default:\n"); true },
+		Statement::CaseSingle(v) => { write!(self, "case {}:\n", v); true },
+		Statement::CaseRange(v1, v2) => { write!(self, "case {} ... {}:\n", v1, v2); true },
+
+		Statement::Comment(ref text) => { self.write_str(text); self.write_str("\n"); true },
+		Statement::Directive(ref d) => {
+			use preproc::token::Preprocessor;
+			match *d
+			{
+			Preprocessor::Include { angle_brackets, ref path } => self.write_include(angle_brackets, path),
+			Preprocessor::MacroDefine { ref name, ref arg_names, ref variadic, ref expansion } => self.write_define(name, arg_names.as_ref(), variadic.as_ref(), expansion),
+			Preprocessor::MacroUndefine { ref name } => write!(self, "#undef {}\n", name),
+			Preprocessor::Conditional(ref text) => self.write_str(text),
+			_ => self.write_str("\n"),
+			}
+			true
+			},
 		}
 	}
 	fn write_vardef(&mut self, defs: &super::VarDefList)
@@ -430,25 +547,27 @@ impl<'a, 'b> PrettyPrinter<'a, 'b>
 		if node_p < max_p {
 			self.write_str("(");
 		}
-		use super::Node;
+		use super::NodePrecedence as P;
+		use super::NodeKind as Node;
 		use super::{UniOp,BinOp};
-		match node
+		match node.kind
 		{
-		&Node::StmtList(ref subnodes) => {
-			self.write_node(&subnodes[0], node_p.down());
+		Node::StmtList(ref subnodes) => {
+			self.write_node(&subnodes[0], node_p);
 			for sn in &subnodes[1..] {
 				self.write_str(", ");
-				self.write_node(sn, node_p.down());
+				self.write_node(sn, node_p.up());
 			}
 			},
 
-		&Node::Identifier(ref n) => self.write_str(n),
-		&Node::String(ref s) => write!(self, "{:?}", s),
-		&Node::Integer(v) => write!(self, "{}", v),
-		&Node::Float(v) => write!(self, "{}", v),
+		Node::Identifier(ref n) => self.write_str(n),
+		Node::String(ref s) => write!(self, "{}", s),
+		Node::Integer(v) => write!(self, "{}", v),
+		Node::Float(_, _, ref text) => self.write_str(text),
+		Node::Macro { ref input, .. } => self.write_tokens(input, true),
 
-		&Node::FcnCall(ref fcn, ref values) => {
-			self.write_node(fcn, node_p);
+		Node::FcnCall(ref fcn, ref values) => {
+			self.write_node(fcn, P::Postfix);
 			self.write_str("(");
 			if values.len() > 0 {
 				self.write_node(&values[0], super::NodePrecedence::CommaOperator.up());
@@ -460,18 +579,15 @@ impl<'a, 'b> PrettyPrinter<'a, 'b>
 			self.write_str(")");
 			},
 
-		&Node::Assign(ref dst, ref v) => {
-			self.write_node(dst, node_p);
+		Node::Assign(ref dst, ref v) => {
+			self.write_node(dst, P::Unary);
 			self.write_str(" = ");
-			self.write_node(v, node_p);
+			self.write_node(v, P::Assignment);
 			},
-		&Node::AssignOp(ref op, ref dst, ref v) => {
-			self.write_node(dst, node_p);
+		Node::AssignOp(ref op, ref dst, ref v) => {
+			self.write_node(dst, P::Unary);
 			match op
 			{
-			BinOp::LogicAnd => self.write_str(" &&= "),
-			BinOp::LogicOr  => self.write_str(" ||= "),
-
 			BinOp::BitAnd => self.write_str(" &= "),
 			BinOp::BitOr  => self.write_str(" |= "),
 			BinOp::BitXor => self.write_str(" ^= "),
@@ -479,7 +595,9 @@ impl<'a, 'b> PrettyPrinter<'a, 'b>
 			BinOp::ShiftLeft  => self.write_str(" <<= "),
 			BinOp::ShiftRight => self.write_str(" >>= "),
 
-			BinOp::CmpEqu
+			BinOp::LogicAnd
+			| BinOp::LogicOr
+			| BinOp::CmpEqu
 			| BinOp::CmpNEqu
 			| BinOp::CmpLt
 			| BinOp::CmpLtE
@@ -494,48 +612,60 @@ impl<'a, 'b> PrettyPrinter<'a, 'b>
 			BinOp::Div => self.write_str(" /= "),
 			BinOp::Mod => self.write_str(" %= "),
 			}
-			self.write_node(v, node_p);
+			self.write_node(v, P::Assignment);
 			},
 
-		&Node::Cast(ref ty, ref v) => {
+		Node::Cast(ref ty, ref v) => {
 			self.write_str("(");
 			self.write_type(ty, |_|{});
 			self.write_str(")");
-			self.write_node(v, node_p);
+			self.write_node(v, P::Cast);
 			},
-		&Node::SizeofType(ref ty) => {
-			self.write_str("sizeof ");
+		Node::CompoundLiteral(ref ty, ref init) => {
+			self.write_str("(");
 			self.write_type(ty, |_|{});
+			self.write_str(")");
+			self.write_initialiser_value(init);
 			},
-		&Node::SizeofExpr(ref v) => {
+		Node::SizeofType(ref ty) => {
+			self.write_str("sizeof(");
+			self.write_type(ty, |_|{});
+			self.write_str(")");
+			},
+		Node::SizeofExpr(ref v) => {
 			self.write_str("sizeof(");
 			self.write_node(v, super::NodePrecedence::CommaOperator.up());
 			self.write_str(")");
 			},
 
-		&Node::Ternary(ref c, ref t, ref f) => {
-			self.write_node(c, node_p.down());
+		Node::Ternary(ref c, ref t, ref f) => {
+			self.write_node(c, P::LogicOr);
 			self.write_str("?");
-			self.write_node(t, node_p.down());
+			self.write_node(t, P::Lowest);
 			self.write_str(":");
-			self.write_node(f, node_p);
+			self.write_node(f, P::Ternary);
 			},
-		&Node::UniOp(ref op, ref v) => {
-			match op
+		Node::UniOp(ref op, ref v) => {
+			// The operand of a prefix operator is a cast-expression, except for `++` and `--`
+			let operand_p = match op
 			{
-			&UniOp::Neg => self.write_str("- "),
-			&UniOp::BitNot => self.write_str("~"),
-			&UniOp::LogicNot => self.write_str("!"),
-			&UniOp::PreInc => self.write_str("++"),
-			&UniOp::PreDec => self.write_str("--"),
-			&UniOp::PostInc => { self.write_node(v, node_p); self.write_str("++"); return },
-			&UniOp::PostDec => { self.write_node(v, node_p); self.write_str("--"); return },
-			&UniOp::Address => self.write_str("&"),
-			&UniOp::Deref => self.write_str("*"),
-			}
-			self.write_node(v, node_p);
-			},
-		&Node::BinOp(ref op, ref l, ref r) => {
+			&UniOp::Plus => { self.write_str("+ "); Some(P::Cast) },
+			&UniOp::Neg => { self.write_str("- "); Some(P::Cast) },
+			&UniOp::BitNot => { self.write_str("~"); Some(P::Cast) },
+			&UniOp::LogicNot => { self.write_str("!"); Some(P::Cast) },
+			&UniOp::PreInc => { self.write_str("++"); Some(P::Unary) },
+			&UniOp::PreDec => { self.write_str("--"); Some(P::Unary) },
+			&UniOp::PostInc => { self.write_node(v, P::Postfix); self.write_str("++"); None },
+			&UniOp::PostDec => { self.write_node(v, P::Postfix); self.write_str("--"); None },
+			&UniOp::Address => { self.write_str("&"); Some(P::Cast) },
+			&UniOp::Deref => { self.write_str("*"); Some(P::Cast) },
+			};
+			if let Some(p) = operand_p {
+				self.write_node(v, p);
+			}
+			},
+		// All binary operators are left associative
+		Node::BinOp(ref op, ref l, ref r) => {
 			self.write_node(l, node_p);
 			match op
 			{
@@ -563,24 +693,46 @@ impl<'a, 'b> PrettyPrinter<'a, 'b>
 			&BinOp::Div => self.write_str(" / "),
 			&BinOp::Mod => self.write_str(" % "),
 			}
-			self.write_node(r, node_p);
+			self.write_node(r, node_p.up());
 			},
-		&Node::Index(ref v, ref i) => {
-			self.write_node(v, node_p);
+		Node::Index(ref v, ref i) => {
+			self.write_node(v, P::Postfix);
 			self.write_str("[");
-			self.write_node(i, super::NodePrecedence::CommaOperator.up());
+			self.write_node(i, P::Lowest);
 			self.write_str("]");
 			},
-		&Node::DerefMember(ref v, ref n) => {
-			self.write_node(v, node_p);
+		Node::DerefMember(ref v, ref n) => {
+			self.write_node(v, P::Postfix);
 			self.write_str("->");
 			self.write_str(n);
 			},
-		&Node::Member(ref v, ref n) => {
-			self.write_node(v, node_p);
+		Node::Member(ref v, ref n) => {
+			self.write_node(v, P::Postfix);
 			self.write_str(".");
 			self.write_str(n);
 			},
+
+		Node::Generic(ref v, ref assocs) => {
+			self.write_str("_Generic(");
+			self.write_node(v, P::Assignment);
+			for &(ref ty, ref e) in assocs {
+				self.write_str(", ");
+				match *ty
+				{
+				Some(ref ty) => self.write_type(ty, |_|{}),
+				None => self.write_str("default"),
+				}
+				self.write_str(": ");
+				self.write_node(e, P::Assignment);
+			}
+			self.write_str(")");
+			},
+		Node::StmtExpr(ref block) => {
+			let indent = self.indent;
+			self.write_str("(");
+			self.write_block_inner(block, indent);
+			self.write_str(")");
+			},
 		}
 		if node.get_precedence() < max_p {
 			self.write_str(")");
@@ -595,3 +747,21 @@ impl<'a, 'b> PrettyPrinter<'a, 'b>
 	}
 }
 
+/// Returns true if writing `next` directly after `prev` would change how they're lexed
+fn needs_space(prev: &str, next: &str) -> bool
+{
+	let is_word = |c: char| c.is_alphanumeric() || c == '_';
+	match (prev.chars().next_back(), next.chars().next())
+	{
+	(Some(a), Some(b)) if is_word(a) && is_word(b) => true,
+	(Some(a), Some(b)) => match (a, b)
+		{
+		('+','+') | ('-','-') | ('-','>') | ('<','<') | ('>','>') | ('&','&') | ('|','|') => true,
+		('/','/') | ('/','*') | ('.','.') => true,
+		('.', b) if b.is_digit(10) => true,
+		(_, '=') => "+-*/%&|^<>=!".contains(a),
+		_ => false,
+		},
+	_ => false,
+	}
+}
@@ -0,0 +1,210 @@
+/*!
+ * Machine-readable diagnostic output (plain JSON and SARIF 2.1.0)
+ */
+use std::io::Write;
+use std::io::Result;
+use preproc::Location;
+use super::{Diagnostic,SourceLines};
+
+/// Write all diagnostics as a JSON array
+///
+/// Each entry has the form
+/// `{"code":..,"severity":..,"message":..,"location":{..}|null,"include_stack":[{..}],"notes":[{..}]}`, with locations
+/// as `{"file":..,"line":..,"column":..,"length":..}` (`length` is in bytes).
+pub fn write_json(out: &mut Write, diags: &[Diagnostic]) -> Result<()>
+{
+	out.write_all(b"[")?;
+	for (i, d) in diags.iter().enumerate()
+	{
+		if i > 0 { out.write_all(b",")?; }
+		write_json_diag(out, d)?;
+	}
+	out.write_all(b"]\n")
+}
+
+fn write_json_diag(out: &mut Write, d: &Diagnostic) -> Result<()>
+{
+	write!(out, "{{\"code\":")?;
+	write_str(out, d.code)?;
+	write!(out, ",\"severity\":\"{}\",\"message\":", d.severity)?;
+	write_str(out, &d.message)?;
+	write!(out, ",\"location\":")?;
+	match d.location
+	{
+	Some(ref l) => write_json_location(out, l)?,
+	None => write!(out, "null")?,
+	}
+	write!(out, ",\"include_stack\":[")?;
+	for (i, l) in d.include_stack.iter().enumerate()
+	{
+		if i > 0 { write!(out, ",")?; }
+		write_json_location(out, l)?;
+	}
+	write!(out, "],\"notes\":[")?;
+	for (i, n) in d.notes.iter().enumerate()
+	{
+		if i > 0 { write!(out, ",")?; }
+		write_json_diag(out, n)?;
+	}
+	write!(out, "]}}")
+}
+
+fn write_json_location(out: &mut Write, l: &Location) -> Result<()>
+{
+	write!(out, "{{\"file\":")?;
+	match l.filename
+	{
+	Some(ref p) => write_str(out, &p.display().to_string())?,
+	None => write!(out, "null")?,
+	}
+	write!(out, ",\"line\":{},\"column\":{},\"length\":{}}}", l.line, l.column, l.length)
+}
+
+/// Write all diagnostics as a SARIF 2.1.0 log (a single run)
+///
+/// Notes and the include stack are emitted as `relatedLocations`. Relative paths are given relative to the `SRCROOT`
+/// base (the current directory), and `provider` (as passed to the pre-processor) is used to read the located text so
+/// `endColumn` can be counted in characters.
+pub fn write_sarif(out: &mut Write, diags: &[Diagnostic], provider: ::std::rc::Rc<::preproc::source::SourceProvider>) -> Result<()>
+{
+	let mut sources = SourceLines::new(provider);
+
+	// Each distinct code becomes a rule
+	let mut rules: Vec<&str> = diags.iter().map(|d| d.code).collect();
+	rules.sort();
+	rules.dedup();
+
+	write!(out, "{{\"$schema\":\"https://json.schemastore.org/sarif-2.1.0.json\",\"version\":\"2.1.0\",\"runs\":[{{")?;
+	write!(out, "\"tool\":{{\"driver\":{{\"name\":\"{}\",\"version\":\"{}\",\"rules\":[", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"))?;
+	for (i, r) in rules.iter().enumerate()
+	{
+		if i > 0 { write!(out, ",")?; }
+		write!(out, "{{\"id\":")?;
+		write_str(out, r)?;
+		write!(out, "}}")?;
+	}
+	write!(out, "]}}}}")?;
+	if let Ok(cwd) = ::std::env::current_dir() {
+		// NOTE: A base URI must end with a `/`
+		let mut base = file_uri(&cwd);
+		if !base.ends_with('/') {
+			base.push('/');
+		}
+		write!(out, ",\"originalUriBaseIds\":{{\"SRCROOT\":{{\"uri\":")?;
+		write_str(out, &base)?;
+		write!(out, "}}}}")?;
+	}
+	write!(out, ",\"results\":[")?;
+	for (i, d) in diags.iter().enumerate()
+	{
+		if i > 0 { write!(out, ",")?; }
+		write!(out, "{{\"ruleId\":")?;
+		write_str(out, d.code)?;
+		// NOTE: Severity names match SARIF's `level` values
+		write!(out, ",\"ruleIndex\":{},\"level\":\"{}\",\"message\":", rules.iter().position(|r| *r == d.code).unwrap(), d.severity)?;
+		write_sarif_message(out, &d.message)?;
+		if let Some(ref l) = d.location
+		{
+			write!(out, ",\"locations\":[{{\"physicalLocation\":")?;
+			write_sarif_location(out, l, &mut sources)?;
+			write!(out, "}}]")?;
+		}
+
+		let related = d.notes.iter()
+			.filter_map(|n| n.location.as_ref().map(|l| (format!("{}: {}", n.severity, n.message), l)))
+			.chain( d.include_stack.iter().map(|l| ("included from here".to_owned(), l)) )
+			;
+		write!(out, ",\"relatedLocations\":[")?;
+		for (i, (msg, l)) in related.enumerate()
+		{
+			if i > 0 { write!(out, ",")?; }
+			write!(out, "{{\"id\":{},\"message\":", i)?;
+			write_sarif_message(out, &msg)?;
+			write!(out, ",\"physicalLocation\":")?;
+			write_sarif_location(out, l, &mut sources)?;
+			write!(out, "}}")?;
+		}
+		write!(out, "]}}")?;
+	}
+	write!(out, "]}}]}}\n")
+}
+
+fn write_sarif_message(out: &mut Write, msg: &str) -> Result<()>
+{
+	write!(out, "{{\"text\":")?;
+	write_str(out, msg)?;
+	write!(out, "}}")
+}
+fn write_sarif_location(out: &mut Write, l: &Location, sources: &mut SourceLines) -> Result<()>
+{
+	write!(out, "{{\"artifactLocation\":{{\"uri\":")?;
+	match l.filename
+	{
+	Some(ref p) if p.is_absolute() => write_str(out, &file_uri(p))?,
+	Some(ref p) => {
+		write_str(out, &uri_encode(&p.to_string_lossy(), false))?;
+		write!(out, ",\"uriBaseId\":\"SRCROOT\"")?;
+		},
+	None => write_str(out, "stdin")?,
+	}
+	write!(out, "}},\"region\":{{\"startLine\":{},\"startColumn\":{}", l.line, l.column)?;
+	// Columns are in characters (the length is in bytes), so the end needs the source text
+	if l.length > 0 {
+		if let Some(n) = sources.get_line(l).and_then(|line| super::char_length(&line, l)) {
+			write!(out, ",\"endColumn\":{}", l.column + n)?;
+		}
+	}
+	write!(out, "}}}}")
+}
+
+/// `file://` URI for an absolute path
+fn file_uri(path: &::std::path::Path) -> String
+{
+	let path = uri_encode(&path.to_string_lossy(), true);
+	// Windows paths start with the drive letter
+	if path.starts_with('/') {
+		format!("file://{}", path)
+	}
+	else {
+		format!("file:///{}", path)
+	}
+}
+/// Percent-encode a path for use in a URI (`:` is only kept in an absolute path, as it can't be in the first segment
+/// of a relative reference)
+fn uri_encode(path: &str, is_absolute: bool) -> String
+{
+	let mut rv = String::new();
+	for b in path.bytes()
+	{
+		match b
+		{
+		b'a' ... b'z' | b'A' ... b'Z' | b'0' ... b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => rv.push(b as char),
+		b':' if is_absolute => rv.push(':'),
+		b'\\' => rv.push('/'),
+		_ => rv.push_str(&format!("%{:02X}", b)),
+		}
+	}
+	rv
+}
+
+/// Write a JSON string literal
+fn write_str(out: &mut Write, s: &str) -> Result<()>
+{
+	out.write_all(b"\"")?;
+	for c in s.chars()
+	{
+		match c
+		{
+		'"' => out.write_all(b"\\\"")?,
+		'\\' => out.write_all(b"\\\\")?,
+		'\n' => out.write_all(b"\\n")?,
+		'\r' => out.write_all(b"\\r")?,
+		'\t' => out.write_all(b"\\t")?,
+		'\0' ... '\x1f' => write!(out, "\\u{:04x}", c as u32)?,
+		_ => write!(out, "{}", c)?,
+		}
+	}
+	out.write_all(b"\"")
+}
+
+// vim: ft=rust
@@ -0,0 +1,279 @@
+/*!
+ * Compiler diagnostics (errors, warnings and notes), and rendering them for the user
+ */
+use std::collections::HashMap;
+use std::io::{Read,Write};
+use preproc::Location;
+
+mod json;
+pub use self::json::{write_json,write_sarif};
+
+/// How serious a diagnostic is
+#[derive(Debug,Copy,Clone,PartialEq,Eq,PartialOrd,Ord)]
+pub enum Severity
+{
+	/// Extra information attached to another diagnostic
+	Note,
+	/// Suspicious code, compilation can continue
+	Warning,
+	/// Invalid code, compilation fails
+	Error,
+}
+impl Severity
+{
+	/// ANSI colour code used when rendering
+	fn colour(&self) -> &'static str
+	{
+		match *self
+		{
+		Severity::Note => "\x1b[1;36m",
+		Severity::Warning => "\x1b[1;35m",
+		Severity::Error => "\x1b[1;31m",
+		}
+	}
+}
+impl ::std::fmt::Display for Severity
+{
+	fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result
+	{
+		f.write_str(match *self
+			{
+			Severity::Note => "note",
+			Severity::Warning => "warning",
+			Severity::Error => "error",
+			})
+	}
+}
+
+/// A message to the user about the code being compiled
+#[derive(Debug)]
+pub struct Diagnostic
+{
+	pub severity: Severity,
+	/// Short machine-readable identifier for the kind of diagnostic (e.g. `syntax-error`)
+	pub code: &'static str,
+	pub message: String,
+	/// Where in the source the diagnostic applies (`None` for e.g. command-line problems)
+	pub location: Option<Location>,
+	/// Locations of the `#include`s that lead to `location` (innermost first)
+	pub include_stack: Vec<Location>,
+	/// Attached notes (e.g. the macro expansion backtrace)
+	pub notes: Vec<Diagnostic>,
+}
+impl Diagnostic
+{
+	/// Create a diagnostic with no location
+	pub fn new(severity: Severity, code: &'static str, message: String) -> Diagnostic
+	{
+		Diagnostic {
+			severity: severity,
+			code: code,
+			message: message,
+			location: None,
+			include_stack: Vec::new(),
+			notes: Vec::new(),
+			}
+	}
+
+	pub fn is_error(&self) -> bool
+	{
+		self.severity == Severity::Error
+	}
+}
+
+/// How diagnostics are printed (`--diagnostics-format`)
+#[derive(Debug,Copy,Clone,PartialEq)]
+pub enum Format
+{
+	/// Human-readable text (see `Renderer`)
+	Text,
+	/// A JSON array of diagnostics
+	Json,
+	/// A SARIF 2.1.0 log
+	Sarif,
+}
+impl ::std::str::FromStr for Format
+{
+	type Err = String;
+	fn from_str(s: &str) -> Result<Self,String>
+	{
+		match s
+		{
+		"text" => Ok(Format::Text),
+		"json" => Ok(Format::Json),
+		"sarif" => Ok(Format::Sarif),
+		_ => Err(format!("Unknown diagnostics format '{}', expected text/json/sarif", s)),
+		}
+	}
+}
+
+/// When to use colour in rendered diagnostics (`--color`)
+#[derive(Debug,Copy,Clone,PartialEq)]
+pub enum ColorChoice
+{
+	/// Colour if the output is a terminal
+	Auto,
+	Always,
+	Never,
+}
+impl ::std::str::FromStr for ColorChoice
+{
+	type Err = String;
+	fn from_str(s: &str) -> Result<Self,String>
+	{
+		match s
+		{
+		"auto" => Ok(ColorChoice::Auto),
+		"always" => Ok(ColorChoice::Always),
+		"never" => Ok(ColorChoice::Never),
+		_ => Err(format!("Unknown colour mode '{}', expected always/never/auto", s)),
+		}
+	}
+}
+
+const BOLD: &str = "\x1b[1m";
+const CARET: &str = "\x1b[1;32m";
+const RESET: &str = "\x1b[0m";
+
+/// Renders diagnostics in a GCC-like format (with source excerpts)
+pub struct Renderer
+{
+	colour: bool,
+	sources: SourceLines,
+}
+impl Renderer
+{
+	/// Create a renderer for output sent to stderr, with excerpts read from `provider` (as passed to the
+	/// pre-processor in `Options::sources`)
+	pub fn new(colour: ColorChoice, provider: ::std::rc::Rc<::preproc::source::SourceProvider>) -> Renderer
+	{
+		Renderer {
+			colour: match colour
+				{
+				ColorChoice::Auto => ::atty::is(::atty::Stream::Stderr),
+				ColorChoice::Always => true,
+				ColorChoice::Never => false,
+				},
+			sources: SourceLines::new(provider),
+			}
+	}
+
+	pub fn emit(&mut self, out: &mut Write, diag: &Diagnostic) -> ::std::io::Result<()>
+	{
+		let (bold, reset) = if self.colour { (BOLD, RESET) } else { ("", "") };
+
+		for (i, inc) in diag.include_stack.iter().enumerate()
+		{
+			let prefix = if i == 0 { "In file included from" } else { "                 from" };
+			let sep = if i == diag.include_stack.len() - 1 { ':' } else { ',' };
+			writeln!(out, "{} {}{}:{}{}{}", prefix, bold, file_name(inc), inc.line, reset, sep)?;
+		}
+
+		match diag.location
+		{
+		Some(ref l) => write!(out, "{}{}:{} ", bold, l, reset)?,
+		None => write!(out, "{}cc:{} ", bold, reset)?,
+		}
+		if self.colour {
+			writeln!(out, "{}{}:{} {}", diag.severity.colour(), diag.severity, RESET, diag.message)?;
+		}
+		else {
+			writeln!(out, "{}: {}", diag.severity, diag.message)?;
+		}
+
+		if let Some(ref l) = diag.location
+		{
+			self.emit_excerpt(out, l)?;
+		}
+
+		for note in &diag.notes
+		{
+			self.emit(out, note)?;
+		}
+		Ok( () )
+	}
+
+	/// Print the source line for a location, with a caret/underline under the located text
+	fn emit_excerpt(&mut self, out: &mut Write, l: &Location) -> ::std::io::Result<()>
+	{
+		let line = match self.sources.get_line(l)
+			{
+			Some(v) => v,
+			None => return Ok( () ),
+			};
+		writeln!(out, " {:5} | {}", l.line, line)?;
+
+		// Indent using the source line's own whitespace so tabs line up
+		let indent: String = line.chars().take(l.column - 1).map(|c| if c == '\t' { '\t' } else { ' ' }).collect();
+		let underline_len = char_length(&line, l).unwrap_or(0).saturating_sub(1);
+		let underline: String = ::std::iter::once('^').chain( ::std::iter::repeat('~').take(underline_len) ).collect();
+		if self.colour {
+			writeln!(out, " {:5} | {}{}{}{}", "", indent, CARET, underline, RESET)?;
+		}
+		else {
+			writeln!(out, " {:5} | {}{}", "", indent, underline)?;
+		}
+		Ok( () )
+	}
+}
+
+/// Source lines for excerpts, read through the provider the pre-processor used (so stdin and in-memory files work)
+struct SourceLines
+{
+	provider: ::std::rc::Rc<::preproc::source::SourceProvider>,
+	/// Lines of each file read so far (`None` if the file couldn't be read)
+	files: HashMap<::std::path::PathBuf, Option<Vec<String>>>,
+}
+impl SourceLines
+{
+	fn new(provider: ::std::rc::Rc<::preproc::source::SourceProvider>) -> SourceLines
+	{
+		SourceLines {
+			provider: provider,
+			files: HashMap::new(),
+			}
+	}
+	/// Text of the line containing a location
+	fn get_line(&mut self, l: &Location) -> Option<String>
+	{
+		let path = match l.filename
+			{
+			Some(ref v) => v,
+			None => return None,
+			};
+		if l.line == 0 || l.column == 0 {
+			return None;
+		}
+		let provider = &self.provider;
+		let lines = self.files.entry(path.clone())
+			.or_insert_with(|| {
+				let mut text = String::new();
+				let mut f = provider.open(path).ok()?;
+				f.read_to_string(&mut text).ok()?;
+				Some( text.lines().map(|l| l.to_owned()).collect() )
+				});
+		match *lines
+		{
+		Some(ref lines) => lines.get(l.line - 1).cloned(),
+		None => None,
+		}
+	}
+}
+
+/// Length in characters of the located text within `line` (`None` if the location is past the end of the line)
+fn char_length(line: &str, l: &Location) -> Option<usize>
+{
+	let start = line.char_indices().nth(l.column - 1)?.0;
+	Some( line[start..].char_indices().take_while(|&(i,_)| i < l.length).count() )
+}
+
+fn file_name(l: &Location) -> String
+{
+	match l.filename
+	{
+	Some(ref p) => p.display().to_string(),
+	None => "<stdin>".to_owned(),
+	}
+}
+
+// vim: ft=rust
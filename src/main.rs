@@ -11,9 +11,11 @@ extern crate log;
 extern crate env_logger;
 
 extern crate utf8reader;
+extern crate atty;
 #[macro_use]
 extern crate structopt;
 
+mod diagnostics;
 mod preproc;
 mod parse;
 mod types;
@@ -23,15 +25,87 @@ mod ast;
 #[derive(StructOpt)]
 struct Options
 {
+	/// Source file (`-` reads from stdin)
 	#[structopt(parse(from_os_str))]
 	input: ::std::path::PathBuf,
 
-	#[structopt(short="I",parse(from_os_str))]
+	#[structopt(short="I",parse(from_os_str),raw(number_of_values="1"))]
 	include_dirs: Vec<::std::path::PathBuf>,
+	/// `-iquote dir` - Search `dir` for `#include "..."` only (before the `-I` directories)
+	#[structopt(long="iquote",parse(from_os_str),raw(number_of_values="1"))]
+	quote_dirs: Vec<::std::path::PathBuf>,
+	/// `-isystem dir` - Search `dir` after the `-I` directories, headers found there are system headers
+	#[structopt(long="isystem",parse(from_os_str),raw(number_of_values="1"))]
+	system_dirs: Vec<::std::path::PathBuf>,
+	/// `-idirafter dir` - Search `dir` after all other directories (as a system directory)
+	#[structopt(long="idirafter",parse(from_os_str),raw(number_of_values="1"))]
+	after_dirs: Vec<::std::path::PathBuf>,
 
-	/// `-D FOO=bar`
-	#[structopt(short="D")]
+	/// `-D FOO=bar`, `-D FOO` (defines as `1`), or `-D 'FOO(a,b)=body'`
+	#[structopt(short="D",raw(number_of_values="1"))]
 	defines: Vec<String>,
+	/// `-U FOO` - Undefine a macro (applied in order with `-D`)
+	#[structopt(short="U",raw(number_of_values="1"))]
+	undefines: Vec<String>,
+	/// `-include file` - Include a file before the main source
+	#[structopt(long="include",parse(from_os_str),raw(number_of_values="1"))]
+	force_includes: Vec<::std::path::PathBuf>,
+
+	/// Colour diagnostics (always, never, or auto)
+	#[structopt(long="color", default_value="auto")]
+	color: ::diagnostics::ColorChoice,
+
+	/// Pre-process only, writing the expanded source to stdout
+	#[structopt(short="E")]
+	preprocess_only: bool,
+	/// (With `-E`) Don't emit linemarkers
+	#[structopt(short="P")]
+	no_linemarkers: bool,
+	/// (With `-E`) Keep comments
+	#[structopt(short="C")]
+	keep_comments: bool,
+	/// (With `-E`) Debug dumps, `-dM` prints the final macro definitions instead of the source
+	#[structopt(short="d")]
+	dump: Option<String>,
+	/// Keep comments, `#include`s, `#define`s and macro invocations, so the output follows the input source
+	#[structopt(long="round-trip")]
+	round_trip: bool,
+
+	/// `-M` - Write a Makefile rule listing the input's dependencies (instead of the pre-processed output)
+	#[structopt(long="M")]
+	deps_only: bool,
+	/// `-MM` - As `-M`, but omitting system headers
+	#[structopt(long="MM")]
+	deps_only_user: bool,
+	/// `-MD` - Write the dependency rule to a file while compiling
+	#[structopt(long="MD")]
+	deps_file_too: bool,
+	/// `-MMD` - As `-MD`, but omitting system headers
+	#[structopt(long="MMD")]
+	deps_file_too_user: bool,
+	/// `-MF file` - Write the dependency rule to `file` (default is stdout for `-M`, and `<input>.d` for `-MD`)
+	#[structopt(long="MF",parse(from_os_str))]
+	deps_file: Option<::std::path::PathBuf>,
+	/// `-MT target` - Set the rule's target (default is the object file name, can be repeated)
+	#[structopt(long="MT",raw(number_of_values="1"))]
+	deps_targets: Vec<String>,
+	/// `-MP` - Add an empty rule for each header
+	#[structopt(long="MP")]
+	deps_phony: bool,
+
+	/// Target profile (selects the predefined macros)
+	#[structopt(long="target", default_value="x86_64-linux-gnu")]
+	target: ::preproc::Target,
+	/// Accept C23 digit separators in numeric constants (`1'000'000`)
+	#[structopt(long="digit-separators")]
+	digit_separators: bool,
+	/// `-trigraphs` - Replace trigraphs (`??=` etc)
+	#[structopt(long="trigraphs")]
+	trigraphs: bool,
+
+	/// Format of error/warning output (text, json, or sarif)
+	#[structopt(long="diagnostics-format", default_value="text")]
+	diagnostics_format: ::diagnostics::Format,
 }
 
 fn main()
@@ -39,21 +113,176 @@ fn main()
 	env_logger::init();
 
 	// 1. Parse command line arguments
-	let args: Options = ::structopt::StructOpt::from_args();
+	// - GCC spells `-include` (and the `-i` directory options) with a single dash, so rewrite them to the long form
+	let argv = ::std::env::args_os().map(gcc_long_option);
+	let matches = <Options as ::structopt::StructOpt>::clap().get_matches_from(argv);
+	let args = <Options as ::structopt::StructOpt>::from_clap(&matches);
 	
 	let mut program = ::ast::Program::new();
 
-	// - Parse into the AST
-	match ::parse::parse(&mut program, &args.input, args.include_dirs, &args.defines)
+	let mut pp_opts = ::preproc::Options::default();
+	pp_opts.quote_paths = args.quote_dirs.clone();
+	pp_opts.include_paths = args.include_dirs.clone();
+	pp_opts.system_paths = args.system_dirs.clone();
+	pp_opts.after_paths = args.after_dirs.clone();
+	pp_opts.return_most_comments = args.preprocess_only && args.keep_comments;
+	// GCC passes unknown pragmas through to the pre-processed output
+	pp_opts.propagate_pragmas = args.preprocess_only;
+	if args.round_trip && ! args.preprocess_only {
+		pp_opts.include_handling = ::preproc::Handling::InternalAndPropagate;
+		pp_opts.define_handling = ::preproc::Handling::InternalAndPropagate;
+		pp_opts.wrap_define_expansion = true;
+		pp_opts.return_most_comments = true;
+		pp_opts.propagate_conditionals = true;
+	}
+	pp_opts.target = args.target;
+	pp_opts.digit_separators = args.digit_separators;
+	pp_opts.trigraphs = args.trigraphs;
+	// `-` reads the source from stdin, as an in-memory file named `<stdin>` (so `#include "..."` uses the current
+	// directory, as with GCC)
+	let input = if args.input == ::std::path::Path::new("-") {
+			let mut text = String::new();
+			if let Err(e) = ::std::io::Read::read_to_string(&mut ::std::io::stdin(), &mut text) {
+				eprintln!("cc: error: Reading stdin: {}", e);
+				::std::process::exit(1);
+			}
+			let name = ::std::path::PathBuf::from("<stdin>");
+			let mut overlay = ::preproc::source::Overlay::new(Some(box ::preproc::source::FileSystem));
+			overlay.add_file(&name, text);
+			pp_opts.sources = ::std::rc::Rc::new(overlay);
+			name
+		}
+		else {
+			args.input.clone()
+		};
+	pp_opts.command_line = {
+		// `-D` and `-U` are applied in the order they were given, then the `-include` files
+		let mut ops: Vec<(usize, ::preproc::CommandLineOp)> = Vec::new();
+		if let Some(idxs) = matches.indices_of("defines") {
+			ops.extend( idxs.zip(args.defines.iter()).map(|(i,d)| (i, ::preproc::CommandLineOp::Define(d.clone()))) );
+		}
+		if let Some(idxs) = matches.indices_of("undefines") {
+			ops.extend( idxs.zip(args.undefines.iter()).map(|(i,d)| (i, ::preproc::CommandLineOp::Undefine(d.clone()))) );
+		}
+		ops.sort_by_key(|&(i,_)| i);
+		let mut ops: Vec<_> = ops.into_iter().map(|(_,o)| o).collect();
+		ops.extend( args.force_includes.iter().map(|p| ::preproc::CommandLineOp::Include(p.clone())) );
+		ops
+		};
+
+	// `-M`/`-MM` replace the output with the dependency rule (and imply `-E`)
+	let deps_only = args.deps_only || args.deps_only_user;
+	let deps_opts = if deps_only || args.deps_file_too || args.deps_file_too_user {
+			let stem = args.input.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+			Some(::preproc::deps::Options {
+				targets: if args.deps_targets.is_empty() { vec![format!("{}.o", stem)] } else { args.deps_targets.clone() },
+				include_system: args.deps_only || args.deps_file_too,
+				phony_targets: args.deps_phony,
+				})
+		}
+		else {
+			None
+		};
+	let mut deps = Vec::new();
+	// - Diagnostics excerpt the source through the same provider
+	let sources = pp_opts.sources.clone();
+
+	let diagnostics = if args.preprocess_only || deps_only {
+			// - Just run the pre-processor
+			let output = match args.dump
+				{
+				None if deps_only => ::preproc::print::Output::Nothing,
+				None => ::preproc::print::Output::Source { linemarkers: !args.no_linemarkers },
+				Some(ref v) if v == "M" => ::preproc::print::Output::Macros,
+				Some(ref v) => {
+					eprintln!("cc: error: Unknown dump option -d{}", v);
+					::std::process::exit(1);
+					},
+				};
+			let stdout = ::std::io::stdout();
+			::preproc::print::preprocess(&mut stdout.lock(), &input, pp_opts, output, &mut deps)
+		}
+		else {
+			// - Parse into the AST
+			::parse::parse(&mut program, &input, pp_opts, &mut deps)
+		};
+	{
+		let stderr = ::std::io::stderr();
+		let mut stderr = stderr.lock();
+		let _ = match args.diagnostics_format
+			{
+			::diagnostics::Format::Text => {
+				let mut renderer = ::diagnostics::Renderer::new(args.color, sources);
+				diagnostics.iter().map(|d| renderer.emit(&mut stderr, d)).collect()
+				},
+			::diagnostics::Format::Json => ::diagnostics::write_json(&mut stderr, &diagnostics),
+			::diagnostics::Format::Sarif => ::diagnostics::write_sarif(&mut stderr, &diagnostics, sources),
+			};
+	}
+	let n_errors = diagnostics.iter().filter(|d| d.is_error()).count();
+	if n_errors > 0
+	{
+		if args.diagnostics_format == ::diagnostics::Format::Text {
+			eprintln!("{} error(s) generated.", n_errors);
+		}
+		::std::process::exit(1);
+	}
+
+	if let Some(ref deps_opts) = deps_opts
+	{
+		let rv = match args.deps_file
+			{
+			None if deps_only => {
+				let stdout = ::std::io::stdout();
+				::preproc::deps::write_rule(&mut stdout.lock(), deps_opts, &deps)
+				},
+			ref p => {
+				let path = match *p
+					{
+					Some(ref p) => p.clone(),
+					None => args.input.with_extension("d").file_name().map(|n| n.into()).unwrap_or_default(),
+					};
+				::std::fs::File::create(&path).and_then(|mut f| ::preproc::deps::write_rule(&mut f, deps_opts, &deps))
+				},
+			};
+		if let Err(e) = rv {
+			eprintln!("cc: error: Writing dependencies failed: {}", e);
+			::std::process::exit(1);
+		}
+	}
+
+	if ! args.preprocess_only && ! deps_only
 	{
-	Err(e) => {
-		panic!("Error parsing file: {:?}", e);
-		},
-	Ok(_) => {}
+		let stdout = ::std::io::stdout();
+		::ast::pretty_print::write(stdout.lock(), &program);
 	}
+}
 
-	let stdout = ::std::io::stdout();
-	::ast::pretty_print::write(stdout.lock(), &program);
+/// Rewrite GCC's single-dash long options (`-include file`, `-isystem dir`, `-isystemdir`, `-MD`, `-trigraphs`, ...) to `--name[=value]`
+fn gcc_long_option(arg: ::std::ffi::OsString) -> ::std::ffi::OsString
+{
+	// Option names, and if they take a value (which can be joined to the name)
+	const OPTIONS: &[(&str, bool)] = &[
+		("include", true), ("iquote", true), ("isystem", true), ("idirafter", true),
+		("M", false), ("MM", false), ("MD", false), ("MMD", false), ("MP", false), ("MF", true), ("MT", true),
+		("trigraphs", false),
+		];
+	let rewritten = match arg.to_str()
+		{
+		Some(a) if a.starts_with("-") && !a.starts_with("--") => {
+			let a = &a[1..];
+			OPTIONS.iter()
+				.filter(|&&(o, has_value)| a == o || (has_value && a.starts_with(o)))
+				.map(|&(o, _)| if a == o { format!("--{}", o) } else { format!("--{}={}", o, &a[o.len()..]) })
+				.next()
+			},
+		_ => None,
+		};
+	match rewritten
+	{
+	Some(a) => a.into(),
+	None => arg,
+	}
 }
 
 // vim: ft=rust
@@ -3,29 +3,6 @@
 use parse::Token;
 use parse::ParseResult;
 
-
-// Parse, left associative
-macro_rules! parse_left_assoc
-{
-	($_self:ident, $name:ident, $next:ident, $rv:ident, { $($patterns:pat => $vals:expr),*, }) => {
-		fn $name(&mut $_self) -> ParseResult<::ast::Node> {
-			let mut $rv = try!($_self.$next());
-			loop
-			{
-				$rv = match try!($_self.lex.get_token())
-					{
-					$($patterns => $vals),*,
-					t @ _ => {
-						$_self.lex.put_back(t);
-						break;
-						}
-					};
-			}
-			Ok($rv)
-		}
-	}
-}
-
 impl<'ast> super::ParseState<'ast>
 {
 	// ----------------------------------------------------------------
@@ -42,12 +19,13 @@ impl<'ast> super::ParseState<'ast>
 		let exp = try!(self.parse_expr());
 		if peek_token_nc!(self.lex, Token::Comma)
 		{
+			let span = exp.span.clone();
 			let mut exprs = vec![exp];
 			while peek_token!(self.lex, Token::Comma)
 			{
 				exprs.push( try!(self.parse_expr()) );
 			}
-			Ok(::ast::Node::StmtList(exprs))
+			Ok(::ast::Node::new(span, ::ast::NodeKind::StmtList(exprs)))
 		}
 		else
 		{
@@ -55,150 +33,196 @@ impl<'ast> super::ParseState<'ast>
 		}
 	}
 	
-	/// Parse #0 : Assignment
+	/// Parse #0 : Assignment (right associative)
 	fn parse_expr_0(&mut self) -> ParseResult<::ast::Node>
 	{
 		let rv = try!(self.parse_expr_1());
-		Ok( match try!(self.lex.get_token())
+		let span = rv.span.clone();
+		let kind = match try!(self.lex.get_token())
 		{
-		Token::Assign => ::ast::Node::Assign(box rv, box try!(self.parse_expr_0())),
-		Token::AssignBitAnd => ::ast::Node::AssignOp(::ast::BinOp::BitAnd, box rv, box try!(self.parse_expr_0())),
-		Token::AssignBitOr  => ::ast::Node::AssignOp(::ast::BinOp::BitOr,  box rv, box try!(self.parse_expr_0())),
-		Token::AssignAdd  => ::ast::Node::AssignOp(::ast::BinOp::Add,  box rv, box try!(self.parse_expr_0())),
-		Token::AssignSub  => ::ast::Node::AssignOp(::ast::BinOp::Sub,  box rv, box try!(self.parse_expr_0())),
-		Token::AssignMul  => ::ast::Node::AssignOp(::ast::BinOp::Mul,  box rv, box try!(self.parse_expr_0())),
-		Token::AssignDiv  => ::ast::Node::AssignOp(::ast::BinOp::Div,  box rv, box try!(self.parse_expr_0())),
-		Token::AssignMod  => ::ast::Node::AssignOp(::ast::BinOp::Mod,  box rv, box try!(self.parse_expr_0())),
+		Token::Assign => ::ast::NodeKind::Assign(box rv, box try!(self.parse_expr_0())),
+		Token::AssignBitAnd => ::ast::NodeKind::AssignOp(::ast::BinOp::BitAnd, box rv, box try!(self.parse_expr_0())),
+		Token::AssignBitOr  => ::ast::NodeKind::AssignOp(::ast::BinOp::BitOr,  box rv, box try!(self.parse_expr_0())),
+		Token::AssignBitXor => ::ast::NodeKind::AssignOp(::ast::BinOp::BitXor, box rv, box try!(self.parse_expr_0())),
+		Token::AssignShiftLeft  => ::ast::NodeKind::AssignOp(::ast::BinOp::ShiftLeft,  box rv, box try!(self.parse_expr_0())),
+		Token::AssignShiftRight => ::ast::NodeKind::AssignOp(::ast::BinOp::ShiftRight, box rv, box try!(self.parse_expr_0())),
+		Token::AssignAdd  => ::ast::NodeKind::AssignOp(::ast::BinOp::Add,  box rv, box try!(self.parse_expr_0())),
+		Token::AssignSub  => ::ast::NodeKind::AssignOp(::ast::BinOp::Sub,  box rv, box try!(self.parse_expr_0())),
+		Token::AssignMul  => ::ast::NodeKind::AssignOp(::ast::BinOp::Mul,  box rv, box try!(self.parse_expr_0())),
+		Token::AssignDiv  => ::ast::NodeKind::AssignOp(::ast::BinOp::Div,  box rv, box try!(self.parse_expr_0())),
+		Token::AssignMod  => ::ast::NodeKind::AssignOp(::ast::BinOp::Mod,  box rv, box try!(self.parse_expr_0())),
 		t @ _ => {
 			self.lex.put_back(t);
-			rv
+			return Ok(rv);
 			}
-		})
+		};
+		Ok( ::ast::Node::new(span, kind) )
 	}
 	
-	/// Expression #1 - Ternary
+	/// Expression #1 - Ternary (`logical-OR-expression ? expression : conditional-expression`)
 	fn parse_expr_1(&mut self) -> ParseResult<::ast::Node>
 	{
-		let rv = try!(self.parse_expr_2());
+		let rv = try!(self.parse_expr_binary(::ast::NodePrecedence::LogicOr));
+		let span = rv.span.clone();
 		
-		Ok( match try!(self.lex.get_token())
+		let kind = match try!(self.lex.get_token())
 		{
 		Token::QuestionMark => {
 			debug!("Ternary, rv (cnd) = {:?}", rv);
-			let tv = box try!(self.parse_expr_1());
+			let tv = box try!(self.parse_expr_list());
 			debug!("Ternary - tv = {:?}", tv);
-			syntax_assert!(try!(self.lex.get_token()), Token::Colon);
+			syntax_assert!(self.lex => Token::Colon);
 			let fv = box try!(self.parse_expr_1());
 			debug!("Ternary - fv = {:?}", fv);
-			::ast::Node::Ternary(box rv, tv, fv)
+			::ast::NodeKind::Ternary(box rv, tv, fv)
 			}
 		t @ _ => {
 			self.lex.put_back(t);
-			rv
+			return Ok(rv);
 			}
-		})
+		};
+		Ok( ::ast::Node::new(span, kind) )
 	}
 	
-	/// Expression #2 - Boolean AND/OR
-	parse_left_assoc!{self, parse_expr_2, parse_expr_3, rv, {
-		Token::DoublePipe      => ::ast::Node::BinOp(::ast::BinOp::LogicOr,  box rv, box try!(self.parse_expr_3())),
-		Token::DoubleAmpersand => ::ast::Node::BinOp(::ast::BinOp::LogicAnd, box rv, box try!(self.parse_expr_3())),
-	}}
-	
-	/// Expresission #3 - Bitwise
-	parse_left_assoc!{self, parse_expr_3, parse_expr_4, rv, {
-		Token::Pipe      => ::ast::Node::BinOp(::ast::BinOp::BitOr , box rv, box try!(self.parse_expr_4())),
-		Token::Ampersand => ::ast::Node::BinOp(::ast::BinOp::BitAnd, box rv, box try!(self.parse_expr_4())),
-		Token::Caret     => ::ast::Node::BinOp(::ast::BinOp::BitXor, box rv, box try!(self.parse_expr_4())),
-	}}
-	
-	/// Expression #4 - Comparison Operators
-	parse_left_assoc!{self, parse_expr_4, parse_expr_5, rv, {
-		Token::Equality => ::ast::Node::BinOp(::ast::BinOp::CmpEqu, box rv, box try!(self.parse_expr_5())),
-		Token::NotEquals => ::ast::Node::BinOp(::ast::BinOp::CmpNEqu, box rv, box try!(self.parse_expr_5())),
-		Token::Lt  => ::ast::Node::BinOp(::ast::BinOp::CmpLt,  box rv, box try!(self.parse_expr_5())),
-		Token::LtE => ::ast::Node::BinOp(::ast::BinOp::CmpLtE, box rv, box try!(self.parse_expr_5())),
-		Token::Gt  => ::ast::Node::BinOp(::ast::BinOp::CmpGt,  box rv, box try!(self.parse_expr_5())),
-		Token::GtE => ::ast::Node::BinOp(::ast::BinOp::CmpGtE, box rv, box try!(self.parse_expr_5())),
-	}}
-	
-	/// Expression #5 - Bit Shifts
-	parse_left_assoc!{self, parse_expr_5, parse_expr_6, rv, {
-		Token::ShiftLeft  => ::ast::Node::BinOp(::ast::BinOp::ShiftLeft,  box rv, box try!(self.parse_expr_6())),
-		Token::ShiftRight => ::ast::Node::BinOp(::ast::BinOp::ShiftRight, box rv, box try!(self.parse_expr_6())),
-	}}
-	
-	/// Expresion #6 - Arithmatic
-	parse_left_assoc!{self, parse_expr_6, parse_expr_7, rv, {
-		Token::Plus  => ::ast::Node::BinOp(::ast::BinOp::Add, box rv, box try!(self.parse_expr_7())),
-		Token::Minus => ::ast::Node::BinOp(::ast::BinOp::Sub, box rv, box try!(self.parse_expr_7())),
-	}}
-	
-	/// Expression #7 - Multiply/Divide
-	parse_left_assoc!{self, parse_expr_7, parse_expr_8, rv, {
-		Token::Star  => ::ast::Node::BinOp(::ast::BinOp::Mul, box rv, box try!(self.parse_expr_8())),
-		Token::Slash => ::ast::Node::BinOp(::ast::BinOp::Div, box rv, box try!(self.parse_expr_8())),
-		Token::Percent => ::ast::Node::BinOp(::ast::BinOp::Mod, box rv, box try!(self.parse_expr_8())),
-	}}
-	
-	/// Expression #8 - Unary Righthand
-	parse_left_assoc!{self, parse_expr_8, parse_expr_9, rv, {
-		Token::DoublePlus  => ::ast::Node::UniOp(::ast::UniOp::PostInc, box rv),
-		Token::DoubleMinus => ::ast::Node::UniOp(::ast::UniOp::PostDec, box rv),
-	}}
+	/// Expression #2 - Binary operators (precedence climbing, see `binary_operator`)
+	///
+	/// Parses operators with a precedence of at least `min_p`, all binary operators are left associative.
+	fn parse_expr_binary(&mut self, min_p: ::ast::NodePrecedence) -> ParseResult<::ast::Node>
+	{
+		let mut rv = try!(self.parse_expr_unary());
+		loop
+		{
+			let tok = try!(self.lex.get_token());
+			match binary_operator(&tok)
+			{
+			Some((op, p)) if p >= min_p => {
+				let span = rv.span.clone();
+				let rhs = try!(self.parse_expr_binary(p.up()));
+				rv = ::ast::Node::new(span, ::ast::NodeKind::BinOp(op, box rv, box rhs));
+				},
+			_ => {
+				self.lex.put_back(tok);
+				return Ok(rv);
+				},
+			}
+		}
+	}
 	
-	/// Expression #9 - Unary left
-	fn parse_expr_9(&mut self) -> ParseResult<::ast::Node>
+	/// Expression #3 - Unary prefix operators (casts are handled with parens, see `parse_expr_p`)
+	fn parse_expr_unary(&mut self) -> ParseResult<::ast::Node>
 	{
-		Ok( match try!(self.lex.get_token())
+		let tok = try!(self.lex.get_token());
+		let span = self.lex.span().clone();
+		let kind = match tok
 		{
-		Token::Minus       => ::ast::Node::UniOp(::ast::UniOp::Neg,      box try!(self.parse_expr_9())),
-		Token::Tilde       => ::ast::Node::UniOp(::ast::UniOp::BitNot,   box try!(self.parse_expr_9())),
-		Token::Exclamation => ::ast::Node::UniOp(::ast::UniOp::LogicNot, box try!(self.parse_expr_9())),
-		Token::DoublePlus  => ::ast::Node::UniOp(::ast::UniOp::PreInc,   box try!(self.parse_expr_9())),
-		Token::DoubleMinus => ::ast::Node::UniOp(::ast::UniOp::PreDec,   box try!(self.parse_expr_9())),
-		Token::Star        => ::ast::Node::UniOp(::ast::UniOp::Deref,    box try!(self.parse_expr_9())),
-		Token::Ampersand   => ::ast::Node::UniOp(::ast::UniOp::Address, box try!(self.parse_expr_member())),	// different, as double addr is inval
+		Token::Plus        => ::ast::NodeKind::UniOp(::ast::UniOp::Plus,     box try!(self.parse_expr_unary())),
+		Token::Minus       => ::ast::NodeKind::UniOp(::ast::UniOp::Neg,      box try!(self.parse_expr_unary())),
+		Token::Tilde       => ::ast::NodeKind::UniOp(::ast::UniOp::BitNot,   box try!(self.parse_expr_unary())),
+		Token::Exclamation => ::ast::NodeKind::UniOp(::ast::UniOp::LogicNot, box try!(self.parse_expr_unary())),
+		Token::Star        => ::ast::NodeKind::UniOp(::ast::UniOp::Deref,    box try!(self.parse_expr_unary())),
+		Token::Ampersand   => ::ast::NodeKind::UniOp(::ast::UniOp::Address,  box try!(self.parse_expr_unary())),
+		Token::DoublePlus  => ::ast::NodeKind::UniOp(::ast::UniOp::PreInc,   box try!(self.parse_expr_unary())),
+		Token::DoubleMinus => ::ast::NodeKind::UniOp(::ast::UniOp::PreDec,   box try!(self.parse_expr_unary())),
+		// `sizeof ( type-name )` or `sizeof unary-expression`
+		Token::Rword_sizeof => {
+			if peek_token!(self.lex, Token::ParenOpen)
+			{
+				match try!(self.get_base_type_opt())
+				{
+				Some(t) => {
+					let (tr, name) = try!(self.get_full_type(t));
+					if ! name.is_empty() {
+						syntax_error!("Unexpected name in sizeof");
+					}
+					syntax_assert!(self.lex => Token::ParenClose);
+					if peek_token!(self.lex, Token::BraceOpen) {
+						let lit = ::ast::Node::new(span.clone(), ::ast::NodeKind::CompoundLiteral(tr, box self.parse_composite_lit()?));
+						::ast::NodeKind::SizeofExpr(box self.parse_expr_postfix(lit)?)
+					}
+					else {
+						::ast::NodeKind::SizeofType(tr)
+					}
+					},
+				None => {
+					self.lex.put_back(Token::ParenOpen);
+					::ast::NodeKind::SizeofExpr(box try!(self.parse_expr_unary()))
+					},
+				}
+			}
+			else
+			{
+				::ast::NodeKind::SizeofExpr(box try!(self.parse_expr_unary()))
+			}
+			},
 		t @ _ => {
 			self.lex.put_back(t);
-			try!(self.parse_expr_member())
+			return self.parse_expr_member();
 			},
-		})
+		};
+		Ok( ::ast::Node::new(span, kind) )
 	}
 	
 	
-	/// Expression - Member access
-	parse_left_assoc!{self, parse_expr_member, parse_expr_p, rv, {
-		Token::DerefMember => ::ast::Node::DerefMember(box rv, syntax_assert!(self.lex => Token::Ident(i) @ i)),
-		Token::Period      => ::ast::Node::Member(     box rv, syntax_assert!(self.lex => Token::Ident(i) @ i)),
-		Token::SquareOpen => {
-				let idx = box try!(self.parse_expr());
-				syntax_assert!(self.lex => Token::SquareClose);
-				::ast::Node::Index(box rv, idx)
-				},
-		Token::ParenOpen => {
-			let mut args = Vec::new();
-			if ! peek_token!(self.lex, Token::ParenClose)
-			{
-				loop
+	/// Expression #4 - Postfix operators (member access, indexing, calls, and post-increment/decrement)
+	fn parse_expr_member(&mut self) -> ParseResult<::ast::Node>
+	{
+		let rv = self.parse_expr_p()?;
+		self.parse_expr_postfix(rv)
+	}
+	/// Apply any postfix operators to an already-parsed operand
+	fn parse_expr_postfix(&mut self, mut rv: ::ast::Node) -> ParseResult<::ast::Node>
+	{
+		loop
+		{
+			let span = rv.span.clone();
+			let kind = match try!(self.lex.get_token())
 				{
-					args.push( try!(self.parse_expr()) );
-					if peek_token!(self.lex, Token::ParenClose) {
-						break;
+				Token::DerefMember => ::ast::NodeKind::DerefMember(box rv, syntax_assert!(self.lex => Token::Ident(i) @ i)),
+				Token::Period      => ::ast::NodeKind::Member(     box rv, syntax_assert!(self.lex => Token::Ident(i) @ i)),
+				Token::SquareOpen => {
+					let idx = box try!(self.parse_expr_list());
+					syntax_assert!(self.lex => Token::SquareClose);
+					::ast::NodeKind::Index(box rv, idx)
+					},
+				Token::ParenOpen => {
+					let mut args = Vec::new();
+					if ! peek_token!(self.lex, Token::ParenClose)
+					{
+						loop
+						{
+							args.push( try!(self.parse_expr()) );
+							if peek_token!(self.lex, Token::ParenClose) {
+								break;
+							}
+							syntax_assert!(self.lex => Token::Comma);
+						}
 					}
-					syntax_assert!(self.lex => Token::Comma);
-				}
-			}
-			::ast::Node::FcnCall(box rv, args)
-			},
-	}}
+					::ast::NodeKind::FcnCall(box rv, args)
+					},
+				Token::DoublePlus  => ::ast::NodeKind::UniOp(::ast::UniOp::PostInc, box rv),
+				Token::DoubleMinus => ::ast::NodeKind::UniOp(::ast::UniOp::PostDec, box rv),
+				t @ _ => {
+					self.lex.put_back(t);
+					break;
+					},
+				};
+			rv = ::ast::Node::new(span, kind);
+		}
+		Ok(rv)
+	}
 	
 	/// Expression - Parens
 	fn parse_expr_p(&mut self) -> ParseResult<::ast::Node>
 	{
-		Ok(match try!(self.lex.get_token())
+		if let Some(node) = self.parse_macro_use()? {
+			return Ok(node);
+		}
+		let tok = try!(self.lex.get_token());
+		let span = self.lex.span().clone();
+		Ok(match tok
 		{
-		// - Either a cast, or a grouped expression
+		// - Either a cast, a compound literal, a statement expression, or a grouped expression
+		// NOTE: A cast's operand is a cast-expression, so it takes any postfix operators before this returns
 		Token::ParenOpen => match try!(self.get_base_type_opt())
 			{
 			Some(basetype) => {
@@ -207,10 +231,20 @@ impl<'ast> super::ParseState<'ast>
 					syntax_error!("Unexpected identifier in cast");
 				}
 				syntax_assert!(self.lex => Token::ParenClose);
-				::ast::Node::Cast(fulltype, box try!(self.parse_expr_9()))
+				if peek_token!(self.lex, Token::BraceOpen) {
+					::ast::Node::new(span, ::ast::NodeKind::CompoundLiteral(fulltype, box self.parse_composite_lit()?))
+				}
+				else {
+					::ast::Node::new(span, ::ast::NodeKind::Cast(fulltype, box try!(self.parse_expr_unary())))
+				}
+				},
+			None if peek_token!(self.lex, Token::BraceOpen) => {
+				let block = self.parse_block()?;
+				syntax_assert!(self.lex => Token::ParenClose);
+				::ast::Node::new(span, ::ast::NodeKind::StmtExpr(block))
 				},
 			None => {
-				let rv = try!(self.parse_expr());
+				let rv = try!(self.parse_expr_list());
 				syntax_assert!(self.lex => Token::ParenClose);
 				rv
 				},
@@ -221,49 +255,197 @@ impl<'ast> super::ParseState<'ast>
 			}
 		})
 	}
+	/// Round-trip mode: Parse an unexpanded macro invocation as a single operand
+	///
+	/// If the expansion isn't a complete operand, it's instead parsed in place. A syntax error before the end of the
+	/// expansion is returned.
+	fn parse_macro_use(&mut self) -> ParseResult<Option<::ast::Node>>
+	{
+		let m = match self.lex.take_macro()?
+			{
+			Some(m) => m,
+			None => return Ok(None),
+			};
+		let saved = self.lex.isolate(&m);
+		// Definitions within the expansion (e.g. a struct in a compound literal) are reverted if it's parsed again
+		let cp = self.ast.checkpoint();
+		let rv = self.parse_expr();
+		let is_invalid = rv.is_err() && !self.lex.isolation_exhausted();
+		let is_complete = self.lex.end_isolation(saved, is_invalid);
+		match rv
+		{
+		Ok(e) if is_complete && is_operand(&e, &m.output) => {
+			debug!("Macro use {:?} = {:?}", m.input, e);
+			self.ast.commit(cp);
+			Ok(Some( ::ast::Node::new(m.span, ::ast::NodeKind::Macro { input: m.input, expansion: box e }) ))
+			},
+		Err(e) if is_invalid => {
+			self.ast.commit(cp);
+			Err(e)
+			},
+		_ => {
+			self.ast.rollback(cp);
+			self.lex.push_tokens(m.output, &m.span);
+			Ok(None)
+			},
+		}
+	}
 	/// Expression - Leaf nodes
 	fn parse_expr_z(&mut self) -> ParseResult<::ast::Node>
 	{
-		Ok(match try!(self.lex.get_token())
+		let tok = try!(self.lex.get_token());
+		let span = self.lex.span().clone();
+		let kind = match tok
 		{
-		Token::Ident(id) => ::ast::Node::Identifier(id),
+		Token::Ident(id) => ::ast::NodeKind::Identifier(id),
 		Token::String(s) => {
 			let mut val = s;
 			loop
 			{
 				match try!(self.lex.get_token()) {
-				Token::String(s) => { val.push_str(&s); },
+				Token::String(s) => {
+					if !val.append(s) {
+						syntax_error!("Unsupported concatenation of string literals with different prefixes");
+					}
+					},
 				t @ _ => { self.lex.put_back(t); break; }
 				}
 			}
-			::ast::Node::String(val)
+			::ast::NodeKind::String(val)
 			},
-		Token::Integer(v,_,_) => ::ast::Node::Integer(v),
-		Token::Character(v) => ::ast::Node::Integer(v),
-		Token::Float(v,_,_) => ::ast::Node::Float(v),
-		Token::Rword_sizeof => {
-			let expect_paren = peek_token!(self.lex, Token::ParenOpen);
-			let rv = match try!(self.get_base_type_opt())
+		Token::Integer(v,_,_) => ::ast::NodeKind::Integer(v),
+		Token::Character(c) => {
+			let v = c.value(self.char_is_signed);
+			if v < 0 {
+				// A negative value (e.g. `'\xff'` with a signed `char`) is stored as the negation of its magnitude
+				::ast::NodeKind::UniOp(::ast::UniOp::Neg, box ::ast::Node::new(span.clone(), ::ast::NodeKind::Integer(v.wrapping_neg() as u64)))
+			}
+			else {
+				::ast::NodeKind::Integer(v as u64)
+			}
+			},
+		Token::Float(v,cls,text) => ::ast::NodeKind::Float(v, cls, text),
+		// `_Generic ( assignment-expression , generic-assoc-list )`
+		Token::Rword_Generic => {
+			syntax_assert!(self.lex => Token::ParenOpen);
+			let ctrl = self.parse_expr()?;
+			let mut assocs = Vec::new();
+			let mut has_default = false;
+			while peek_token!(self.lex, Token::Comma)
+			{
+				let ty = if peek_token!(self.lex, Token::Rword_default) {
+						if has_default {
+							syntax_error!("Duplicate default association in _Generic");
+						}
+						has_default = true;
+						None
+					}
+					else {
+						match self.get_base_type_opt()?
+						{
+						Some(t) => {
+							let (ty, name) = self.get_full_type(t)?;
+							if ! name.is_empty() {
+								syntax_error!("Unexpected name in _Generic association");
+							}
+							Some(ty)
+							},
+						None => syntax_error!("Expected a type name or `default` in _Generic association"),
+						}
+					};
+				syntax_assert!(self.lex => Token::Colon);
+				assocs.push( (ty, self.parse_expr()?) );
+			}
+			syntax_assert!(self.lex => Token::ParenClose);
+			if assocs.is_empty() {
+				syntax_error!("_Generic requires at least one association");
+			}
+			::ast::NodeKind::Generic(box ctrl, assocs)
+			},
+		t @ _ => {
+			let msg = format!("Unexpected {:?}, expected value", t);
+			self.lex.put_back(t);
+			syntax_error!(msg)
+			},
+		};
+		Ok( ::ast::Node::new(span, kind) )
+	}
+}
+
+/// Binary operators, with their precedence (C11 6.5.5 to 6.5.14)
+fn binary_operator(tok: &Token) -> Option<(::ast::BinOp, ::ast::NodePrecedence)>
+{
+	use ast::{BinOp,NodePrecedence};
+	Some(match *tok
+	{
+	Token::Star    => (BinOp::Mul, NodePrecedence::MulDivMod),
+	Token::Slash   => (BinOp::Div, NodePrecedence::MulDivMod),
+	Token::Percent => (BinOp::Mod, NodePrecedence::MulDivMod),
+
+	Token::Plus  => (BinOp::Add, NodePrecedence::AddSub),
+	Token::Minus => (BinOp::Sub, NodePrecedence::AddSub),
+
+	Token::ShiftLeft  => (BinOp::ShiftLeft,  NodePrecedence::BitShift),
+	Token::ShiftRight => (BinOp::ShiftRight, NodePrecedence::BitShift),
+
+	Token::Lt  => (BinOp::CmpLt,  NodePrecedence::Relational),
+	Token::Gt  => (BinOp::CmpGt,  NodePrecedence::Relational),
+	Token::LtE => (BinOp::CmpLtE, NodePrecedence::Relational),
+	Token::GtE => (BinOp::CmpGtE, NodePrecedence::Relational),
+
+	Token::Equality  => (BinOp::CmpEqu,  NodePrecedence::Equality),
+	Token::NotEquals => (BinOp::CmpNEqu, NodePrecedence::Equality),
+
+	Token::Ampersand => (BinOp::BitAnd, NodePrecedence::BitAnd),
+	Token::Caret     => (BinOp::BitXor, NodePrecedence::BitXor),
+	Token::Pipe      => (BinOp::BitOr,  NodePrecedence::BitOr),
+
+	Token::DoubleAmpersand => (BinOp::LogicAnd, NodePrecedence::LogicAnd),
+	Token::DoublePipe      => (BinOp::LogicOr,  NodePrecedence::LogicOr),
+
+	_ => return None,
+	})
+}
+
+/// Returns true if a macro's expansion binds as a single operand (so the invocation can stand in for it)
+fn is_operand(node: &::ast::Node, tokens: &[Token]) -> bool
+{
+	use ast::NodeKind;
+	match node.kind
+	{
+	// NOTE: Strings aren't included, as they can be concatenated with following literals
+	NodeKind::Identifier(_)
+	| NodeKind::Integer(_)
+	| NodeKind::Float(..)
+	| NodeKind::FcnCall(..)
+	| NodeKind::Index(..)
+	| NodeKind::Member(..)
+	| NodeKind::DerefMember(..)
+	| NodeKind::UniOp(::ast::UniOp::PostInc, _)
+	| NodeKind::UniOp(::ast::UniOp::PostDec, _)
+		=> true,
+	// Anything else must be wrapped in a single pair of parens
+	_ => match tokens.first()
+		{
+		Some(&Token::ParenOpen) => {
+			let mut depth = 0;
+			for (i, t) in tokens.iter().enumerate()
+			{
+				match *t
 				{
-				Some(t) => {
-					let (tr, name) = try!(self.get_full_type(t));
-					if ! name.is_empty() {
-						syntax_error!("Unexpected name in sizeof");
+				Token::ParenOpen => depth += 1,
+				Token::ParenClose => {
+					depth -= 1;
+					if depth == 0 {
+						return i == tokens.len() - 1;
 					}
-					::ast::Node::SizeofType(tr)
-					},
-				None => {
-					let val = if expect_paren { box try!(self.parse_expr_0()) } else { box try!(self.parse_expr_p()) };
-					::ast::Node::SizeofExpr(val)
 					},
-				};
-			if expect_paren {
-				syntax_assert!(self.lex => Token::ParenClose);
+				_ => {},
+				}
 			}
-			debug!("{:?}, lex={}", rv, self.lex);
-			rv
+			false
 			},
-		t @ _ => syntax_error!("Unexpected {:?}, expected value", t),
-		})
+		_ => false,
+		},
 	}
 }
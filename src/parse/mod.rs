@@ -12,18 +12,31 @@ macro_rules! syntax_error
 	($msg:expr) => ({ return Err(::parse::Error::SyntaxError(format!("{}",$msg))) });
 	($fmt:expr, $($arg:tt)*) => ({ return Err(::parse::Error::SyntaxError(format!($fmt, $($arg)*))) });
 }
+// NOTE: On failure the offending token is put back, so error recovery can see it
 macro_rules! syntax_assert
 {
-	($lex:expr => Token::$exp:ident) => (syntax_assert!(try!($lex.get_token()), Token::$exp));
-	($lex:expr => Token::$exp:ident($($a:ident),+) @ $v:expr) => (syntax_assert!(try!($lex.get_token()), Token::$exp($($a),+) => $v));
-	($tok:expr, Token::$exp:ident) => (match $tok {
+	($lex:expr => Token::$exp:ident) => ({
+		let lex = &mut $lex;
+		match try!(lex.get_token()) {
 		Token::$exp => {},
-		tok @ _ => syntax_error!("Unexpected token {:?}, expected {:?}", tok, stringify!($exp)),
+		tok @ _ => {
+			let msg = format!("Unexpected token {:?}, expected {:?}", tok, stringify!($exp));
+			lex.put_back(tok);
+			syntax_error!(msg)
+			},
+		}
 		});
-	($tok:expr, Token::$exp:ident($($a:ident),+) => $v:expr) => (match $tok {
+	($lex:expr => Token::$exp:ident($($a:ident),+) @ $v:expr) => ({
+		let lex = &mut $lex;
+		match try!(lex.get_token()) {
 		Token::$exp($($a),+) => $v,
-		tok @ _ => syntax_error!("Unexpected token {:?}, expected {:?}", tok, stringify!($exp)),
-		})
+		tok @ _ => {
+			let msg = format!("Unexpected token {:?}, expected {:?}", tok, stringify!($exp));
+			lex.put_back(tok);
+			syntax_error!(msg)
+			},
+		}
+		});
 }
 macro_rules! peek_token_nc
 {
@@ -60,6 +73,7 @@ macro_rules! parse_todo
 mod parsing;
 mod expr;
 mod types;
+mod stream;
 
 #[derive(Debug)]
 pub enum Error
@@ -69,6 +83,8 @@ pub enum Error
 	IOError(::std::io::Error),
 	BadCharacter(char),
 	SyntaxError(String),
+	/// Error from a pre-processor directive
+	Preprocessor(::preproc::Span, ::preproc::DirectiveError),
 }
 impl From<::preproc::Error> for Error
 {
@@ -79,8 +95,53 @@ impl From<::preproc::Error> for Error
 		::preproc::Error::EOF => Error::EOF,
 		::preproc::Error::IoError(e) => Error::IOError(e),
 		::preproc::Error::UnexpectedEof => Error::SyntaxError(format!("Unexpected EOF in preprocessor")),
-		::preproc::Error::MalformedLiteral(v) => Error::SyntaxError(format!("Malformed literal: {}", v)),
 		::preproc::Error::BadCharacter(c) => Error::BadCharacter(c),
+		::preproc::Error::Directive(span, e) => Error::Preprocessor(span, e),
+		}
+	}
+}
+
+impl Error
+{
+	/// Returns true if parsing cannot continue after this error
+	pub fn is_fatal(&self) -> bool
+	{
+		match *self
+		{
+		Error::EOF => true,
+		Error::IOError(_) => true,
+		Error::Preprocessor(_, ::preproc::DirectiveError::IncludeNotFound(_)) => true,
+		Error::Preprocessor(_, ::preproc::DirectiveError::UnterminatedConditional) => true,
+		Error::Preprocessor(_, ::preproc::DirectiveError::ErrorDirective(_)) => true,
+		_ => false,
+		}
+	}
+	/// Identifier used for machine-readable diagnostics
+	pub fn code(&self) -> &'static str
+	{
+		match *self
+		{
+		Error::Todo(_) => "unimplemented",
+		Error::EOF => "unexpected-eof",
+		Error::IOError(_) => "io-error",
+		Error::BadCharacter(_) => "bad-character",
+		Error::SyntaxError(_) => "syntax-error",
+		Error::Preprocessor(_, ref e) => e.code(),
+		}
+	}
+}
+impl ::std::fmt::Display for Error
+{
+	fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result
+	{
+		match *self
+		{
+		Error::Todo(s) => write!(f, "Unimplemented: {}", s),
+		Error::EOF => write!(f, "Unexpected end of file"),
+		Error::IOError(ref e) => write!(f, "{}", e),
+		Error::BadCharacter(c) => write!(f, "Unexpected character {:?}", c),
+		Error::SyntaxError(ref s) => write!(f, "{}", s),
+		Error::Preprocessor(_, ref e) => write!(f, "{}", e),
 		}
 	}
 }
@@ -91,42 +152,72 @@ pub type ParseResult<T> = Result<T,Error>;
 struct ParseState<'ast>
 {
 	ast: &'ast mut ::ast::Program,
-	lex: ::preproc::Preproc,
+	lex: stream::TokenStream,
+	/// Round-trip mode: `#include`s are kept, so the contents of included files aren't listed in the AST
+	round_trip: bool,
+	/// Last line of the previous top-level item in the main file (for keeping blank lines in round-trip mode)
+	last_line: usize,
+	/// Diagnostics raised so far (including errors that have been recovered from)
+	diagnostics: Vec<::diagnostics::Diagnostic>,
+	/// Whether plain `char` is signed on the target (for the values of character constants)
+	char_is_signed: bool,
+}
+
+impl<'ast> ParseState<'ast>
+{
+	/// Record an error (located at the most recent token) so parsing can continue
+	fn record_error(&mut self, e: Error)
+	{
+		debug!("Parse error {:?} at {}", e, self.lex);
+		// Keep any warnings raised before this error in order
+		self.diagnostics.extend( self.lex.take_diagnostics() );
+		let span = match e
+			{
+			Error::Preprocessor(ref s, _) => s.clone(),
+			_ => self.lex.span().clone(),
+			};
+		let d = self.lex.diagnostic(::diagnostics::Severity::Error, e.code(), &span, e.to_string());
+		self.diagnostics.push(d);
+	}
 }
 
 /// Parse a file into the passed AST program representation
-pub fn parse(ast: &mut ::ast::Program, filename: &::std::path::Path, include_paths: Vec<::std::path::PathBuf>, defines: &[String]) -> ParseResult<()>
+///
+/// Returns all diagnostics raised. Parsing continues past most errors, so if any of the diagnostics are errors then
+/// `ast` contains everything that could be parsed. The files read are stored in `deps`.
+///
+/// If the pre-processor is set to propagate `#include`s, the parser runs in round-trip mode, keeping comments,
+/// directives, and macro invocations (see `Options::wrap_define_expansion`) in the AST.
+pub fn parse(ast: &mut ::ast::Program, filename: &::std::path::Path, pp_opts: ::preproc::Options, deps: &mut Vec<::preproc::deps::Dependency>) -> Vec<::diagnostics::Diagnostic>
 {
-	let pp_opts = {
-		let mut pp_opts = super::preproc::Options::default();
-		pp_opts.include_paths = include_paths;
-		pp_opts
+	let round_trip = pp_opts.include_handling != ::preproc::Handling::InternalOnly;
+	let char_is_signed = pp_opts.target.char_is_signed();
+	let lex = match ::preproc::Preproc::new( Some(filename), pp_opts )
+		{
+		Ok(v) => v,
+		Err(e) => {
+			let e = Error::from(e);
+			return vec![ ::diagnostics::Diagnostic::new(::diagnostics::Severity::Error, e.code(), format!("{}: {}", filename.display(), e)) ];
+			},
 		};
-
 	let mut self_ = ParseState {
 		ast: ast,
-		lex: ::preproc::Preproc::new( Some(filename), pp_opts )?,
+		lex: stream::TokenStream::new(lex),
+		round_trip: round_trip,
+		last_line: 0,
+		diagnostics: Vec::new(),
+		char_is_signed: char_is_signed,
 		};
 	
-	// Process command-line `-D` arguments
-	for d in defines
-	{
-		self_.lex.parse_define_str(d)?;
-	}
-	
-	match self_.parseroot()
+	if let Err(e) = self_.parseroot()
 	{
-	Ok(o) => Ok(o),
-	Err(e) => {
-		error!("Parse error {:?} at {}", e, self_.lex);
-		match e
-		{
-		::parse::Error::SyntaxError(s) => Err( ::parse::Error::SyntaxError(format!("{} {}", self_.lex, s)) ),
-		_ => Err( e ),
-		}
-		}
+		self_.record_error(e);
 	}
+	let warnings = self_.lex.take_diagnostics();
+	self_.diagnostics.extend(warnings);
+	*deps = self_.lex.dependencies();
+
+	self_.diagnostics
 }
 
 // vim: ft=rust
-
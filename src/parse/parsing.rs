@@ -3,6 +3,7 @@
  */
 use parse::Token;
 use parse::ParseResult;
+use parse::stream::Extra;
 
 impl<'ast> super::ParseState<'ast>
 {
@@ -10,24 +11,21 @@ impl<'ast> super::ParseState<'ast>
 	{
 		loop
 		{
-			let tok = try!(self.lex.get_token());
-			match tok
+			match self.parse_root_item()
 			{
-			Token::EOF => {
-				break;
-				},
-			Token::Rword_typedef => {
-				let basetype = try!(self.get_base_type());
-				debug!("do_typedef: basetype={:?}", basetype);
-				let (typeid, name) = try!(self.get_full_type(basetype));
-				self.ast.set_typedef(name, typeid);
-				syntax_assert!( try!(self.lex.get_token()), Token::Semicolon );
-				},
-			_ => {
-				self.lex.put_back(tok);
-				try!( self.do_definition() )
+			Ok(true) => {},
+			Ok(false) => break,
+			Err(e) => {
+				if e.is_fatal() {
+					return Err(e);
+				}
+				self.record_error(e);
+				self.skip_declaration()?;
 				},
 			}
+			if self.lex.is_main_file(self.lex.span()) {
+				self.last_line = self.lex.span().line;
+			}
 			debug!("--- {}", self.lex);
 		}
 		debug!("=== {}", self.lex);
@@ -35,13 +33,269 @@ impl<'ast> super::ParseState<'ast>
 		Ok( () )
 	}
 
+	/// Parse a single top-level item, returns `false` at EOF
+	fn parse_root_item(&mut self) -> ParseResult<bool>
+	{
+		let m = if self.round_trip { self.lex.take_macro()? } else { None };
+		let tok = match m
+			{
+			Some(m) => {
+				self.start_root_item(&m.span);
+				if self.lex.is_main_file(&m.span) && self.parse_root_macro(&m) {
+					self.ast.add_macro_use(m.input);
+					return Ok(true);
+				}
+				self.lex.push_tokens(m.output, &m.span);
+				try!(self.lex.get_token())
+				},
+			None => {
+				let tok = try!(self.lex.get_token());
+				let span = self.lex.span().clone();
+				self.start_root_item(&span);
+				tok
+				},
+			};
+		self.parse_root_decl(tok)
+	}
+	/// Round-trip mode: Parse a macro invocation's expansion as a sequence of complete top-level items
+	///
+	/// Returns false if it isn't (only expansions ending with a `;` or `}` are tried), in which case anything it
+	/// defined is reverted and the expansion is parsed in place. A syntax error before the end of the expansion is
+	/// recorded, and the rest of the expansion skipped.
+	fn parse_root_macro(&mut self, m: &::parse::stream::MacroUse) -> bool
+	{
+		match m.output.iter().rev().find(|t| match **t { Token::Whitespace => false, _ => true })
+		{
+		Some(&Token::Semicolon) | Some(&Token::BraceClose) => {},
+		_ => return false,
+		}
+		let saved = self.lex.isolate(m);
+		self.ast.set_listing(false);
+		let cp = self.ast.checkpoint();
+		let rv = loop
+			{
+			match self.lex.get_token()
+			{
+			Ok(Token::EOF) => break Ok( () ),
+			Ok(tok) => match self.parse_root_decl(tok)
+				{
+				Ok(_) => {},
+				Err(e) => break Err(e),
+				},
+			Err(e) => break Err(::parse::Error::from(e)),
+			}
+			};
+		let rv = match rv
+			{
+			Ok(_) => true,
+			Err(_) if self.lex.isolation_exhausted() => false,
+			Err(e) => {
+				self.record_error(e);
+				true
+				},
+			};
+		self.lex.end_isolation(saved, false);
+		self.ast.set_listing(true);
+		if rv {
+			self.ast.commit(cp);
+		}
+		else {
+			self.ast.rollback(cp);
+		}
+		rv
+	}
+	/// Round-trip mode: Add the comments and directives before an item starting at `span`, and list the item if
+	/// it's in the main file
+	fn start_root_item(&mut self, span: &::preproc::Span)
+	{
+		self.add_root_extras(span);
+		if self.round_trip {
+			let is_main = self.lex.is_main_file(span);
+			self.ast.set_listing(is_main);
+		}
+	}
+	/// Parse a top-level item starting with `tok`, returns `false` at EOF
+	fn parse_root_decl(&mut self, tok: Token) -> ParseResult<bool>
+	{
+		match tok
+		{
+		Token::EOF => {
+			return Ok(false);
+			},
+		Token::Rword_typedef => {
+			let basetype = try!(self.get_base_type());
+			debug!("do_typedef: basetype={:?}", basetype);
+			let (typeid, name) = try!(self.get_full_type(basetype));
+			self.ast.set_typedef(name, typeid);
+			syntax_assert!(self.lex => Token::Semicolon);
+			},
+		_ => {
+			self.lex.put_back(tok);
+			try!( self.do_definition() )
+			},
+		}
+		Ok(true)
+	}
+
+	/// Round-trip mode: Add the comments and directives before an item starting at `span` to the program
+	fn add_root_extras(&mut self, span: &::preproc::Span)
+	{
+		for (e, e_span) in self.lex.take_extras()
+		{
+			self.add_blank_line(&e_span);
+			match e
+			{
+			Extra::Comment(text) => {
+				self.last_line = e_span.line + text.matches('\n').count();
+				self.ast.add_comment(text);
+				},
+			Extra::Directive(d) => {
+				// Conditionals include the text of skipped groups (and end with a newline)
+				self.last_line = match d
+					{
+					::preproc::token::Preprocessor::Conditional(ref text) => e_span.line + text.matches('\n').count() - 1,
+					_ => e_span.line,
+					};
+				self.ast.add_directive(d);
+				},
+			}
+		}
+		if self.lex.is_main_file(span) {
+			self.add_blank_line(span);
+		}
+	}
+	/// Round-trip mode: Keep a blank line before an item starting at `span`
+	fn add_blank_line(&mut self, span: &::preproc::Span)
+	{
+		if self.round_trip && span.line > self.last_line + 1 {
+			self.ast.add_blank_line();
+		}
+	}
+	/// Round-trip mode: Add the comments and directives read so far to a block
+	fn add_block_extras(&mut self, block: &mut ::ast::Block)
+	{
+		for (e, span) in self.lex.take_extras()
+		{
+			let kind = match e
+				{
+				Extra::Comment(text) => ::ast::StatementKind::Comment(text),
+				Extra::Directive(d) => ::ast::StatementKind::Directive(d),
+				};
+			block.push( ::ast::Statement::new(span, kind) );
+		}
+	}
+
+	/// Error recovery: Get a token, recording (non-fatal) errors from the pre-processor
+	fn recovery_token(&mut self) -> ParseResult<Token>
+	{
+		loop
+		{
+			match self.lex.get_token()
+			{
+			Ok(t) => return Ok(t),
+			Err(e) => {
+				let e = ::parse::Error::from(e);
+				if e.is_fatal() {
+					return Err(e);
+				}
+				self.record_error(e);
+				},
+			}
+		}
+	}
+
+	/// Error recovery: Skip to the end of the current top-level declaration
+	///
+	/// Stops after a `;` or the closing brace of a function body
+	fn skip_declaration(&mut self) -> ParseResult<()>
+	{
+		let mut depth = 0;
+		loop
+		{
+			match self.recovery_token()?
+			{
+			Token::EOF => {
+				self.lex.put_back(Token::EOF);
+				return Ok( () );
+				},
+			Token::Semicolon if depth == 0 => return Ok( () ),
+			Token::BraceOpen => depth += 1,
+			// Unbalanced close (e.g. the error was within a struct body)
+			Token::BraceClose if depth == 0 => {},
+			Token::BraceClose => {
+				depth -= 1;
+				if depth == 0 {
+					return Ok( () );
+				}
+				},
+			_ => {},
+			}
+		}
+	}
+	/// Error recovery: Skip to the end of the current statement
+	///
+	/// Stops after a `;` or a balanced `{ ... }`, or before the `}` closing the current block. Returns `false` if EOF
+	/// was reached.
+	fn skip_statement(&mut self) -> ParseResult<bool>
+	{
+		let mut depth = 0;
+		loop
+		{
+			match self.recovery_token()?
+			{
+			Token::EOF => {
+				self.lex.put_back(Token::EOF);
+				return Ok(false);
+				},
+			Token::Semicolon if depth == 0 => return Ok(true),
+			Token::BraceOpen => depth += 1,
+			Token::BraceClose if depth == 0 => {
+				self.lex.put_back(Token::BraceClose);
+				return Ok(true);
+				},
+			Token::BraceClose => {
+				depth -= 1;
+				if depth == 0 {
+					return Ok(true);
+				}
+				},
+			_ => {},
+			}
+		}
+	}
+	/// Error recovery: Skip to the end of the current braced initialiser
+	///
+	/// Stops after the closing `}`, or before a `;` (unterminated initialiser). Returns `false` if EOF was reached.
+	fn skip_initialiser(&mut self) -> ParseResult<bool>
+	{
+		let mut depth = 0;
+		loop
+		{
+			match self.recovery_token()?
+			{
+			Token::EOF => {
+				self.lex.put_back(Token::EOF);
+				return Ok(false);
+				},
+			Token::Semicolon if depth == 0 => {
+				self.lex.put_back(Token::Semicolon);
+				return Ok(true);
+				},
+			Token::BraceOpen => depth += 1,
+			Token::BraceClose if depth == 0 => return Ok(true),
+			Token::BraceClose => depth -= 1,
+			_ => {},
+			}
+		}
+	}
+
 	/// Parse the body of a GCC `__attribute__(...)`
 	pub(super) fn parse_gcc_attributes(&mut self, mut cb: impl FnMut(&mut Self, String, Vec<Token>)->ParseResult<()>) -> ParseResult<()>
 	{
-		syntax_assert!( self.lex.get_token()?, Token::ParenOpen );
+		syntax_assert!(self.lex => Token::ParenOpen);
 		let is_double_wrapped = peek_token!(self.lex, Token::ParenOpen);
 		loop {
-			let name = syntax_assert!( self.lex.get_token()?, Token::Ident(n) => n );
+			let name = syntax_assert!(self.lex => Token::Ident(n) @ n);
 			let opts = if peek_token!(self.lex, Token::ParenOpen) {
 					let mut toks = vec![];
 					let mut depth = 0;
@@ -67,9 +321,9 @@ impl<'ast> super::ParseState<'ast>
 			}
 		}
 		if is_double_wrapped {
-			syntax_assert!( self.lex.get_token()?, Token::ParenClose );
+			syntax_assert!(self.lex => Token::ParenClose);
 		}
-		syntax_assert!( self.lex.get_token()?, Token::ParenClose );
+		syntax_assert!(self.lex => Token::ParenClose);
 		Ok( () )
 	}
 
@@ -98,7 +352,7 @@ impl<'ast> super::ParseState<'ast>
 		}
 		else
 		{
-			syntax_assert!( try!(self.lex.get_token()), Token::Semicolon );
+			syntax_assert!(self.lex => Token::Semicolon);
 			return Ok( () );
 		}
 	}
@@ -120,7 +374,7 @@ impl<'ast> super::ParseState<'ast>
 	fn parse_opt_block(&mut self) -> ParseResult<::ast::Block>
 	{
 		let exprs = try!(self.parse_block_line());
-		if let ::ast::Statement::Block(b) = exprs {
+		if let ::ast::StatementKind::Block(b) = exprs.kind {
 			Ok( b )
 		}
 		else {
@@ -128,13 +382,18 @@ impl<'ast> super::ParseState<'ast>
 		}
 	}
 	
-	fn parse_block(&mut self) -> ParseResult<::ast::Block>
+	pub(super) fn parse_block(&mut self) -> ParseResult<::ast::Block>
 	{
 		// Opening brace has been eaten
 		let mut statements = Vec::new();
 		
-		while !peek_token!(self.lex, Token::BraceClose)
+		loop
 		{
+			let is_end = peek_token!(self.lex, Token::BraceClose);
+			self.add_block_extras(&mut statements);
+			if is_end {
+				break;
+			}
 			let s = self.parse_block_line()?;
 			debug!("> {:?}", s);
 			statements.push( s );
@@ -198,46 +457,66 @@ impl<'ast> super::ParseState<'ast>
 	}
 	
 	/// Parse a single line in a block
+	///
+	/// On error, the error is recorded and the rest of the statement is skipped
 	fn parse_block_line(&mut self) -> ParseResult<::ast::Statement>
+	{
+		match self.parse_block_line_inner()
+		{
+		Ok(v) => Ok(v),
+		Err(e) => {
+			if e.is_fatal() {
+				return Err(e);
+			}
+			self.record_error(e);
+			if ! self.skip_statement()? {
+				return Err(::parse::Error::EOF);
+			}
+			Ok( ::ast::Statement::new(self.lex.span().clone(), ::ast::StatementKind::Empty) )
+			},
+		}
+	}
+	fn parse_block_line_inner(&mut self) -> ParseResult<::ast::Statement>
 	{
 		debug!(">>> {}", self.lex);
+		let span = self.lex.peek_span()?;
 		// Attempt to get a type, returns None if no type was present
-		Ok(match try!(self.try_parse_local_var())
+		let kind = match try!(self.try_parse_local_var())
 		{
 		Some(n) => {
-			syntax_assert!(try!(self.lex.get_token()), Token::Semicolon);
-			::ast::Statement::VarDef(n)
+			syntax_assert!(self.lex => Token::Semicolon);
+			::ast::StatementKind::VarDef(n)
 			},
 		None => match try!(self.lex.get_token())
 			{
-			Token::Semicolon => ::ast::Statement::Empty,
-			Token::BraceOpen => ::ast::Statement::Block( try!(self.parse_block()) ),
+			Token::Semicolon => ::ast::StatementKind::Empty,
+			Token::BraceOpen => ::ast::StatementKind::Block( try!(self.parse_block()) ),
 			Token::Rword_return => if peek_token!(self.lex, Token::Semicolon) {
-					::ast::Statement::Return( None )
+					::ast::StatementKind::Return( None )
 				} else {
-					let rv = ::ast::Statement::Return( Some(self.parse_expr_list()?) );
-					syntax_assert!(try!(self.lex.get_token()), Token::Semicolon);
+					let rv = ::ast::StatementKind::Return( Some(self.parse_expr_list()?) );
+					syntax_assert!(self.lex => Token::Semicolon);
 					rv
 				},
 			Token::Rword_break => {
-				syntax_assert!(try!(self.lex.get_token()), Token::Semicolon);
-				::ast::Statement::Break
+				syntax_assert!(self.lex => Token::Semicolon);
+				::ast::StatementKind::Break
 				},
 			Token::Rword_continue => {
-				syntax_assert!(try!(self.lex.get_token()), Token::Semicolon);
-				::ast::Statement::Continue
+				syntax_assert!(self.lex => Token::Semicolon);
+				::ast::StatementKind::Continue
 				},
 			Token::Rword_goto => {
 				let dest = syntax_assert!(self.lex => Token::Ident(s) @ s);
 				syntax_assert!(self.lex => Token::Semicolon);
-				::ast::Statement::Goto(dest)
+				::ast::StatementKind::Goto(dest)
 				},
 			Token::Rword_while => {
 				syntax_assert!(self.lex => Token::ParenOpen);
 				let cnd = self.parse_expr_list()?;
 				syntax_assert!(self.lex => Token::ParenClose);
 				let code = self.parse_opt_block()?;
-				::ast::Statement::WhileLoop {
+				::ast::StatementKind::WhileLoop {
 					cond: ::ast::ExprOrDef::Expr(cnd),
 					body: code,
 					}
@@ -249,7 +528,7 @@ impl<'ast> super::ParseState<'ast>
 				let cnd = self.parse_expr_list()?;
 				syntax_assert!(self.lex => Token::ParenClose);
 				syntax_assert!(self.lex => Token::Semicolon);
-				::ast::Statement::DoWhileLoop {
+				::ast::StatementKind::DoWhileLoop {
 					body: code,
 					cond: cnd,
 					}
@@ -266,7 +545,7 @@ impl<'ast> super::ParseState<'ast>
 						None
 					};
 				debug!("{}IF: {:?} {:?} {:?}", self.lex, cnd, tcode, fcode);
-				::ast::Statement::IfStatement {
+				::ast::StatementKind::IfStatement {
 					cond: ::ast::ExprOrDef::Expr(cnd),
 					true_arm: tcode,
 					else_arm: fcode,
@@ -277,19 +556,19 @@ impl<'ast> super::ParseState<'ast>
 			t @ Token::Ident(_) => {
 				self.lex.put_back(t);
 				let rv = try!(self.parse_expr_list());
-				if let ::ast::Node::Identifier(i) = rv
+				if let ::ast::NodeKind::Identifier(ref i) = rv.kind
 				{
 					if peek_token!(self.lex, Token::Colon) {
-						::ast::Statement::Label(i)
+						::ast::StatementKind::Label(i.clone())
 					}
 					else {
-						syntax_assert!(try!(self.lex.get_token()), Token::Semicolon);
-						::ast::Statement::Expr( ::ast::Node::Identifier(i) )
+						syntax_assert!(self.lex => Token::Semicolon);
+						::ast::StatementKind::Expr(rv)
 					}
 				}
 				else {
-					syntax_assert!(try!(self.lex.get_token()), Token::Semicolon);
-					::ast::Statement::Expr(rv)
+					syntax_assert!(self.lex => Token::Semicolon);
+					::ast::StatementKind::Expr(rv)
 				}
 				},
 			
@@ -297,16 +576,17 @@ impl<'ast> super::ParseState<'ast>
 			t @ _ => {
 				self.lex.put_back(t);
 				let rv = try!(self.parse_expr_list());
-				syntax_assert!(try!(self.lex.get_token()), Token::Semicolon);
-				::ast::Statement::Expr(rv)
+				syntax_assert!(self.lex => Token::Semicolon);
+				::ast::StatementKind::Expr(rv)
 				}
 			}, 
-		})
+		};
+		Ok( ::ast::Statement::new(span, kind) )
 	}
 	
 	/// Parse a for loop
 	// ('for' has been eaten)
-	fn parse_for_loop(&mut self) -> ParseResult<::ast::Statement>
+	fn parse_for_loop(&mut self) -> ParseResult<::ast::StatementKind>
 	{
 		syntax_assert!(self.lex => Token::ParenOpen);
 		debug!("parse_for_loop");
@@ -336,7 +616,7 @@ impl<'ast> super::ParseState<'ast>
 		syntax_assert!(self.lex => Token::ParenClose);
 		debug!("parse_for_loop: inc = {:?}", inc);
 		let code = try!(self.parse_opt_block());
-		Ok( ::ast::Statement::ForLoop {
+		Ok( ::ast::StatementKind::ForLoop {
 			init,
 			cond: cnd,
 			inc,
@@ -344,18 +624,21 @@ impl<'ast> super::ParseState<'ast>
 			} )
 	}
 	
-	fn parse_switch_statement(&mut self) -> ParseResult<::ast::Statement>
+	fn parse_switch_statement(&mut self) -> ParseResult<::ast::StatementKind>
 	{
 		let cnd = self.parse_expr()?;
 		let mut code = Vec::new();
 		syntax_assert!(self.lex => Token::BraceOpen);
 		loop
 		{
-			match try!(self.lex.get_token())
+			let tok = try!(self.lex.get_token());
+			let span = self.lex.span().clone();
+			self.add_block_extras(&mut code);
+			match tok
 			{
 			Token::BraceClose => break,
 			Token::Rword_default => {
-				code.push( ::ast::Statement::CaseDefault );
+				code.push( ::ast::Statement::new(span, ::ast::StatementKind::CaseDefault) );
 				syntax_assert!(self.lex => Token::Colon);
 				},
 			Token::Rword_case => {
@@ -371,10 +654,10 @@ impl<'ast> super::ParseState<'ast>
 						Some(i) => i as u64,
 						None => syntax_error!("Case value is not literal"),
 						};
-					code.push( ::ast::Statement::CaseRange(first, last) );
+					code.push( ::ast::Statement::new(span, ::ast::StatementKind::CaseRange(first, last)) );
 				}
 				else {
-					code.push( ::ast::Statement::CaseSingle(first) );
+					code.push( ::ast::Statement::new(span, ::ast::StatementKind::CaseSingle(first)) );
 				}
 				syntax_assert!(self.lex => Token::Colon);
 				},
@@ -384,7 +667,7 @@ impl<'ast> super::ParseState<'ast>
 				}
 			}
 		}
-		Ok( ::ast::Statement::Switch(cnd, code) )
+		Ok( ::ast::StatementKind::Switch(cnd, code) )
 	}
 	
 	fn parse_variable_list(&mut self, basetype: ::types::TypeRef) -> ParseResult<()>
@@ -453,7 +736,27 @@ impl<'ast> super::ParseState<'ast>
 // ---
 impl<'ast> super::ParseState<'ast>
 {
-	fn parse_composite_lit(&mut self) -> ParseResult<::ast::Initialiser>
+	/// Parse a braced initialiser (opening brace has been eaten)
+	///
+	/// On error, the error is recorded and the rest of the initialiser is skipped
+	pub(super) fn parse_composite_lit(&mut self) -> ParseResult<::ast::Initialiser>
+	{
+		match self.parse_composite_lit_inner()
+		{
+		Ok(v) => Ok(v),
+		Err(e) => {
+			if e.is_fatal() {
+				return Err(e);
+			}
+			self.record_error(e);
+			if ! self.skip_initialiser()? {
+				return Err(::parse::Error::EOF);
+			}
+			Ok( ::ast::Initialiser::ListLiteral(vec![]) )
+			},
+		}
+	}
+	fn parse_composite_lit_inner(&mut self) -> ParseResult<::ast::Initialiser>
 	{
 		Ok(match try!(self.lex.get_token())
 		{
@@ -0,0 +1,265 @@
+/*!
+ * Token stream read by the parser
+ *
+ * Wraps the pre-processor, setting aside the tokens that aren't part of the C grammar (comments, and propagated
+ * directives) so they can be added to the AST, and holding propagated macro invocations until they're either parsed
+ * as a unit or expanded in place.
+ */
+use parse::Token;
+use preproc::Span;
+use preproc::token::Preprocessor;
+
+/// Source text outside of the C grammar (only from the main file)
+pub enum Extra
+{
+	/// Comment text (including the delimiters)
+	Comment(String),
+	Directive(Preprocessor),
+}
+
+/// A macro invocation that hasn't been expanded yet (see `Options::wrap_define_expansion`)
+#[derive(Clone)]
+pub struct MacroUse
+{
+	pub input: Vec<Token>,
+	pub output: Vec<Token>,
+	pub span: Span,
+}
+
+enum Item
+{
+	Token(Token, Span),
+	Macro(MacroUse),
+}
+
+pub struct TokenStream
+{
+	pp: ::preproc::Preproc,
+	/// Items read but not yet used (the next item is last)
+	pending: Vec<Item>,
+	/// The last token returned was the start of this macro's expansion (so `put_back` can restore the invocation)
+	just_expanded: Option<MacroUse>,
+	/// Location of the last token returned by `get_token`
+	last_span: Span,
+	/// Comments and directives read since the last `take_extras`
+	extras: Vec<(Extra, Span)>,
+	/// Reading a macro's expansion (see `isolate`)
+	isolated: bool,
+	/// The `EOF` after an isolated expansion has been returned
+	exhausted: bool,
+}
+/// State saved by `TokenStream::isolate`
+pub struct Isolation
+{
+	pending: Vec<Item>,
+	last_span: Span,
+}
+
+impl TokenStream
+{
+	pub fn new(pp: ::preproc::Preproc) -> TokenStream
+	{
+		TokenStream {
+			last_span: pp.span().clone(),
+			pp: pp,
+			pending: Vec::new(),
+			just_expanded: None,
+			extras: Vec::new(),
+			isolated: false,
+			exhausted: false,
+		}
+	}
+
+	/// Get the next token, expanding macro invocations
+	pub fn get_token(&mut self) -> ::preproc::Result<Token>
+	{
+		self.just_expanded = None;
+		loop
+		{
+			match self.get_item()?
+			{
+			Item::Token(tok, span) => {
+				if self.isolated && tok == Token::EOF {
+					self.exhausted = true;
+				}
+				self.last_span = span;
+				return Ok(tok);
+				},
+			Item::Macro(m) => {
+				if m.output.len() > 0 {
+					self.push_tokens(m.output.clone(), &m.span);
+					self.just_expanded = Some(m);
+				}
+				},
+			}
+		}
+	}
+	/// Return a token to the stream (can be called repeatedly, tokens are returned in reverse order)
+	pub fn put_back(&mut self, tok: Token)
+	{
+		match self.just_expanded.take()
+		{
+		Some(m) => {
+			// Nothing else has been read from the expansion, so restore the invocation
+			let n = m.output.len() - 1;
+			let len = self.pending.len();
+			self.pending.truncate(len - n);
+			self.pending.push(Item::Macro(m));
+			},
+		None => self.pending.push( Item::Token(tok, self.last_span.clone()) ),
+		}
+	}
+	/// Push tokens to be read next (all with the location `span`)
+	pub fn push_tokens(&mut self, tokens: Vec<Token>, span: &Span)
+	{
+		self.pending.extend( tokens.into_iter().rev().map(|t| Item::Token(t, span.clone())) );
+	}
+
+	/// If the next item is an unexpanded macro invocation, remove and return it
+	pub fn take_macro(&mut self) -> ::preproc::Result<Option<MacroUse>>
+	{
+		self.just_expanded = None;
+		match self.get_item()?
+		{
+		Item::Macro(m) => Ok(Some(m)),
+		i => {
+			self.pending.push(i);
+			Ok(None)
+			},
+		}
+	}
+	/// Start reading a macro's expansion in isolation (`EOF` is returned after the expansion)
+	pub fn isolate(&mut self, m: &MacroUse) -> Isolation
+	{
+		let rv = Isolation {
+			pending: ::std::mem::replace(&mut self.pending, Vec::new()),
+			last_span: self.last_span.clone(),
+			};
+		self.push_tokens(m.output.clone(), &m.span);
+		self.just_expanded = None;
+		self.isolated = true;
+		self.exhausted = false;
+		rv
+	}
+	/// Returns true if the parser has tried to read past the end of the isolated expansion (so an error means the
+	/// expansion is incomplete, rather than invalid)
+	pub fn isolation_exhausted(&self) -> bool
+	{
+		self.exhausted
+	}
+	/// Return to the main stream, returns true if the whole expansion was read
+	///
+	/// If the expansion wasn't all read, the location is reset to before it unless `keep_span` is set (e.g. to report
+	/// an error within the expansion).
+	pub fn end_isolation(&mut self, saved: Isolation, keep_span: bool) -> bool
+	{
+		let rv = self.pending.iter().all(|i| match *i { Item::Token(Token::EOF, _) => true, _ => false });
+		self.pending = saved.pending;
+		self.just_expanded = None;
+		self.isolated = false;
+		if !rv && !keep_span {
+			self.last_span = saved.last_span;
+		}
+		rv
+	}
+
+	/// Location of the last token returned by `get_token`
+	pub fn span(&self) -> &Span
+	{
+		&self.last_span
+	}
+	/// Location of the next token (without expanding a macro invocation)
+	pub fn peek_span(&mut self) -> ::preproc::Result<Span>
+	{
+		self.just_expanded = None;
+		let i = self.get_item()?;
+		let rv = match i
+			{
+			Item::Token(_, ref span) => span.clone(),
+			Item::Macro(ref m) => m.span.clone(),
+			};
+		self.pending.push(i);
+		Ok(rv)
+	}
+
+	/// Take the comments and directives read so far (with their locations)
+	pub fn take_extras(&mut self) -> Vec<(Extra, Span)>
+	{
+		::std::mem::replace(&mut self.extras, Vec::new())
+	}
+	/// Take the comments read so far (leaving any directives for `take_extras`)
+	pub fn take_comments(&mut self) -> Vec<(String, Span)>
+	{
+		let mut rv = Vec::new();
+		let mut directives = Vec::new();
+		for (e, span) in self.extras.drain(..)
+		{
+			match e
+			{
+			Extra::Comment(text) => rv.push( (text, span) ),
+			e => directives.push( (e, span) ),
+			}
+		}
+		self.extras = directives;
+		rv
+	}
+	/// Returns true if `span` is in the file being parsed
+	pub fn is_main_file(&self, span: &Span) -> bool
+	{
+		self.pp.is_main_file(span)
+	}
+
+	pub fn diagnostic(&self, severity: ::diagnostics::Severity, code: &'static str, span: &Span, message: String) -> ::diagnostics::Diagnostic
+	{
+		self.pp.diagnostic(severity, code, span, message)
+	}
+	pub fn take_diagnostics(&mut self) -> Vec<::diagnostics::Diagnostic>
+	{
+		self.pp.take_diagnostics()
+	}
+	pub fn dependencies(&self) -> Vec<::preproc::deps::Dependency>
+	{
+		self.pp.dependencies()
+	}
+
+	fn get_item(&mut self) -> ::preproc::Result<Item>
+	{
+		if let Some(i) = self.pending.pop() {
+			return Ok(i);
+		}
+		if self.isolated {
+			return Ok(Item::Token(Token::EOF, self.last_span.clone()));
+		}
+		loop
+		{
+			let tok = self.pp.get_token()?;
+			let span = self.pp.span().clone();
+			let extra = match tok
+				{
+				Token::LineComment(_) | Token::BlockComment(_) => Extra::Comment(tok.to_string()),
+				Token::Preprocessor(Preprocessor::MacroInvocaton { input, output }) => {
+					return Ok(Item::Macro(MacroUse { input: input, output: output, span: span }));
+					},
+				Token::Preprocessor(d @ Preprocessor::Include { .. })
+				| Token::Preprocessor(d @ Preprocessor::MacroDefine { .. })
+				| Token::Preprocessor(d @ Preprocessor::MacroUndefine { .. })
+				| Token::Preprocessor(d @ Preprocessor::Conditional(_))
+					=> Extra::Directive(d),
+				Token::Preprocessor(_) => continue,
+				tok => return Ok(Item::Token(tok, span)),
+				};
+			if self.pp.is_main_file(&span) {
+				self.extras.push( (extra, span) );
+			}
+		}
+	}
+}
+impl ::std::fmt::Display for TokenStream
+{
+	fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result
+	{
+		::std::fmt::Display::fmt(&self.pp, f)
+	}
+}
+
+// vim: ft=rust
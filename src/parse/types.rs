@@ -148,9 +148,9 @@ impl<'ast> super::ParseState<'ast>
 				typeid = Some(::types::BaseType::MagicType(::types::MagicType::VaList));
 				},
 			Token::Ident(ref n) if n == "__magictype__" => {
-				syntax_assert!(self.lex.get_token()?, Token::ParenOpen);
-				let s = syntax_assert!(self.lex.get_token()?, Token::String(s) => s);
-				syntax_assert!(self.lex.get_token()?, Token::ParenClose);
+				syntax_assert!(self.lex => Token::ParenOpen);
+				let s = syntax_assert!(self.lex => Token::String(s) @ s.to_string_lossy().into_owned());
+				syntax_assert!(self.lex => Token::ParenClose);
 				let (name, repr) = {
 					let mut it = s.splitn(2, ':');
 					( it.next().unwrap().to_owned(), it.next().expect("No repr in magic type").to_owned() )
@@ -238,10 +238,11 @@ impl<'ast> super::ParseState<'ast>
 					},
 				TypeNode::Array(sub, size) => {
 					let array_size = if let Some(size_expr) = size {
+							// Round-trip mode keeps the expression as written (e.g. a macro)
 							match size_expr.literal_integer()
 							{
-							Some(v) => ::types::ArraySize::Fixed(v),
-							None => ::types::ArraySizeExpr::new(size_expr).into(),
+							Some(v) if !self.round_trip => ::types::ArraySize::Fixed(v),
+							_ => ::types::ArraySizeExpr::new(size_expr).into(),
 							}
 						}
 						else {
@@ -339,7 +340,7 @@ impl<'ast> super::ParseState<'ast>
 			if args.len() == 1 && args[0] == (::types::Type::new_ref_bare(::types::BaseType::Void),"".to_string()) {
 				args.clear();
 			}
-			syntax_assert!(try!(self.lex.get_token()), Token::ParenClose);
+			syntax_assert!(self.lex => Token::ParenClose);
 
 			if peek_token!(self.lex, Token::Ident(ref n) if n == "__attribute__") {
 				self.parse_gcc_attributes(|self_, name, _opts|
@@ -383,7 +384,7 @@ impl<'ast> super::ParseState<'ast>
 				t @ _ => {
 					self.lex.put_back(t);
 					let size = self.parse_expr()?;
-					syntax_assert!(self.lex.get_token()?, Token::SquareClose);
+					syntax_assert!(self.lex => Token::SquareClose);
 					Some( size )
 					},
 				};
@@ -418,10 +419,14 @@ impl<'ast> super::ParseState<'ast>
 		match try!(self.lex.get_token())
 		{
 		Token::BraceOpen => {
-			let fields = try!(self.populate_struct());
+			let mut comments = Vec::new();
+			let fields = try!(self.populate_struct(&mut comments));
 			match self.ast.make_struct(&name, fields)
 			{
-			Ok(sr) => Ok(sr),
+			Ok(sr) => {
+				sr.borrow_mut().comments = comments;
+				Ok(sr)
+				},
 			Err( () ) => syntax_error!("Multiple definitions of struct '{}'", name),
 			}
 			},
@@ -432,12 +437,27 @@ impl<'ast> super::ParseState<'ast>
 			}
 		}
 	}
-	fn populate_struct(&mut self) -> ParseResult<Vec<(::types::TypeRef,String)>>
+	fn populate_struct(&mut self, comments: &mut Vec<::types::MemberComment>) -> ParseResult<Vec<(::types::TypeRef,String)>>
 	{
 		let mut items = Vec::new();
+		// Line of the `;` ending the previous member (for attaching trailing comments)
+		let mut last_line = None;
 		loop
 		{
-			if peek_token!(self.lex, Token::BraceClose) {
+			let is_end = peek_token!(self.lex, Token::BraceClose);
+			if self.round_trip
+			{
+				for (text, span) in self.lex.take_comments()
+				{
+					let trailing = last_line == Some(span.line);
+					comments.push(::types::MemberComment {
+						index: if trailing { items.len() - 1 } else { items.len() },
+						trailing: trailing,
+						text: text,
+						});
+				}
+			}
+			if is_end {
 				break;
 			}
 			
@@ -456,7 +476,7 @@ impl<'ast> super::ParseState<'ast>
 					&::types::BaseType::Integer(::types::IntClass::Int(s)) => s,
 					ft @ _ => syntax_error!("Invalid type for bitfield, expected signed/unsigned, got {:?}", ft),
 					};
-				let i = syntax_assert!( self.lex.get_token()?, Token::Integer(i,_class,_s) => i as u8 );
+				let i = syntax_assert!(self.lex => Token::Integer(i,_class,_s) @ i as u8);
 				let bt = ::types::BaseType::Integer(::types::IntClass::Bits(sign, i));
 				items.push( (::types::Type::new_ref_bare(bt), ident) );
 				
@@ -476,7 +496,8 @@ impl<'ast> super::ParseState<'ast>
 					items.push( self.get_full_type(basetype.clone())? );
 				}
 			}
-			syntax_assert!( try!(self.lex.get_token()), Token::Semicolon );
+			syntax_assert!(self.lex => Token::Semicolon);
+			last_line = Some(self.lex.span().line);
 		}
 		
 		if peek_token!(self.lex, Token::Ident(ref n) if n == "__attribute__")
@@ -534,7 +555,7 @@ impl<'ast> super::ParseState<'ast>
 			{
 				items.push( try!(self.get_full_type(basetype.clone())) );
 			}
-			syntax_assert!( try!(self.lex.get_token()), Token::Semicolon );
+			syntax_assert!(self.lex => Token::Semicolon);
 		}
 		
 		Ok( items )
@@ -578,7 +599,7 @@ impl<'ast> super::ParseState<'ast>
 			if peek_token!(self.lex, Token::BraceClose) {
 				break;
 			}
-			let name = syntax_assert!( try!(self.lex.get_token()), Token::Ident(v) => v );
+			let name = syntax_assert!(self.lex => Token::Ident(v) @ v);
 			
 			if peek_token!(self.lex, Token::Assign) {
 				// This can be a constant expression
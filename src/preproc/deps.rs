@@ -0,0 +1,100 @@
+/*!
+ * Makefile dependency rules (`-M` and related options)
+ */
+use std::io::Write;
+
+/// A file read while pre-processing
+#[derive(Debug,Clone)]
+pub struct Dependency
+{
+	pub path: ::std::path::PathBuf,
+	/// The file is a system header (see `SourceFile::is_system_header`)
+	pub is_system: bool,
+}
+
+/// How the dependency rule is written
+#[derive(Debug)]
+pub struct Options
+{
+	/// Targets of the rule (written as-is)
+	pub targets: Vec<String>,
+	/// Include system headers (`-M` vs `-MM`)
+	pub include_system: bool,
+	/// Add an empty rule for each header (`-MP`), so removing a header doesn't break the build
+	pub phony_targets: bool,
+}
+
+/// Lines are wrapped (with a `\` continuation) before they reach this length
+const MAX_LINE: usize = 76;
+
+/// Write a rule making the targets depend on the input file and every header it included
+///
+/// `deps` starts with the input file, as returned by `Preproc::dependencies`.
+pub fn write_rule(out: &mut Write, options: &Options, deps: &[Dependency]) -> ::std::io::Result<()>
+{
+	let deps: Vec<_> = deps.iter()
+		.enumerate()
+		.filter(|&(i, d)| i == 0 || options.include_system || !d.is_system)
+		.map(|(_, d)| escape(&d.path.display().to_string()))
+		.collect();
+
+	let mut line_len = 0;
+	for (i, t) in options.targets.iter().enumerate()
+	{
+		if i > 0 {
+			line_len = write_wrapped(out, line_len, t)?;
+		}
+		else {
+			write!(out, "{}", t)?;
+			line_len = t.len();
+		}
+	}
+	write!(out, ":")?;
+	line_len += 1;
+	for d in &deps
+	{
+		line_len = write_wrapped(out, line_len, d)?;
+	}
+	writeln!(out, "")?;
+
+	if options.phony_targets
+	{
+		for d in deps.iter().skip(1)
+		{
+			write!(out, "\n{}:\n", d)?;
+		}
+	}
+	Ok( () )
+}
+
+/// Write a space then `word`, starting a continuation line first if it wouldn't fit
+fn write_wrapped(out: &mut Write, line_len: usize, word: &str) -> ::std::io::Result<usize>
+{
+	if line_len + 1 + word.len() > MAX_LINE {
+		write!(out, " \\\n {}", word)?;
+		Ok(1 + word.len())
+	}
+	else {
+		write!(out, " {}", word)?;
+		Ok(line_len + 1 + word.len())
+	}
+}
+
+/// Escape characters that are special to make
+fn escape(path: &str) -> String
+{
+	let mut rv = String::new();
+	for c in path.chars()
+	{
+		match c
+		{
+		' ' => rv.push_str("\\ "),
+		'#' => rv.push_str("\\#"),
+		'$' => rv.push_str("$$"),
+		_ => rv.push(c),
+		}
+	}
+	rv
+}
+
+// vim: ft=rust
@@ -0,0 +1,366 @@
+/*!
+ * `#if`/`#elif` constant-expression evaluation (C11 6.10.1)
+ *
+ * Expressions are evaluated using `intmax_t`/`uintmax_t` (64-bit) arithmetic, after `defined` and macro expansion have
+ * been handled by the caller.
+ */
+use super::{Token,DirectiveError};
+
+type Result<T> = ::std::result::Result<T,DirectiveError>;
+
+/// Value of an `#if` (sub-)expression
+#[derive(Debug,Copy,Clone,PartialEq)]
+pub enum Value
+{
+	/// `intmax_t`
+	Signed(i64),
+	/// `uintmax_t`
+	Unsigned(u64),
+}
+impl Value
+{
+	pub fn is_true(&self) -> bool
+	{
+		match *self
+		{
+		Value::Signed(v) => v != 0,
+		Value::Unsigned(v) => v != 0,
+		}
+	}
+	fn from_bool(v: bool) -> Value
+	{
+		Value::Signed(v as i64)
+	}
+	/// Shift count (saturated to the range of `i64`)
+	fn as_count(&self) -> i64
+	{
+		match *self
+		{
+		Value::Signed(v) => v,
+		Value::Unsigned(v) => if v > i64::max_value() as u64 { i64::max_value() } else { v as i64 },
+		}
+	}
+	/// Apply the usual arithmetic conversions (if either operand is unsigned, both are converted to unsigned)
+	fn convert(a: Value, b: Value) -> (Value, Value)
+	{
+		match (a, b)
+		{
+		(Value::Signed(a), Value::Signed(b)) => (Value::Signed(a), Value::Signed(b)),
+		(Value::Signed(a), Value::Unsigned(b)) => (Value::Unsigned(a as u64), Value::Unsigned(b)),
+		(Value::Unsigned(a), Value::Signed(b)) => (Value::Unsigned(a), Value::Unsigned(b as u64)),
+		(Value::Unsigned(a), Value::Unsigned(b)) => (Value::Unsigned(a), Value::Unsigned(b)),
+		}
+	}
+}
+impl ::std::fmt::Display for Value
+{
+	fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result
+	{
+		match *self
+		{
+		Value::Signed(v) => write!(f, "{}", v),
+		Value::Unsigned(v) => write!(f, "{}u", v),
+		}
+	}
+}
+
+/// Evaluate a (fully macro-expanded) `#if` expression
+///
+/// Identifiers that remain (including keywords) evaluate to zero
+pub fn evaluate(tokens: Vec<Token>, char_is_signed: bool) -> Result<Value>
+{
+	let tokens: Vec<_> = tokens.into_iter()
+		.filter(|t| match *t { Token::Whitespace | Token::EscapedNewline => false, _ => true })
+		.collect();
+	if tokens.is_empty() {
+		return Err(bad_expr("#if with no expression"));
+	}
+	let mut p = Parser {
+		tokens: tokens.into_iter().peekable(),
+		char_is_signed: char_is_signed,
+		unevaluated: 0,
+		};
+	let rv = p.expr_comma()?;
+	match p.tokens.next()
+	{
+	None => Ok(rv),
+	Some(t) => Err(bad_expr(format!("missing binary operator before token \"{}\"", t))),
+	}
+}
+
+fn bad_expr<S: Into<String>>(msg: S) -> DirectiveError
+{
+	DirectiveError::BadIfExpression(msg.into())
+}
+
+struct Parser
+{
+	tokens: ::std::iter::Peekable<::std::vec::IntoIter<Token>>,
+	char_is_signed: bool,
+	/// Non-zero while parsing an operand that isn't evaluated (e.g. the RHS of `0 && x`), which suppresses errors
+	unevaluated: usize,
+}
+
+/// Precedence of a binary operator (higher binds tighter)
+fn binary_precedence(tok: &Token) -> Option<u8>
+{
+	Some(match *tok
+	{
+	Token::DoublePipe => 1,
+	Token::DoubleAmpersand => 2,
+	Token::Pipe => 3,
+	Token::Caret => 4,
+	Token::Ampersand => 5,
+	Token::Equality | Token::NotEquals => 6,
+	Token::Lt | Token::Gt | Token::LtE | Token::GtE => 7,
+	Token::ShiftLeft | Token::ShiftRight => 8,
+	Token::Plus | Token::Minus => 9,
+	Token::Star | Token::Slash | Token::Percent => 10,
+	_ => return None,
+	})
+}
+
+impl Parser
+{
+	fn expect(&mut self, exp: Token, msg: &str) -> Result<()>
+	{
+		match self.tokens.next()
+		{
+		Some(ref t) if *t == exp => Ok( () ),
+		_ => Err(bad_expr(msg)),
+		}
+	}
+
+	/// expression: conditional-expression { `,` conditional-expression }
+	fn expr_comma(&mut self) -> Result<Value>
+	{
+		let mut v = self.expr_conditional()?;
+		while self.tokens.peek() == Some(&Token::Comma)
+		{
+			self.tokens.next();
+			v = self.expr_conditional()?;
+		}
+		Ok(v)
+	}
+
+	/// conditional-expression: binary-expression [ `?` expression `:` conditional-expression ]
+	fn expr_conditional(&mut self) -> Result<Value>
+	{
+		let cond = self.expr_binary(1)?;
+		if self.tokens.peek() != Some(&Token::QuestionMark) {
+			return Ok(cond);
+		}
+		self.tokens.next();
+
+		let is_true = cond.is_true();
+		let a = self.unevaluated_if(!is_true, |p| p.expr_comma())?;
+		self.expect(Token::Colon, "'?' without following ':'")?;
+		let b = self.unevaluated_if(is_true, |p| p.expr_conditional())?;
+		// The result has the type of both operands after the usual arithmetic conversions
+		let (a, b) = Value::convert(a, b);
+		Ok(if is_true { a } else { b })
+	}
+
+	/// Binary operators (precedence climbing, all are left-associative)
+	fn expr_binary(&mut self, min_prec: u8) -> Result<Value>
+	{
+		let mut lhs = self.expr_unary()?;
+		loop
+		{
+			let prec = match self.tokens.peek().and_then(binary_precedence)
+				{
+				Some(p) if p >= min_prec => p,
+				_ => return Ok(lhs),
+				};
+			let op = self.tokens.next().unwrap();
+			// `&&` and `||` short-circuit
+			let skip_rhs = match op
+				{
+				Token::DoubleAmpersand => !lhs.is_true(),
+				Token::DoublePipe => lhs.is_true(),
+				_ => false,
+				};
+			let rhs = self.unevaluated_if(skip_rhs, |p| p.expr_binary(prec + 1))?;
+			lhs = self.apply_binary(&op, lhs, rhs)?;
+		}
+	}
+
+	fn expr_unary(&mut self) -> Result<Value>
+	{
+		match self.tokens.peek()
+		{
+		Some(&Token::Plus) => { self.tokens.next(); self.expr_unary() },
+		Some(&Token::Minus) => {
+			self.tokens.next();
+			Ok(match self.expr_unary()?
+			{
+			Value::Signed(v) => Value::Signed(v.wrapping_neg()),
+			Value::Unsigned(v) => Value::Unsigned(v.wrapping_neg()),
+			})
+			},
+		Some(&Token::Tilde) => {
+			self.tokens.next();
+			Ok(match self.expr_unary()?
+			{
+			Value::Signed(v) => Value::Signed(!v),
+			Value::Unsigned(v) => Value::Unsigned(!v),
+			})
+			},
+		Some(&Token::Exclamation) => {
+			self.tokens.next();
+			Ok(Value::from_bool( !self.expr_unary()?.is_true() ))
+			},
+		_ => self.expr_primary(),
+		}
+	}
+
+	fn expr_primary(&mut self) -> Result<Value>
+	{
+		match self.tokens.next()
+		{
+		None => Err(bad_expr("#if expression ends unexpectedly")),
+		Some(Token::Integer(v, cls, _)) => {
+			// NOTE: Values that don't fit in `intmax_t` are `uintmax_t` (C11 6.4.4.1p5)
+			if is_unsigned_class(&cls) || v > i64::max_value() as u64 {
+				Ok(Value::Unsigned(v))
+			}
+			else {
+				Ok(Value::Signed(v as i64))
+			}
+			},
+		// Character constants have type `int`, and a single byte is sign-extended if `char` is signed
+		Some(Token::Character(c)) => Ok(Value::Signed(c.value(self.char_is_signed))),
+		// Any remaining identifier (including keywords) is replaced by `0` (C11 6.10.1p4)
+		Some(Token::Ident(n)) => {
+			debug!("#if: Undefined identifier {}, evaluating to 0", n);
+			Ok(Value::Signed(0))
+			},
+		Some(Token::ParenOpen) => {
+			let rv = self.expr_comma()?;
+			self.expect(Token::ParenClose, "missing ')' in expression")?;
+			Ok(rv)
+			},
+		Some(Token::Float(..)) => Err(bad_expr("floating constant in preprocessor expression")),
+		Some(t) => Err(bad_expr(format!("token \"{}\" is not valid in preprocessor expressions", t))),
+		}
+	}
+
+	/// Run `f`, treating it as an unevaluated operand if `cnd` is true
+	fn unevaluated_if<F>(&mut self, cnd: bool, f: F) -> Result<Value>
+	where
+		F: FnOnce(&mut Self) -> Result<Value>
+	{
+		if cnd {
+			self.unevaluated += 1;
+		}
+		let rv = f(self);
+		if cnd {
+			self.unevaluated -= 1;
+		}
+		rv
+	}
+
+	fn apply_binary(&self, op: &Token, lhs: Value, rhs: Value) -> Result<Value>
+	{
+		Ok(match *op
+		{
+		Token::DoubleAmpersand => Value::from_bool(lhs.is_true() && rhs.is_true()),
+		Token::DoublePipe => Value::from_bool(lhs.is_true() || rhs.is_true()),
+		// Shifts have the type of the (promoted) left operand
+		Token::ShiftLeft => shift(lhs, rhs.as_count(), true),
+		Token::ShiftRight => shift(lhs, rhs.as_count(), false),
+		_ => match Value::convert(lhs, rhs)
+			{
+			(Value::Signed(a), Value::Signed(b)) => match *op
+				{
+				Token::Slash | Token::Percent if b == 0 => return self.division_by_zero(),
+				Token::Star => Value::Signed(a.wrapping_mul(b)),
+				Token::Slash => Value::Signed(a.wrapping_div(b)),
+				Token::Percent => Value::Signed(a.wrapping_rem(b)),
+				Token::Plus => Value::Signed(a.wrapping_add(b)),
+				Token::Minus => Value::Signed(a.wrapping_sub(b)),
+				Token::Ampersand => Value::Signed(a & b),
+				Token::Caret => Value::Signed(a ^ b),
+				Token::Pipe => Value::Signed(a | b),
+				_ => Value::from_bool(compare(op, a, b)),
+				},
+			(Value::Unsigned(a), Value::Unsigned(b)) => match *op
+				{
+				Token::Slash | Token::Percent if b == 0 => return self.division_by_zero(),
+				Token::Star => Value::Unsigned(a.wrapping_mul(b)),
+				Token::Slash => Value::Unsigned(a / b),
+				Token::Percent => Value::Unsigned(a % b),
+				Token::Plus => Value::Unsigned(a.wrapping_add(b)),
+				Token::Minus => Value::Unsigned(a.wrapping_sub(b)),
+				Token::Ampersand => Value::Unsigned(a & b),
+				Token::Caret => Value::Unsigned(a ^ b),
+				Token::Pipe => Value::Unsigned(a | b),
+				_ => Value::from_bool(compare(op, a, b)),
+				},
+			_ => unreachable!(),
+			},
+		})
+	}
+
+	fn division_by_zero(&self) -> Result<Value>
+	{
+		if self.unevaluated > 0 {
+			Ok(Value::Signed(0))
+		}
+		else {
+			Err(DirectiveError::DivisionByZero)
+		}
+	}
+}
+
+fn compare<T: PartialOrd>(op: &Token, a: T, b: T) -> bool
+{
+	match *op
+	{
+	Token::Lt => a < b,
+	Token::Gt => a > b,
+	Token::LtE => a <= b,
+	Token::GtE => a >= b,
+	Token::Equality => a == b,
+	Token::NotEquals => a != b,
+	_ => panic!("BUGCHECK: {:?} isn't a comparison", op),
+	}
+}
+
+/// Shift a value (negative counts shift the other way, and over-large counts shift out all bits)
+fn shift(v: Value, count: i64, is_left: bool) -> Value
+{
+	let (is_left, count) = if count < 0 { (!is_left, count.wrapping_neg() as u64) } else { (is_left, count as u64) };
+	match v
+	{
+	Value::Signed(v) => Value::Signed(match (is_left, count >= 64)
+		{
+		(true, false) => v.wrapping_shl(count as u32),
+		(true, true) => 0,
+		(false, false) => v >> count,
+		(false, true) => if v < 0 { -1 } else { 0 },
+		}),
+	Value::Unsigned(v) => Value::Unsigned(match (is_left, count >= 64)
+		{
+		(_, true) => 0,
+		(true, false) => v << count,
+		(false, false) => v >> count,
+		}),
+	}
+}
+
+fn is_unsigned_class(cls: &::types::IntClass) -> bool
+{
+	use ::types::IntClass;
+	match *cls
+	{
+	IntClass::Bits(s, _) => s.is_unsigned(),
+	IntClass::Char(s) => s.map(|s| s.is_unsigned()).unwrap_or(false),
+	IntClass::Short(s) => s.is_unsigned(),
+	IntClass::Int(s) => s.is_unsigned(),
+	IntClass::Long(s) => s.is_unsigned(),
+	IntClass::LongLong(s) => s.is_unsigned(),
+	}
+}
+
+// vim: ft=rust
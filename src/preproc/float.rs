@@ -0,0 +1,253 @@
+/*!
+ * Conversion of floating constants to binary
+ *
+ * Conversion is exact (using arbitrary-precision integers) then rounded once, to nearest with ties to even, so the
+ * result is the correctly rounded value for the constant's type.
+ */
+use super::token::FloatValue;
+
+/// Binary format of a floating type
+struct Format
+{
+	/// Significand bits (including the leading bit)
+	precision: u32,
+	/// Exponent of the smallest normal value
+	min_exp: i64,
+	/// Exponent of the largest finite value
+	max_exp: i64,
+}
+const FLOAT: Format = Format { precision: 24, min_exp: -126, max_exp: 127 };
+const DOUBLE: Format = Format { precision: 53, min_exp: -1022, max_exp: 1023 };
+/// x87 extended precision
+const LONG_DOUBLE: Format = Format { precision: 64, min_exp: -16382, max_exp: 16383 };
+
+/// Decimal exponents beyond this (relative to the first significant digit) are out of range for every type
+const MAX_DECIMAL_EXP: i64 = 5000;
+/// Likewise for binary exponents
+const MAX_BINARY_EXP: i64 = 17000;
+
+/// Convert the digits of a floating constant to a value of type `class`
+///
+/// `base` is 10 or 16, and `exponent` is a power of ten for decimal constants and a power of two for hexadecimal ones.
+/// Values too large for the type give `FloatValue::Infinity`, and values too small become zero.
+pub fn make_float(base: u32, int_digits: &str, frac_digits: &str, exponent: i64, class: &::types::FloatClass) -> FloatValue
+{
+	let format = match *class
+		{
+		::types::FloatClass::Float => &FLOAT,
+		::types::FloatClass::Double => &DOUBLE,
+		::types::FloatClass::LongDouble => &LONG_DOUBLE,
+		};
+	let digits: String = int_digits.chars().chain(frac_digits.chars()).skip_while(|&c| c == '0').collect();
+	if digits.is_empty() {
+		return FloatValue::ZERO;
+	}
+	let significand = BigUint::from_digits(&digits, base);
+	let frac_len = frac_digits.len() as i64;
+
+	if base == 16
+	{
+		let bin_exp = exponent.saturating_sub(4 * frac_len);
+		let magnitude = bin_exp.saturating_add(significand.bit_len() as i64);
+		if magnitude > MAX_BINARY_EXP {
+			return FloatValue::Infinity;
+		}
+		if magnitude < -MAX_BINARY_EXP {
+			return FloatValue::ZERO;
+		}
+		round(significand, BigUint::from_u32(1), bin_exp, format)
+	}
+	else
+	{
+		let dec_exp = exponent.saturating_sub(frac_len);
+		let magnitude = dec_exp.saturating_add(digits.len() as i64);
+		if magnitude > MAX_DECIMAL_EXP {
+			return FloatValue::Infinity;
+		}
+		if magnitude < -MAX_DECIMAL_EXP {
+			return FloatValue::ZERO;
+		}
+		// 10^n = 5^n * 2^n, with the power of two kept in the binary exponent
+		if dec_exp >= 0 {
+			let mut num = significand;
+			num.mul_pow5(dec_exp as u32);
+			round(num, BigUint::from_u32(1), dec_exp, format)
+		}
+		else {
+			let mut den = BigUint::from_u32(1);
+			den.mul_pow5((-dec_exp) as u32);
+			round(significand, den, dec_exp, format)
+		}
+	}
+}
+
+/// Round `num / den * 2^bin_exp` to the format
+fn round(mut num: BigUint, mut den: BigUint, bin_exp: i64, format: &Format) -> FloatValue
+{
+	// Scale so the quotient has at least two more bits than the significand (for the rounding bit)
+	let want = format.precision as i64 + 2;
+	let shift = want - (num.bit_len() as i64 - den.bit_len() as i64);
+	if shift > 0 {
+		num.shl(shift as usize);
+	}
+	else {
+		den.shl((-shift) as usize);
+	}
+	let (q, inexact) = num.div(&den);
+	let bin_exp = bin_exp - shift;
+
+	// The value is `q * 2^bin_exp` (plus a remainder), in [2^e, 2^(e+1))
+	let q_bits = 128 - q.leading_zeros() as i64;
+	let e = q_bits - 1 + bin_exp;
+	// Subnormal values have fewer significant bits
+	let keep = if e < format.min_exp { format.precision as i64 - (format.min_exp - e) } else { format.precision as i64 };
+	let drop = q_bits - keep;
+
+	let bit = |n: i64| n < 128 && (q >> n) & 1 != 0;
+	let mut mantissa = if drop >= 128 { 0 } else { q >> drop };
+	let round_bit = bit(drop - 1);
+	let sticky = inexact || (drop - 1 > 0 && q & ((1u128 << ::std::cmp::min(drop - 1, 127)) - 1) != 0);
+	if round_bit && (sticky || mantissa & 1 != 0) {
+		mantissa += 1;
+	}
+	let mut exponent = bin_exp + drop;
+	// Rounding up can carry into an extra bit
+	if mantissa >> format.precision != 0 {
+		mantissa >>= 1;
+		exponent += 1;
+	}
+
+	if mantissa == 0 {
+		FloatValue::ZERO
+	}
+	else if (128 - mantissa.leading_zeros() as i64) - 1 + exponent > format.max_exp {
+		FloatValue::Infinity
+	}
+	else {
+		FloatValue::Finite { mantissa: mantissa as u64, exponent: exponent as i32 }
+	}
+}
+
+/// Minimal arbitrary-precision unsigned integer (little-endian 32-bit limbs)
+struct BigUint(Vec<u32>);
+impl BigUint
+{
+	fn from_u32(v: u32) -> BigUint
+	{
+		BigUint(vec![v])
+	}
+	fn from_digits(digits: &str, base: u32) -> BigUint
+	{
+		let mut rv = BigUint::from_u32(0);
+		for c in digits.chars()
+		{
+			rv.mul_add(base, c.to_digit(base).expect("BUG: Invalid digit in floating constant"));
+		}
+		rv
+	}
+
+	fn bit_len(&self) -> usize
+	{
+		match self.0.iter().rposition(|&l| l != 0)
+		{
+		Some(i) => i * 32 + (32 - self.0[i].leading_zeros() as usize),
+		None => 0,
+		}
+	}
+	/// `self = self * m + a`
+	fn mul_add(&mut self, m: u32, a: u32)
+	{
+		let mut carry = a as u64;
+		for l in self.0.iter_mut()
+		{
+			let v = *l as u64 * m as u64 + carry;
+			*l = v as u32;
+			carry = v >> 32;
+		}
+		if carry != 0 {
+			self.0.push(carry as u32);
+		}
+	}
+	fn mul_pow5(&mut self, mut n: u32)
+	{
+		// 5^13 is the largest power of five that fits in a limb
+		while n >= 13 {
+			self.mul_add(1220703125, 0);
+			n -= 13;
+		}
+		self.mul_add(5u32.pow(n), 0);
+	}
+	fn shl(&mut self, bits: usize)
+	{
+		let (limbs, bits) = (bits / 32, bits % 32);
+		if bits > 0 {
+			let mut carry = 0;
+			for l in self.0.iter_mut()
+			{
+				let v = *l;
+				*l = (v << bits) | carry;
+				carry = v >> (32 - bits);
+			}
+			if carry != 0 {
+				self.0.push(carry);
+			}
+		}
+		for _ in 0 .. limbs {
+			self.0.insert(0, 0);
+		}
+	}
+	fn cmp(&self, other: &BigUint) -> ::std::cmp::Ordering
+	{
+		let len = ::std::cmp::max(self.0.len(), other.0.len());
+		for i in (0 .. len).rev()
+		{
+			let a = self.0.get(i).cloned().unwrap_or(0);
+			let b = other.0.get(i).cloned().unwrap_or(0);
+			if a != b {
+				return a.cmp(&b);
+			}
+		}
+		::std::cmp::Ordering::Equal
+	}
+	/// `self -= other` (`other` must not be larger)
+	fn sub(&mut self, other: &BigUint)
+	{
+		let mut borrow = 0i64;
+		for (i, l) in self.0.iter_mut().enumerate()
+		{
+			let v = *l as i64 - other.0.get(i).cloned().unwrap_or(0) as i64 - borrow;
+			*l = v as u32;
+			borrow = if v < 0 { 1 } else { 0 };
+		}
+		assert!(borrow == 0, "BUG: BigUint::sub underflow");
+	}
+	/// Divide, returning the quotient (which must fit in 128 bits) and whether there was a remainder
+	fn div(mut self, den: &BigUint) -> (u128, bool)
+	{
+		let mut q = 0u128;
+		let top = self.bit_len().saturating_sub(den.bit_len());
+		let mut shifted_den = BigUint(den.0.clone());
+		shifted_den.shl(top);
+		for i in (0 .. top + 1).rev()
+		{
+			if self.cmp(&shifted_den) != ::std::cmp::Ordering::Less {
+				self.sub(&shifted_den);
+				q |= 1 << i;
+			}
+			shifted_den.shr1();
+		}
+		(q, self.bit_len() != 0)
+	}
+	fn shr1(&mut self)
+	{
+		let mut carry = 0;
+		for l in self.0.iter_mut().rev()
+		{
+			let v = *l;
+			*l = (v >> 1) | carry;
+			carry = v << 31;
+		}
+	}
+}
+
+// vim: ft=rust
@@ -1,18 +1,66 @@
 /*!
  * Converts a source file into a stream of tokens
  */
-use super::token::Token;
-use super::Error;
+use super::token::{Token,Digraph,Encoding,StringLit,CharLit,FloatValue};
+use super::span::{Span,FileId};
+use super::{Error,LiteralError};
 
 pub type LexerInput<'a> = Box< ::std::iter::Iterator<Item=::std::io::Result<char>> + 'a >;
 
 pub struct Lexer<'a>
 {
 	instream: LexerInput<'a>,
-	lastchar: Option<char>,
+	/// Characters read ahead from `instream` while checking for trigraphs (the next is last)
+	raw_pending: Vec<char>,
+	/// Character pushed back by `ungetc`, and the number of source characters it came from
+	lastchar: Option<(char, usize)>,
 
 	/// String currently being captured (for floats/intergers)
 	capture: Option<String>,
+
+	/// File being lexed (for spans)
+	file: FileId,
+	/// Position of the next character, and the value before the last `getc`
+	pos: Position,
+	prev_pos: Position,
+	/// Problems found in literals since the last `take_literal_errors`
+	literal_errors: Vec<LiteralError>,
+	options: Options,
+}
+/// Value of an escape sequence
+enum Escape
+{
+	/// A character (simple escapes and universal character names)
+	Char(char),
+	/// A code unit (octal and hex escapes)
+	Value(u64),
+}
+/// Lexer settings (derived from the pre-processor's `Options`)
+#[derive(Copy,Clone,Default)]
+pub struct Options
+{
+	/// Accept C23 digit separators (`1'000'000`)
+	pub digit_separators: bool,
+	/// `long` is 64 bits (used to select the type of integer constants)
+	pub long_is_64bit: bool,
+	/// Replace trigraphs (`??=` etc)
+	pub trigraphs: bool,
+}
+
+/// Value of a numeric constant (see `Lexer::read_numeric`)
+enum Number
+{
+	Integer(u64, ::types::IntClass),
+	Float(FloatValue, ::types::FloatClass),
+}
+#[derive(Copy,Clone)]
+struct Position
+{
+	/// Byte offset
+	offset: usize,
+	line: usize,
+	/// Zero-based character column
+	column: usize,
 }
 struct CaptureHandle;
 impl Drop for CaptureHandle {
@@ -41,26 +89,101 @@ macro_rules! match_ch {
 
 impl<'a> Lexer<'a>
 {
-	pub fn new(instream: LexerInput<'a>) -> Lexer {
+	pub fn new(instream: LexerInput<'a>, file: FileId, options: Options) -> Lexer {
+		let pos = Position { offset: 0, line: 1, column: 0 };
 		Lexer {
 			instream: instream,
+			raw_pending: Vec::new(),
 			lastchar: None,
 			capture: None,
+			file: file,
+			pos: pos,
+			prev_pos: pos,
+			literal_errors: Vec::new(),
+			options: options,
 		}
 	}
 	
+	/// File ID given to spans
+	pub fn file(&self) -> FileId {
+		self.file
+	}
+	/// Change the file ID given to spans (for `#line`)
+	pub fn set_file(&mut self, file: FileId) {
+		self.file = file;
+	}
+	/// Take the problems found in literals since the last call (all from the last token returned)
+	pub fn take_literal_errors(&mut self) -> Vec<LiteralError> {
+		::std::mem::replace(&mut self.literal_errors, Vec::new())
+	}
+	
+	fn next_raw(&mut self) -> super::Result<Option<char>>
+	{
+		if let Some(ch) = self.raw_pending.pop() {
+			return Ok(Some(ch));
+		}
+		match self.instream.next()
+		{
+		Some(Ok(ch)) => Ok(Some(ch)),
+		Some(Err(e)) => Err(Error::IoError(e)),
+		None => Ok(None),
+		}
+	}
+	/// Read a character, replacing trigraphs if enabled (C11 5.2.1.1)
+	///
+	/// Returns the character and the number of source characters it was read from.
+	fn read_char(&mut self) -> super::Result<Option<(char, usize)>>
+	{
+		let ch = match self.next_raw()?
+			{
+			Some(ch) => ch,
+			None => return Ok(None),
+			};
+		if ch == '?' && self.options.trigraphs
+		{
+			match self.next_raw()?
+			{
+			Some('?') => {
+				if let Some(ch3) = self.next_raw()?
+				{
+					let replacement = match ch3
+						{
+						'=' => Some('#'),
+						'(' => Some('['),
+						'/' => Some('\\'),
+						')' => Some(']'),
+						'\'' => Some('^'),
+						'<' => Some('{'),
+						'!' => Some('|'),
+						'>' => Some('}'),
+						'-' => Some('~'),
+						_ => None,
+						};
+					if let Some(r) = replacement {
+						return Ok(Some( (r, 3) ));
+					}
+					self.raw_pending.push(ch3);
+				}
+				self.raw_pending.push('?');
+				},
+			Some(ch2) => self.raw_pending.push(ch2),
+			None => {},
+			}
+		}
+		Ok(Some( (ch, 1) ))
+	}
+
 	fn getc(&mut self) -> super::Result<char>
 	{
-		let ch = if let Some(ch) = self.lastchar.take()
+		let (ch, n_chars) = if let Some(v) = self.lastchar.take()
 			{
-				ch
+				v
 			}
 			else
 			{
-				match self.instream.next()
+				match self.read_char()?
 				{
-				Some(Ok(ch)) => ch,
-				Some(Err(e)) => return Err(Error::IoError(e)),
+				Some(v) => v,
 				None => return Err(Error::EOF),
 				}
 			};
@@ -68,10 +191,22 @@ impl<'a> Lexer<'a>
 		{
 			cap.push(ch)
 		}
+		self.prev_pos = self.pos;
+		// Trigraphs are three ASCII characters
+		self.pos.offset += if n_chars == 1 { ch.len_utf8() } else { n_chars };
+		if ch == '\n' {
+			self.pos.line += 1;
+			self.pos.column = 0;
+		}
+		else {
+			self.pos.column += n_chars;
+		}
 		Ok(ch)
 	}
 	fn ungetc(&mut self, ch: char) {
-		self.lastchar = Some(ch);
+		let n_chars = if ch == '\n' { 1 } else { self.pos.column - self.prev_pos.column };
+		self.lastchar = Some( (ch, n_chars) );
+		self.pos = self.prev_pos;
 		if let Some(cap) = self.capture.as_mut()
 		{
 			cap.pop();
@@ -131,99 +266,348 @@ impl<'a> Lexer<'a>
 		}
 		return Ok(name);
 	}
-	// Read a number from the input stream
-	fn read_number_with_len(&mut self, base: u32) -> super::Result<(u64,usize)>
+	/// Read the next character if it matches `f`
+	fn getc_if<F: Fn(char)->bool>(&mut self, f: F) -> super::Result<Option<char>>
 	{
-		let mut val = 0;
-		let mut len = 0;
+		match self.getc()
+		{
+		Ok(ch) if f(ch) => Ok(Some(ch)),
+		Ok(ch) => { self.ungetc(ch); Ok(None) },
+		Err(Error::EOF) => Ok(None),
+		Err(e) => Err(e),
+		}
+	}
+	// Read a sequence of digits (decimal digits for bases up to ten, so invalid ones can be reported)
+	// - `after_digit` is set if the previous character was a digit, so a digit separator can follow it
+	fn read_digits(&mut self, base: u32, after_digit: bool) -> super::Result<String>
+	{
+		let base = ::std::cmp::max(base, 10);
+		let mut rv = String::new();
 		loop
 		{
-			let ch = try_eof!( self.getc(), (val,len) );
-			match ch.to_digit(base) {
-			Some(d) => {
-				val *= base as u64;
-				val += d as u64;
-				len += 1;
-				},
-			None => {
-				self.ungetc(ch);
-				return Ok( (val, len) );
+			if let Some(ch) = self.getc_if(|c| c.is_digit(base))? {
+				rv.push(ch);
+			}
+			// C23 digit separators (only between digits)
+			else if self.options.digit_separators && (after_digit || !rv.is_empty()) && self.getc_if(|c| c == '\'')?.is_some() {
+				match self.getc_if(|c| c.is_digit(base))?
+				{
+				Some(ch) => rv.push(ch),
+				None => {
+					self.literal_errors.push(LiteralError::Malformed("digit separator not followed by a digit"));
+					break
+					},
 				}
 			}
+			else {
+				break;
+			}
 		}
+		Ok(rv)
 	}
-	fn read_number(&mut self, base: u32) -> super::Result<u64>
+	// Read the suffix of a numeric constant (any identifier characters)
+	fn read_suffix(&mut self) -> super::Result<String>
 	{
-		Ok( self.read_number_with_len(base)?.0 )
+		let mut rv = String::new();
+		while let Some(ch) = self.getc_if(|c| c.is_alphanumeric() || c == '_')? {
+			rv.push(ch);
+		}
+		Ok(rv)
 	}
-	
-	fn read_escape(&mut self) -> super::Result<Option<char>>
+
+	/// Read an integer or floating constant (`first` is the first character, a digit or `.`)
+	///
+	/// Binary constants (`0b101`) are accepted as a GNU extension.
+	fn read_numeric(&mut self, first: char) -> super::Result<Number>
 	{
-		Ok(match try!(self.getc())
+		let mut base = 10;
+		let mut int_digits = String::new();
+		if first == '0' {
+			match self.getc_if(|c| c == 'x' || c == 'X' || c == 'b' || c == 'B')?
+			{
+			Some('x') | Some('X') => base = 16,
+			Some(_) => base = 2,
+			None => int_digits.push('0'),
+			}
+		}
+		else if first != '.' {
+			int_digits.push(first);
+		}
+		// A leading `.` is followed directly by the fractional part
+		if first != '.' {
+			let after_digit = !int_digits.is_empty();
+			int_digits += &self.read_digits(base, after_digit)?;
+		}
+
+		// Check for a decimal point or exponent
+		let has_point = first == '.' || self.getc_if(|c| c == '.')?.is_some();
+		let frac_digits = if has_point { self.read_digits(base, false)? } else { String::new() };
+		let has_exponent = if base == 16 {
+				self.getc_if(|c| c == 'p' || c == 'P')?.is_some()
+			}
+			else {
+				self.getc_if(|c| c == 'e' || c == 'E')?.is_some()
+			};
+
+		if has_point || has_exponent
 		{
-		'\\' => Some('\\'),
-		'"' => Some('"'),
-		'n' => Some('\n'),
-		'r' => Some('\r'),
-		't' => Some(8u8 as char),
-		'v' => Some(11u8 as char),
-		'0' => Some('\0'),
-		'\n' => None,
-		c @ _ => panic!("Unexpected escape code in string '\\{}'", c)
-		})
+			// Floating point (a malformed constant is reported, and given the value zero)
+			let mut is_valid = true;
+			if base == 2 {
+				self.literal_errors.push(LiteralError::Malformed("only hexadecimal and decimal floating constants are supported"));
+				is_valid = false;
+			}
+			else if int_digits.is_empty() && frac_digits.is_empty() {
+				self.literal_errors.push(LiteralError::Malformed("floating constant has no digits"));
+				is_valid = false;
+			}
+			let exponent = if has_exponent
+				{
+					// Parse the exponent (in base 10)
+					let is_neg = match self.getc_if(|c| c == '-' || c == '+')?
+						{
+						Some(c) => c == '-',
+						None => false,
+						};
+					let digits = self.read_digits(10, false)?;
+					if digits.is_empty() {
+						self.literal_errors.push(LiteralError::Malformed("exponent has no digits"));
+						is_valid = false;
+					}
+					// Saturates, as any exponent this large is out of range
+					let v = digits.parse::<i64>().unwrap_or(i64::max_value());
+					if is_neg { -v } else { v }
+				}
+				else
+				{
+					if base == 16 && is_valid {
+						self.literal_errors.push(LiteralError::Malformed("hexadecimal floating constants require an exponent"));
+						is_valid = false;
+					}
+					0
+				};
+
+			let suffix = self.read_suffix()?;
+			let ty = match &suffix[..]
+				{
+				"" => ::types::FloatClass::Double,
+				"f" | "F" => ::types::FloatClass::Float,
+				"l" | "L" => ::types::FloatClass::LongDouble,
+				_ => {
+					self.literal_errors.push(LiteralError::InvalidSuffix(suffix, true));
+					::types::FloatClass::Double
+					},
+				};
+			if !is_valid {
+				return Ok(Number::Float(FloatValue::ZERO, ty));
+			}
+			let value = super::float::make_float(base, &int_digits, &frac_digits, exponent, &ty);
+			if value == FloatValue::Infinity {
+				self.literal_errors.push(LiteralError::FloatOverflow(ty.clone()));
+			}
+			else if value.is_zero() && int_digits.chars().chain(frac_digits.chars()).any(|c| c != '0') {
+				self.literal_errors.push(LiteralError::FloatUnderflow);
+			}
+			Ok(Number::Float(value, ty))
+		}
+		else
+		{
+			// Integer
+			if int_digits.is_empty() {
+				self.literal_errors.push(LiteralError::Malformed(if base == 16 { "no digits in hexadecimal constant" } else { "no digits in binary constant" }));
+			}
+			if base == 10 && first == '0' {
+				base = 8;
+			}
+			if let Some(c) = int_digits.chars().find(|c| !c.is_digit(base)) {
+				self.literal_errors.push(LiteralError::InvalidDigit(c, base));
+			}
+			let mut overflowed = false;
+			let value = int_digits.chars().fold(0u64, |v, c| {
+				let d = c.to_digit(16).unwrap() as u64;
+				match v.checked_mul(base as u64).and_then(|v| v.checked_add(d))
+				{
+				Some(v) => v,
+				None => { overflowed = true; v.wrapping_mul(base as u64).wrapping_add(d) },
+				}
+				});
+			if overflowed {
+				self.literal_errors.push(LiteralError::IntegerTooLarge);
+			}
+
+			let suffix = self.read_suffix()?;
+			let (is_unsigned, n_longs) = match parse_integer_suffix(&suffix)
+				{
+				Some(v) => v,
+				None => {
+					self.literal_errors.push(LiteralError::InvalidSuffix(suffix, false));
+					(false, 0)
+					},
+				};
+			let (class, so_large) = integer_type(value, base == 10, is_unsigned, n_longs, self.options.long_is_64bit);
+			if so_large && !overflowed {
+				self.literal_errors.push(LiteralError::SoLargeItIsUnsigned);
+			}
+			Ok(Number::Integer(value, class))
+		}
 	}
 	
-	// Read a double-quoted string
-	// - NOTE: has no EOF processing, as an EOF in a double-quoted string is invalid
-	fn read_string(&mut self) -> super::Result<String>
+	/// Read an escape sequence (after the backslash), returns `None` for an escaped newline
+	fn read_escape(&mut self) -> super::Result<Option<Escape>>
 	{
-		let mut ret = String::new();
-		loop
+		Ok(Some(match try!(self.getc())
 		{
-			let ch = try!(self.getc());
-			if ch == '\"' {
-				break;
+		'\n' => return Ok(None),
+		'\'' => Escape::Char('\''),
+		'"' => Escape::Char('"'),
+		'?' => Escape::Char('?'),
+		'\\' => Escape::Char('\\'),
+		'a' => Escape::Char('\x07'),
+		'b' => Escape::Char('\x08'),
+		'f' => Escape::Char('\x0C'),
+		'n' => Escape::Char('\n'),
+		'r' => Escape::Char('\r'),
+		't' => Escape::Char('\t'),
+		'v' => Escape::Char('\x0B'),
+		// GCC extension: ESC
+		'e' | 'E' => Escape::Char('\x1B'),
+		// Octal, up to three digits
+		c @ '0' ... '7' => {
+			let mut val = c.to_digit(8).unwrap() as u64;
+			for _ in 0 .. 2
+			{
+				let ch = self.getc()?;
+				match ch.to_digit(8)
+				{
+				Some(d) => val = val * 8 + d as u64,
+				None => { self.ungetc(ch); break },
+				}
 			}
-			if ch == '\\' {
-				match try!(self.read_escape())
+			Escape::Value(val)
+			},
+		// Hex, any number of digits
+		'x' => {
+			let mut val: u64 = 0;
+			let mut n_digits = 0;
+			let mut overflowed = false;
+			loop
+			{
+				let ch = self.getc()?;
+				match ch.to_digit(16)
 				{
-				Some(c) => ret.push(c),
-				None => {},
+				Some(d) => {
+					overflowed |= val >> 60 != 0;
+					val = (val << 4) | d as u64;
+					n_digits += 1;
+					},
+				None => { self.ungetc(ch); break },
 				}
 			}
-			else {
-				ret.push( ch );
+			if n_digits == 0 {
+				self.literal_errors.push(LiteralError::MissingHexDigits);
 			}
-		}
-		return Ok(ret);
+			Escape::Value(if overflowed { !0 } else { val })
+			},
+		// Universal character names
+		c @ 'u' | c @ 'U' => {
+			let len = if c == 'u' { 4 } else { 8 };
+			let mut val = 0;
+			let mut text = format!("\\{}", c);
+			for _ in 0 .. len
+			{
+				let ch = self.getc()?;
+				match ch.to_digit(16)
+				{
+				Some(d) => { val = val * 16 + d; text.push(ch) },
+				None => {
+					self.ungetc(ch);
+					self.literal_errors.push(LiteralError::IncompleteUniversalCharacter);
+					return Ok(Some(Escape::Value(0)));
+					},
+				}
+			}
+			// C11 6.4.3p2: Not a surrogate, and not a basic character (other than `$`, `@`, and `` ` ``)
+			let is_basic = val < 0xA0 && val != 0x24 && val != 0x40 && val != 0x60;
+			match ::std::char::from_u32(val)
+			{
+			Some(ch) if !is_basic => Escape::Char(ch),
+			_ => {
+				self.literal_errors.push(LiteralError::InvalidUniversalCharacter(text));
+				Escape::Value(0)
+				},
+			}
+			},
+		c @ _ => {
+			self.literal_errors.push(LiteralError::UnknownEscape(c));
+			Escape::Char(c)
+			},
+		}))
 	}
-	// Read a single-quoted character constant
-	fn read_charconst(&mut self) -> super::Result<u64>
+	/// Read the contents of a string literal or character constant (up to the closing `quote`) as code units
+	// - NOTE: has no EOF processing, as an EOF in a literal is invalid
+	// - Octal and hex escapes give a single code unit (warning if it's out of range for the encoding)
+	fn read_literal(&mut self, encoding: Encoding, quote: char) -> super::Result<Vec<u32>>
 	{
-		let mut ret = String::new();
+		let mut ret = Vec::new();
 		loop
 		{
 			let ch = try!(self.getc());
-			if ch == '\'' {
+			if ch == quote {
 				break;
 			}
-			if ch == '\\' {
-				match try!(self.read_escape())
-				{
-				Some(c) => ret.push(c),
-				None => {},
+			let ch = if ch == '\\' {
+					match try!(self.read_escape())
+					{
+					Some(Escape::Char(c)) => c,
+					Some(Escape::Value(v)) => {
+						if v > encoding.max_unit() {
+							self.literal_errors.push(LiteralError::EscapeOutOfRange);
+						}
+						ret.push(v as u32);
+						continue ;
+						},
+					None => continue,
+					}
 				}
-			}
-			else {
-				ret.push( ch );
-			}
+				else {
+					ch
+				};
+			encoding.encode(ch, &mut ret);
 		}
-		match ret.len()
+		return Ok(ret);
+	}
+	// Read a double-quoted string (after the opening quote, `prefix` being the encoding prefix)
+	fn read_string(&mut self, encoding: Encoding, prefix: &str) -> super::Result<StringLit>
+	{
+		let caph = self.start_capture('"');
+		let rv = self.read_literal(encoding, '"');
+		let text = self.end_capture(caph);
+		Ok(StringLit {
+			encoding: encoding,
+			units: rv?,
+			spelling: Some(format!("{}{}", prefix, text)),
+			})
+	}
+	// Read a single-quoted character constant (after the opening quote)
+	fn read_charconst(&mut self, encoding: Encoding, prefix: &str) -> super::Result<CharLit>
+	{
+		let caph = self.start_capture('\'');
+		let rv = self.read_literal(encoding, '\'');
+		let text = self.end_capture(caph);
+		let units = rv?;
+		match units.len()
 		{
-		0 => Err( Error::MalformedLiteral("Empty chracter constant") ),
-		1 => Ok( ret.chars().next().unwrap() as u64 ),
-		_ => Err( Error::MalformedLiteral("Over-long character constant") ),
+		0 => self.literal_errors.push(LiteralError::Malformed("empty character constant")),
+		1 => {},
+		// Plain multi-character constants are `int`s (as GCC, up to four characters)
+		2 ... 4 if encoding == Encoding::Plain => self.literal_errors.push(LiteralError::MultiCharacter),
+		_ => self.literal_errors.push(LiteralError::CharacterTooLong),
 		}
+		Ok(CharLit {
+			encoding: encoding,
+			units: units,
+			spelling: Some(format!("{}{}", prefix, text)),
+			})
 	}
 
 	pub fn get_token_includestr(&mut self) -> super::Result<Option<String>>
@@ -250,14 +634,34 @@ impl<'a> Lexer<'a>
 			Ok( None )
 		}
 	}
-	// Read a single token from the stream
-	pub fn get_token(&mut self) -> super::Result<Token>
+	/// Read the rest of the line (without leading/trailing whitespace) as raw text, leaving the newline
+	pub fn get_token_restofline(&mut self) -> super::Result<String>
+	{
+		try_eof!(self.eat_whitespace(), String::new());
+		Ok( self.read_to_eol()?.trim_end().to_owned() )
+	}
+	// Read a single token from the stream, along with its location
+	pub fn get_token(&mut self) -> super::Result<(Token,Span)>
+	{
+		let start = self.pos;
+		let tok = self.get_token_inner()?;
+		let span = Span {
+			file: self.file,
+			offset: start.offset,
+			len: self.pos.offset - start.offset,
+			line: start.line,
+			column: start.column + 1,
+			expansion: None,
+			};
+		Ok( (tok, span) )
+	}
+	fn get_token_inner(&mut self) -> super::Result<Token>
 	{
 		if try_eof!(self.eat_whitespace(), Token::EOF) > 0 {
 			return Ok(Token::Whitespace);
 		}
 	
-		let mut ch = try_eof!(self.getc(), Token::EOF);
+		let ch = try_eof!(self.getc(), Token::EOF);
 		let ret = match ch
 		{
 		'\n' => Token::Newline,
@@ -275,15 +679,40 @@ impl<'a> Lexer<'a>
 		';' => Token::Semicolon,
 		',' => Token::Comma,
 		'?' => Token::QuestionMark,
-		':' => Token::Colon,
-		'^' => Token::Caret,
+		':' => match_ch!(self, Token::Colon,
+			'>' => Token::Digraph(Digraph::SquareClose),
+			),
+		'^' => match_ch!(self, Token::Caret,
+			'=' => Token::AssignBitXor,
+			),
 		'.' => match_ch!(self, Token::Period,
+			c @ '0' ... '9' => {
+				self.ungetc(c);
+				let caph = self.start_capture('.');
+				let rv = self.read_numeric('.');
+				let text = self.end_capture(caph);
+				match rv?
+				{
+				Number::Float(v, cls) => Token::Float(v, cls, text),
+				Number::Integer(..) => panic!("BUG: Number starting with '.' lexed as an integer"),
+				}
+				},
 			'.' => {
-				if try_eof!(self.getc(), Token::Period) != '.' {
-					error!("Lex error '..' hit");
-					return Err( Error::BadCharacter('.') );
+				let (before_second, after_second) = (self.prev_pos, self.pos);
+				match self.getc()
+				{
+				Ok('.') => Token::Vargs,
+				// `..` is two `.` tokens, so push back the second (and the character after it)
+				Ok(ch) => {
+					self.raw_pending.push(ch);
+					self.pos = after_second;
+					self.prev_pos = before_second;
+					self.ungetc('.');
+					Token::Period
+					},
+				Err(Error::EOF) => { self.ungetc('.'); Token::Period },
+				Err(e) => return Err(e),
 				}
-				Token::Vargs
 				},
 			),
 		'=' => match_ch!(self, Token::Assign,
@@ -299,23 +728,26 @@ impl<'a> Lexer<'a>
 			'>' => Token::DerefMember,
 			),
 		'>' => match_ch!(self, Token::Gt,
-			'>' => Token::ShiftRight,
+			'>' => match_ch!(self, Token::ShiftRight,
+				'=' => Token::AssignShiftRight,
+				),
 			'=' => Token::GtE,
 			),
 		'<' => match_ch!(self, Token::Lt,
-			'<' => Token::ShiftLeft,
+			'<' => match_ch!(self, Token::ShiftLeft,
+				'=' => Token::AssignShiftLeft,
+				),
 			'=' => Token::LtE,
+			// Digraphs `<:` and `<%`
+			':' => Token::Digraph(Digraph::SquareOpen),
+			'%' => Token::Digraph(Digraph::BraceOpen),
 			),
 		'|' => match_ch!(self, Token::Pipe,
-			'|' => match_ch!(self, Token::DoublePipe,
-				'=' => Token::AssignLogicOr,
-				),
+			'|' => Token::DoublePipe,
 			'=' => Token::AssignBitOr,
 			),
 		'&' => match_ch!(self, Token::Ampersand,
-			'&' => match_ch!(self, Token::DoubleAmpersand,
-				'=' => Token::AssignLogicAnd,
-				),
+			'&' => Token::DoubleAmpersand,
 			'=' => Token::AssignBitAnd,
 			),
 		'(' => Token::ParenOpen,	')' => Token::ParenClose,
@@ -323,6 +755,9 @@ impl<'a> Lexer<'a>
 		'[' => Token::SquareOpen,	']' => Token::SquareClose,
 		'%' => match_ch!(self, Token::Percent,
 			'=' => Token::AssignMod,
+			// Digraphs `%>` and `%:`
+			'>' => Token::Digraph(Digraph::BraceClose),
+			':' => Token::Digraph(Digraph::Hash),
 			),
 		'*' => match_ch!(self, Token::Star,
 			'=' => Token::AssignMul,
@@ -337,8 +772,8 @@ impl<'a> Lexer<'a>
 					'*' => {
 						match try_eof!(self.getc(), Token::BlockComment(comment)) {
 						'/' => break,
-						'*' => self.ungetc('*'),	// Handles '**/'
-						c @ _ => comment.push(c)
+						'*' => { comment.push('*'); self.ungetc('*') },	// Handles '**/'
+						c @ _ => { comment.push('*'); comment.push(c) },
 						}
 						},
 					c @ _ => comment.push(c)
@@ -348,95 +783,35 @@ impl<'a> Lexer<'a>
 				},
 			),
 
-		'"' => Token::String( try!(self.read_string()) ),
-		'\'' => Token::Character( try!(self.read_charconst()) ),
+		'"' => Token::String( try!(self.read_string(Encoding::Plain, "")) ),
+		'\'' => Token::Character( try!(self.read_charconst(Encoding::Plain, "")) ),
 		
 		'0' ... '9' => {
 			let caph = self.start_capture(ch);
-			let (base, whole) = if ch == '0' {
-					let ch2 = try_eof!(self.getc(), Token::Integer(0, ::types::IntClass::int(), self.end_capture(caph)));
-					match ch2 {
-					'1' ... '7' => {
-						self.ungetc(ch2);
-						(8, self.read_number( 8)?)
-						},
-					'x' => (16, try!(self.read_number(16))),
-					'b' => ( 2, try!(self.read_number( 2))),
-					_ => {
-						self.ungetc(ch2);
-						(10, 0)
-						}
-					}
-				}
-				else {
-					self.ungetc(ch);
-					(10, try!(self.read_number(10)))
-				};
-			// Check for a decimal point
-			let intret = |s: &mut Self, c| Token::Integer(whole, ::types::IntClass::int(), s.end_capture(c));
-			ch = try_eof!(self.getc(), intret(self,caph));
-			if ch == '.' || (base == 10 && (ch == 'e' || ch == 'E')) || (base == 16 && (ch == 'p' || ch == 'P'))
+			let rv = self.read_numeric(ch);
+			let text = self.end_capture(caph);
+			match rv?
 			{
-				// Floating point
-				if base != 10 && base != 16 {
-					return Err(super::Error::MalformedLiteral("Only hex and decimal floats are supported"));
-				}
-				let (frac_value, frac_len) = self.read_number_with_len(base)?;
-				let (exp_is_neg,exponent) = if match self.getc()
-						{
-						Ok('e') | Ok('E') if base != 16 => true,
-						Ok('p') | Ok('P') if base == 16 => true,
-						Ok(ch) => { self.ungetc(ch); false },
-						Err(Error::EOF) => false,
-						Err(e) => return Err(e),
-						}
-					{
-						// e, E, p or P were seen, parse the exponent (in base 10)
-						let is_neg = match self.getc()
-							{
-							Ok('-') => true,
-							Ok('+') => false,
-							Ok(ch) => { self.ungetc(ch); false },
-							Err(Error::EOF) => false,
-							Err(e) => return Err(e),
-							};
-						let (ev, el) = self.read_number_with_len(10)?;
-						if el == 0 { return Err(super::Error::MalformedLiteral("Exponent has no digits")); }
-						(is_neg, ev)
-					}
-					else
-					{
-						(false, 0)
-					};
-
-				let float_val = make_float(base, whole, frac_len, frac_value, exp_is_neg, exponent);
-				let ty = match self.getc()
-					{
-					Ok('f') | Ok('F') => ::types::FloatClass::Float,
-					Ok('l') | Ok('L') => ::types::FloatClass::LongDouble,
-					Ok(_) | Err(Error::EOF) => ::types::FloatClass::Double,
-					Err(e) => return Err(e),
-					};
-				Token::Float( float_val, ty, self.end_capture(caph) )
-			}
-			else
-			{
-				// Integer
-				let is_unsigned = ::types::Signedness::from_bool_signed(if ch=='u'||ch=='U' { ch = try_eof!(self.getc(), intret(self,caph)); true } else { false });
-				let is_long     = if ch=='l'||ch=='L' { ch = try_eof!(self.getc(), intret(self,caph)); true } else { false };
-				let is_longlong = if ch=='l'||ch=='L' { ch = try_eof!(self.getc(), intret(self,caph)); true } else { false };
-				self.ungetc(ch);
-				Token::Integer( whole, match (is_long,is_longlong) {
-					(false,false) => ::types::IntClass::Int(is_unsigned),
-					(true, false) => ::types::IntClass::Long(is_unsigned),
-					(true, true ) => ::types::IntClass::LongLong(is_unsigned),
-					(false, true) => panic!("BUGCHECK: LongLong set, but Long unset")
-					}, self.end_capture(caph) )
+			Number::Integer(v, cls) => Token::Integer(v, cls, text),
+			Number::Float(v, cls) => Token::Float(v, cls, text),
 			}
 			},
 		'a'...'z'|'A'...'Z'|'_'|'$' => {
 			self.ungetc(ch);
-			Token::Ident( try!(self.read_ident()) )
+			let name = try!(self.read_ident());
+			// An encoding prefix directly before a string literal or character constant
+			match Encoding::from_prefix(&name)
+			{
+			Some(enc) => match self.getc()
+				{
+				Ok('"') => Token::String( try!(self.read_string(enc, &name)) ),
+				Ok('\'') => Token::Character( try!(self.read_charconst(enc, &name)) ),
+				Ok(ch) => { self.ungetc(ch); Token::Ident(name) },
+				Err(Error::EOF) => Token::Ident(name),
+				Err(e) => return Err(e),
+				},
+			None => Token::Ident(name),
+			}
 			},
 		_ => {
 			error!("Bad character #{} hit", ch as u32);
@@ -448,6 +823,62 @@ impl<'a> Lexer<'a>
 	}
 }
 
+/// Parse an integer suffix, returning the `unsigned` flag and the number of `long`s
+///
+/// `u` can come before or after `l`/`ll`, and both `l`s must have the same case.
+fn parse_integer_suffix(suffix: &str) -> Option<(bool, u8)>
+{
+	let is_u = |c| c == 'u' || c == 'U';
+	let (is_unsigned, rest) = if suffix.starts_with(is_u) {
+			(true, &suffix[1..])
+		}
+		else if suffix.ends_with(is_u) {
+			(true, &suffix[..suffix.len()-1])
+		}
+		else {
+			(false, suffix)
+		};
+	match rest
+	{
+	"" => Some( (is_unsigned, 0) ),
+	"l" | "L" => Some( (is_unsigned, 1) ),
+	"ll" | "LL" => Some( (is_unsigned, 2) ),
+	_ => None,
+	}
+}
+
+/// Select the type of an integer constant (C11 6.4.4.1p5)
+///
+/// The type is the first of `int`, `long`, and `long long` (starting at the suffix's type) that can hold the value.
+/// Unsigned types are only used for constants with a `u` suffix, or if the constant is octal or hexadecimal.
+///
+/// A decimal constant without a suffix that doesn't fit in any signed type is given an unsigned type (as GCC does),
+/// which is indicated by the second return value.
+fn integer_type(value: u64, is_decimal: bool, is_unsigned: bool, n_longs: u8, long_is_64bit: bool) -> (::types::IntClass, bool)
+{
+	use types::IntClass;
+	use types::Signedness::{Signed,Unsigned};
+	let ranks: [(fn(::types::Signedness)->IntClass, u32); 3] = [
+		(IntClass::Int, 32),
+		(IntClass::Long, if long_is_64bit { 64 } else { 32 }),
+		(IntClass::LongLong, 64),
+		];
+	let candidates = &ranks[n_longs as usize ..];
+	for &(class, bits) in candidates
+	{
+		let unsigned_max = !0u64 >> (64 - bits);
+		if !is_unsigned && value <= unsigned_max >> 1 {
+			return (class(Signed), false);
+		}
+		if (is_unsigned || !is_decimal) && value <= unsigned_max {
+			return (class(Unsigned), false);
+		}
+	}
+	// Only a decimal constant without `u` can get here (`unsigned long long` can hold every value)
+	let &(class, _) = candidates.iter().find(|&&(_, bits)| bits == 64).expect("BUG: No 64-bit integer type");
+	(class(Unsigned), true)
+}
+
 pub(super) fn map_keywords(tok: Token) -> Token
 {
 	match tok
@@ -477,6 +908,7 @@ pub(super) fn map_keywords(tok: Token) -> Token
 		"double" => Token::Rword_double,
 		
 		"sizeof" => Token::Rword_sizeof,
+		"_Generic" => Token::Rword_Generic,
 		"enum"   => Token::Rword_enum,
 		"union"  => Token::Rword_union,
 		"struct" => Token::Rword_struct,
@@ -497,19 +929,9 @@ pub(super) fn map_keywords(tok: Token) -> Token
 		
 		_ => Token::Ident(ident)
 		},
+	Token::Digraph(d) => d.token(),
 	t => t,
 	}
 }
 
-fn make_float(base: u32, whole: u64, frac_len: usize, frac_value: u64, exp_is_neg: bool, exponent: u64) -> f64 //Float32_128
-{
-	if frac_value == 0 && exponent == 0 {
-		whole as f64
-	}
-	else {
-		// 1. Convert the fraction value into an ecoded fraction. (5 => 0x5 => 0x8, 123 => 0x7B => 0x0214D)
-		panic!("TODO: Create floating point values (base={}, whole={},frac={}:{},exponent={}{}", base, whole, frac_len, frac_value, if exp_is_neg { "-" } else { "" }, exponent);
-	}
-}
-
 // vim: ft=rust
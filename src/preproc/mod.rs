@@ -1,10 +1,20 @@
 //! C Pre-processor handling
-use std::collections::HashMap;
+use std::collections::{HashMap,HashSet};
 use std::default::Default;
 
 pub use self::token::Token;
+use self::token::Digraph;
+pub use self::span::{Span,FileId,SourceFile,Expansion};
+pub use self::predefined::Target;
 pub mod token;
+pub mod print;
+pub mod deps;
+pub mod source;
 mod lex;
+mod float;
+mod span;
+mod predefined;
+mod expr;
 
 #[derive(Debug)]
 pub enum Error
@@ -15,14 +25,298 @@ pub enum Error
 	IoError(::std::io::Error),
 	/// An unexpected character was encountered in the input stream
 	BadCharacter(char),
-	/// A literal (integer, float, string) wasn't able to be lexed correctly
-	MalformedLiteral(&'static str),
 	/// An unexpected EOF
 	UnexpectedEof,
+	/// A malformed or unsupported pre-processor directive
+	Directive(Span, DirectiveError),
+}
+
+impl Error
+{
+	/// Returns true if pre-processing cannot continue after this error
+	pub fn is_fatal(&self) -> bool
+	{
+		match *self
+		{
+		Error::EOF => true,
+		Error::IoError(_) => true,
+		Error::UnexpectedEof => true,
+		Error::Directive(_, DirectiveError::IncludeNotFound(_)) => true,
+		Error::Directive(_, DirectiveError::UnterminatedConditional) => true,
+		Error::Directive(_, DirectiveError::ErrorDirective(_)) => true,
+		_ => false,
+		}
+	}
+	/// Identifier used for machine-readable diagnostics
+	pub fn code(&self) -> &'static str
+	{
+		match *self
+		{
+		Error::EOF => "unexpected-eof",
+		Error::IoError(_) => "io-error",
+		Error::BadCharacter(_) => "bad-character",
+		Error::UnexpectedEof => "unexpected-eof",
+		Error::Directive(_, ref e) => e.code(),
+		}
+	}
+}
+impl ::std::fmt::Display for Error
+{
+	fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result
+	{
+		match *self
+		{
+		Error::EOF => f.write_str("Unexpected end of file"),
+		Error::IoError(ref e) => write!(f, "{}", e),
+		Error::BadCharacter(c) => write!(f, "Unexpected character {:?}", c),
+		Error::UnexpectedEof => f.write_str("Unexpected EOF in preprocessor"),
+		Error::Directive(_, ref e) => write!(f, "{}", e),
+		}
+	}
 }
 
 pub type Result<T> = ::std::result::Result<T,Error>;
 
+/// A position in a source file (used when reporting errors)
+#[derive(Debug,Clone,PartialEq)]
+pub struct Location
+{
+	/// Path to the file (`None` for stdin)
+	pub filename: Option<::std::path::PathBuf>,
+	pub line: usize,
+	pub column: usize,
+	/// Length of the located text in bytes (zero for a single point)
+	pub length: usize,
+}
+impl ::std::fmt::Display for Location
+{
+	fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result
+	{
+		match self.filename
+		{
+		Some(ref p) => write!(f, "{}:{}:{}", p.display(), self.line, self.column),
+		None => write!(f, "<stdin>:{}:{}", self.line, self.column),
+		}
+	}
+}
+
+/// Errors raised when handling pre-processor directives
+#[derive(Debug)]
+pub enum DirectiveError
+{
+	/// `#else` without a matching `#if`
+	UnmatchedElse,
+	/// `#elif` without a matching `#if`
+	UnmatchedElif,
+	/// `#endif` without a matching `#if`
+	UnmatchedEndif,
+	/// `#else` after an `#else`
+	DuplicateElse,
+	/// `#elif` after an `#else`
+	ElifAfterElse,
+	/// End of file reached within an `#if` block
+	UnterminatedConditional,
+	/// Unknown `#foo` directive
+	UnknownDirective(String),
+	/// Unexpected token within a directive
+	UnexpectedToken(Token),
+	/// `#include` not followed by `"file"` or `<file>`
+	BadInclude(Token),
+	/// The `#include`d file could not be found
+	IncludeNotFound(String),
+	/// Unknown `#pragma` (reported as a warning)
+	UnknownPragma(String),
+	/// `#error`
+	ErrorDirective(String),
+	/// `#warning` (reported as a warning)
+	WarningDirective(String),
+	/// `#` in a function-like macro not followed by a parameter
+	StringifyNonParameter,
+	/// `##` at the start or end of a replacement list
+	PasteAtEdge,
+	/// `__VA_OPT__` not followed by a parenthesised token list
+	BadVaOpt,
+	/// `##` didn't form a single valid token
+	InvalidPaste(String, String),
+	/// Malformed `#if`/`#elif` expression
+	BadIfExpression(String),
+	/// Division (or remainder) by zero in an `#if`/`#elif` expression
+	DivisionByZero,
+	/// `#include_next` in the main source file (reported as a warning, and treated as `#include`)
+	IncludeNextInPrimarySource,
+	/// Function-like macro invoked with the wrong number of arguments
+	MacroArgumentCount {
+		name: String,
+		expected: usize,
+		given: usize,
+	},
+}
+impl DirectiveError
+{
+	/// Identifier used for machine-readable diagnostics
+	pub fn code(&self) -> &'static str
+	{
+		match self
+		{
+		DirectiveError::UnmatchedElse => "unmatched-else",
+		DirectiveError::UnmatchedElif => "unmatched-elif",
+		DirectiveError::UnmatchedEndif => "unmatched-endif",
+		DirectiveError::DuplicateElse => "duplicate-else",
+		DirectiveError::ElifAfterElse => "elif-after-else",
+		DirectiveError::UnterminatedConditional => "unterminated-conditional",
+		DirectiveError::UnknownDirective(_) => "unknown-directive",
+		DirectiveError::UnexpectedToken(_) => "directive-syntax",
+		DirectiveError::BadInclude(_) => "bad-include",
+		DirectiveError::IncludeNotFound(_) => "include-not-found",
+		DirectiveError::UnknownPragma(_) => "unknown-pragma",
+		DirectiveError::ErrorDirective(_) => "error-directive",
+		DirectiveError::WarningDirective(_) => "warning-directive",
+		DirectiveError::StringifyNonParameter => "stringify-non-parameter",
+		DirectiveError::PasteAtEdge => "paste-at-edge",
+		DirectiveError::BadVaOpt => "bad-va-opt",
+		DirectiveError::InvalidPaste(..) => "invalid-paste",
+		DirectiveError::BadIfExpression(_) => "if-syntax",
+		DirectiveError::DivisionByZero => "division-by-zero",
+		DirectiveError::IncludeNextInPrimarySource => "include-next-in-primary-source",
+		DirectiveError::MacroArgumentCount { .. } => "macro-argument-count",
+		}
+	}
+}
+impl ::std::fmt::Display for DirectiveError
+{
+	fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result
+	{
+		match self
+		{
+		DirectiveError::UnmatchedElse => f.write_str("#else without #if"),
+		DirectiveError::UnmatchedElif => f.write_str("#elif without #if"),
+		DirectiveError::UnmatchedEndif => f.write_str("#endif without #if"),
+		DirectiveError::DuplicateElse => f.write_str("#else after #else"),
+		DirectiveError::ElifAfterElse => f.write_str("#elif after #else"),
+		DirectiveError::UnterminatedConditional => f.write_str("unterminated conditional directive"),
+		DirectiveError::UnknownDirective(n) => write!(f, "invalid preprocessing directive #{}", n),
+		DirectiveError::UnexpectedToken(t) => write!(f, "unexpected {:?} in directive", t),
+		DirectiveError::BadInclude(t) => write!(f, "#include expects \"FILENAME\" or <FILENAME>, got {:?}", t),
+		DirectiveError::IncludeNotFound(p) => write!(f, "{}: No such file or directory", p),
+		DirectiveError::UnknownPragma(n) => write!(f, "unknown pragma `{}`", n),
+		DirectiveError::ErrorDirective(m) => write!(f, "#error {}", m),
+		DirectiveError::WarningDirective(m) => write!(f, "#warning {}", m),
+		DirectiveError::StringifyNonParameter => f.write_str("'#' is not followed by a macro parameter"),
+		DirectiveError::PasteAtEdge => f.write_str("'##' cannot appear at either end of a macro expansion"),
+		DirectiveError::BadVaOpt => f.write_str("__VA_OPT__ must be followed by a parenthesised token list"),
+		DirectiveError::InvalidPaste(a, b) => write!(f, "pasting \"{}\" and \"{}\" does not give a valid preprocessing token", a, b),
+		DirectiveError::BadIfExpression(m) => f.write_str(m),
+		DirectiveError::DivisionByZero => f.write_str("division by zero in #if"),
+		DirectiveError::IncludeNextInPrimarySource => f.write_str("#include_next in primary source file"),
+		DirectiveError::MacroArgumentCount { ref name, expected, given } if given < expected =>
+			write!(f, "macro \"{}\" requires {} arguments, but only {} given", name, expected, given),
+		DirectiveError::MacroArgumentCount { ref name, expected, given } =>
+			write!(f, "macro \"{}\" passed {} arguments, but takes just {}", name, given, expected),
+		}
+	}
+}
+
+/// Problems in character constants and string literals (reported as diagnostics, the literal is still used)
+#[derive(Debug)]
+pub enum LiteralError
+{
+	/// Unknown escape sequence (a warning, the character is used as-is)
+	UnknownEscape(char),
+	/// `\x` not followed by a hex digit
+	MissingHexDigits,
+	/// Octal or hex escape too large for the character type (a warning, the value is truncated)
+	EscapeOutOfRange,
+	/// `\u` or `\U` without enough hex digits
+	IncompleteUniversalCharacter,
+	/// Universal character name (as written) for a surrogate, a value above U+10FFFF, or a basic character (C11 6.4.3p2)
+	InvalidUniversalCharacter(String),
+	/// Plain character constant with two to four characters (a warning, the value is implementation-defined)
+	MultiCharacter,
+	/// Character constant with more characters than fit its type (a warning, the leading characters are dropped)
+	CharacterTooLong,
+	/// Digit not valid for the base of an integer constant (e.g. `8` in an octal constant)
+	InvalidDigit(char, u32),
+	/// Floating constant too large for its type (a warning, the value is infinity)
+	FloatOverflow(::types::FloatClass),
+	/// Non-zero floating constant too small for its type (a warning, the value is zero)
+	FloatUnderflow,
+	/// Unknown suffix on a numeric constant (with a flag set for floating constants)
+	InvalidSuffix(String, bool),
+	/// Integer constant too large for `unsigned long long` (the value is truncated)
+	IntegerTooLarge,
+	/// Decimal integer constant too large for any signed type (a warning, it's given an unsigned type)
+	SoLargeItIsUnsigned,
+	/// A constant that can't be read (e.g. `0x` or `1e`, the value is zero)
+	Malformed(&'static str),
+}
+impl LiteralError
+{
+	/// Returns true if the problem is only a warning
+	pub fn is_warning(&self) -> bool
+	{
+		match self
+		{
+		LiteralError::UnknownEscape(_) => true,
+		LiteralError::EscapeOutOfRange => true,
+		LiteralError::MultiCharacter => true,
+		LiteralError::CharacterTooLong => true,
+		LiteralError::FloatOverflow(_) => true,
+		LiteralError::FloatUnderflow => true,
+		LiteralError::SoLargeItIsUnsigned => true,
+		_ => false,
+		}
+	}
+	/// Identifier used for machine-readable diagnostics
+	pub fn code(&self) -> &'static str
+	{
+		match self
+		{
+		LiteralError::UnknownEscape(_) => "unknown-escape",
+		LiteralError::MissingHexDigits => "missing-hex-digits",
+		LiteralError::EscapeOutOfRange => "escape-out-of-range",
+		LiteralError::IncompleteUniversalCharacter => "incomplete-ucn",
+		LiteralError::InvalidUniversalCharacter(_) => "invalid-ucn",
+		LiteralError::MultiCharacter => "multichar",
+		LiteralError::CharacterTooLong => "char-too-long",
+		LiteralError::InvalidDigit(..) => "invalid-digit",
+		LiteralError::FloatOverflow(_) => "float-overflow",
+		LiteralError::FloatUnderflow => "float-underflow",
+		LiteralError::InvalidSuffix(..) => "invalid-suffix",
+		LiteralError::IntegerTooLarge => "integer-too-large",
+		LiteralError::SoLargeItIsUnsigned => "implicitly-unsigned-literal",
+		LiteralError::Malformed(_) => "malformed-literal",
+		}
+	}
+}
+impl ::std::fmt::Display for LiteralError
+{
+	fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result
+	{
+		match self
+		{
+		LiteralError::UnknownEscape(c) => write!(f, "unknown escape sequence '\\{}'", c),
+		LiteralError::MissingHexDigits => f.write_str("\\x used with no following hex digits"),
+		LiteralError::EscapeOutOfRange => f.write_str("escape sequence out of range"),
+		LiteralError::IncompleteUniversalCharacter => f.write_str("incomplete universal character name"),
+		LiteralError::InvalidUniversalCharacter(s) => write!(f, "{} is not a valid universal character", s),
+		LiteralError::MultiCharacter => f.write_str("multi-character character constant"),
+		LiteralError::CharacterTooLong => f.write_str("character constant too long for its type"),
+		LiteralError::InvalidDigit(c, base) => write!(f, "invalid digit \"{}\" in {} constant", c, if *base == 8 { "octal" } else { "binary" }),
+		LiteralError::FloatOverflow(cls) => write!(f, "floating constant exceeds range of '{}'", match cls
+			{
+			::types::FloatClass::Float => "float",
+			::types::FloatClass::Double => "double",
+			::types::FloatClass::LongDouble => "long double",
+			}),
+		LiteralError::FloatUnderflow => f.write_str("floating constant truncated to zero"),
+		LiteralError::InvalidSuffix(s, is_float) => write!(f, "invalid suffix \"{}\" on {} constant", s, if *is_float { "floating" } else { "integer" }),
+		LiteralError::IntegerTooLarge => f.write_str("integer constant is too large for its type"),
+		LiteralError::SoLargeItIsUnsigned => f.write_str("integer constant is so large that it is unsigned"),
+		LiteralError::Malformed(s) => f.write_str(s),
+		}
+	}
+}
+
 
 trait ReadExt: ::std::io::Read {
 	fn chars(self) -> ::utf8reader::UTF8Reader<Self> where Self: Sized;
@@ -39,12 +333,29 @@ pub struct Preproc
 	lexers: TokenSourceStack,
 	/// Marker used to know if `#foo` should be parsed (i.e. we're at the start of a line)
 	start_of_line: bool,
+	/// Set while expanding an `#if`/`#elif` expression (enables `defined` and the `__has_*` operators)
+	in_if_expr: bool,
+	/// Set while `expand_argument` is running (macro invocations within are never wrapped, see `wrap_define_expansion`)
+	in_argument: bool,
 	/// Saved token for `put_back`
-	saved_tok: Option<Token>,
+	saved_tok: Option<(Token,Span)>,
+	/// Location of the last token returned by `get_token`
+	last_span: Span,
 	/// Parsed macros
 	macros: HashMap<String,MacroDefinition>,
 	/// Stack of active `#if`/`#else` statements
 	if_stack: Vec<Conditional>,
+	/// Value of the next `__COUNTER__`
+	counter: ::std::cell::Cell<u64>,
+	/// Values of `__DATE__` and `__TIME__`
+	build_date: String,
+	build_time: String,
+	/// Warnings raised since the last `take_diagnostics`
+	diagnostics: Vec<::diagnostics::Diagnostic>,
+	/// Location of the first conditional directive not yet passed on (see `Options::propagate_conditionals`)
+	conditional_start: Option<Span>,
+	/// Text of the main file (read for `Options::propagate_conditionals`)
+	main_source: Option<String>,
 
 	/// User-provided pre-processor options
 	options: Options,
@@ -61,7 +372,29 @@ pub struct Options
 	/// Return (most) comments in the tokens steam. This still strips comments that would impede preprocessing
 	pub return_most_comments: bool,
 
+	/// `-iquote` directories (only searched for `#include "..."`)
+	pub quote_paths: Vec<::std::path::PathBuf>,
+	/// `-I` directories
 	pub include_paths: Vec<::std::path::PathBuf>,
+	/// `-isystem` directories (searched after `-I`, headers found in them are system headers)
+	pub system_paths: Vec<::std::path::PathBuf>,
+	/// `-idirafter` directories (searched last, also system directories)
+	pub after_paths: Vec<::std::path::PathBuf>,
+	/// Target profile (selects the predefined macros)
+	pub target: Target,
+	/// Accept C23 digit separators in numeric constants (`1'000'000`)
+	pub digit_separators: bool,
+	/// Replace trigraphs (`-trigraphs`)
+	pub trigraphs: bool,
+	/// Return unknown `#pragma`s as `Preprocessor::Pragma` tokens (instead of warning), so `-E` can pass them on
+	pub propagate_pragmas: bool,
+	/// Return the conditional directives in the main file, and the groups they skip, as `Preprocessor::Conditional`
+	/// tokens (for round-trip mode)
+	pub propagate_conditionals: bool,
+	/// `-D`, `-U`, and `-include` options (in command-line order)
+	pub command_line: Vec<CommandLineOp>,
+	/// Provider of file contents (the main file, and all `#include`s)
+	pub sources: ::std::rc::Rc<source::SourceProvider>,
 }
 impl ::std::default::Default for Options {
 	fn default() -> Self {
@@ -71,10 +404,55 @@ impl ::std::default::Default for Options {
 			wrap_define_expansion: false,
 			return_most_comments: false,
 
+			quote_paths: Vec::new(),
 			include_paths: Vec::new(),
+			system_paths: Vec::new(),
+			after_paths: Vec::new(),
+			target: Target::default(),
+			digit_separators: false,
+			trigraphs: false,
+			propagate_pragmas: false,
+			propagate_conditionals: false,
+			command_line: Vec::new(),
+			sources: ::std::rc::Rc::new(source::FileSystem),
 			}
 	}
 }
+impl Options
+{
+	/// Settings for the lexer
+	fn lex_options(&self) -> lex::Options
+	{
+		lex::Options {
+			digit_separators: self.digit_separators,
+			long_is_64bit: self.target.is_64bit(),
+			trigraphs: self.trigraphs,
+			}
+	}
+	/// Get the include search path, in GCC's order (`-iquote`, `-I`, `-isystem`, then `-idirafter`)
+	///
+	/// Returns each directory with a flag set for system directories, and the index of the first directory that
+	/// `#include <...>` searches.
+	fn search_path(&self) -> (Vec<(&::std::path::Path, bool)>, usize)
+	{
+		let rv: Vec<_> = Iterator::chain(
+			self.quote_paths.iter().chain(self.include_paths.iter()).map(|p| (p.as_path(), false)),
+			self.system_paths.iter().chain(self.after_paths.iter()).map(|p| (p.as_path(), true)),
+			).collect();
+		(rv, self.quote_paths.len())
+	}
+}
+/// A command-line option that changes the pre-processor's initial state
+#[derive(Debug,Clone)]
+pub enum CommandLineOp
+{
+	/// `-D NAME`, `-D NAME=value`, or `-D NAME(args)=body`
+	Define(String),
+	/// `-U NAME`
+	Undefine(String),
+	/// `-include file`
+	Include(::std::path::PathBuf),
+}
 #[allow(dead_code)]
 #[derive(PartialEq)]
 pub enum Handling
@@ -87,11 +465,15 @@ pub enum Handling
 	PropagateOnly,
 }
 
+#[derive(Clone)]
 struct MacroDefinition
 {
+	/// Location of the macro's name in the definition
+	span: Span,
 	arg_names: Option<MacroArgs>,
 	expansion: Vec<Token>,
 }
+#[derive(Clone)]
 struct MacroArgs
 {
 	names: Vec<String>,
@@ -116,6 +498,37 @@ impl Conditional {
 struct TokenSourceStack
 {
 	lexers: Vec<InnerLexer>,
+	/// All files opened (indexed by `FileId`)
+	files: Vec<SourceFile>,
+	/// Files opened by `#include` (in order)
+	included: Vec<FileId>,
+	/// Location of the last token returned by `get_token`
+	last_span: Span,
+	/// Hide set of the last token returned by `get_token`
+	last_hideset: HideSet,
+	/// Token pushed back by `put_back`
+	saved: Option<(Token,Span,HideSet)>,
+	/// Canonical paths of files that have used `#pragma once`
+	once_files: HashSet<::std::path::PathBuf>,
+	/// Guard macros for files that matched the include guard pattern (by canonical path)
+	include_guards: HashMap<::std::path::PathBuf, String>,
+	/// Provider of file contents (shared with `Options::sources`)
+	sources: ::std::rc::Rc<source::SourceProvider>,
+	/// Settings for lexers of included files
+	lex_options: lex::Options,
+	/// Problems found in literals read from files (taken by `Preproc::report_literal_errors`)
+	literal_errors: Vec<(Span, LiteralError)>,
+	/// Files entered and left (taken by `Preproc::take_file_changes`)
+	file_changes: Vec<FileChange>,
+}
+/// Start or end of an `#include`d file
+#[derive(Debug)]
+pub(super) enum FileChange
+{
+	/// Started reading the file
+	Enter(FileId),
+	/// Returned to the including file, at the start of the given (presumed) line
+	Leave(FileId, usize),
 }
 enum InnerLexer
 {
@@ -125,72 +538,379 @@ enum InnerLexer
 struct LexHandle
 {
 	lexer: lex::Lexer<'static>,
+	/// Path of the file being read (`None` for stdin)
+	path: Option<::std::path::PathBuf>,
+	/// Presumed file name (changed by `#line`)
 	filename: Option<::std::path::PathBuf>,
+	/// Presumed line number
 	line: usize,
+	/// Canonical path of the file (used to key `#pragma once` and include guards)
+	canonical_path: Option<::std::path::PathBuf>,
+	/// Include guard detection state
+	guard: IncludeGuard,
+	/// Index of the search path directory the file was found in (used by `#include_next`)
+	search_dir: Option<usize>,
+}
+/// Result of searching for an `#include`d file
+struct FoundInclude
+{
+	path: ::std::path::PathBuf,
+	/// Index of the search path directory (`None` if found relative to the current file)
+	search_dir: Option<usize>,
+	is_system: bool,
+}
+/// Detection of the `#ifndef X` / `#define X` ... `#endif` include guard pattern (the multiple-include optimisation)
+///
+/// A file is guarded if the first thing in it is `#ifndef X`, and the matching `#endif` is the last thing in it (other
+/// than whitespace and comments). Once such a file has been read, re-including it is skipped while `X` is defined.
+#[derive(Debug)]
+enum IncludeGuard
+{
+	/// Nothing seen yet
+	Start,
+	/// Within the guarding `#ifndef` (which sits at the given `#if` stack depth)
+	Open(String, usize),
+	/// After the guard's `#endif`
+	Closed(String),
+	/// Not a guarded file
+	Unguarded,
+}
+impl IncludeGuard
+{
+	/// Something other than whitespace/comments seen (ignored within the guard)
+	fn invalidate(&mut self)
+	{
+		match *self
+		{
+		IncludeGuard::Open(..) => {},
+		_ => *self = IncludeGuard::Unguarded,
+		}
+	}
+	/// `#ifndef name`, about to be pushed at `depth`
+	fn ifndef(&mut self, name: &str, depth: usize)
+	{
+		match *self
+		{
+		IncludeGuard::Start => *self = IncludeGuard::Open(name.to_owned(), depth),
+		_ => self.invalidate(),
+		}
+	}
+	/// `#else`/`#elif` for the conditional at `depth` (a guard can't have an else branch)
+	fn else_branch(&mut self, depth: usize)
+	{
+		match *self
+		{
+		IncludeGuard::Open(_, d) if d == depth => *self = IncludeGuard::Unguarded,
+		_ => {},
+		}
+	}
+	/// `#endif` popped the conditional at `depth`
+	fn endif(&mut self, depth: usize)
+	{
+		let name = match *self
+			{
+			IncludeGuard::Open(ref name, d) if d == depth => name.clone(),
+			_ => return,
+			};
+		*self = IncludeGuard::Closed(name);
+	}
 }
 struct MacroExpansion
 {
 	name: String,
+	/// Location given to all tokens from this expansion
+	span: Span,
 	idx: usize,
-	tokens: ::std::vec::IntoIter<Token>,	// TODO: Instead store Rc<Vec<Token>> to MacroDefinition.expansion and HashMap<String,Vec<Tokens>>
+	tokens: ::std::vec::IntoIter<(Token,HideSet)>,	// TODO: Instead store Rc<Vec<Token>> to MacroDefinition.expansion and HashMap<String,Vec<Tokens>>
+	/// A macro argument being pre-expanded, reading stops at the end of these tokens (instead of continuing with the
+	/// rest of the file)
+	is_argument: bool,
+}
+/// Arguments to a function-like macro (by parameter name)
+type MacroArgTokens<'a> = HashMap<&'a str, Vec<(Token,HideSet)>>;
+/// Names of the macros that a token was produced by, and so must not be expanded from it again (C11 6.10.3.4p2)
+///
+/// A name in a token's own hide set is "painted blue", and is never expanded even if it's later rescanned elsewhere.
+#[derive(Clone,Debug,Default,PartialEq)]
+struct HideSet(::std::rc::Rc<Vec<String>>);
+impl HideSet
+{
+	fn contains(&self, name: &str) -> bool
+	{
+		self.0.iter().any(|n| n == name)
+	}
+	fn with(&self, name: &str) -> HideSet
+	{
+		if self.contains(name) {
+			self.clone()
+		}
+		else {
+			let mut v = (*self.0).clone();
+			v.push(name.to_owned());
+			HideSet(::std::rc::Rc::new(v))
+		}
+	}
+	fn union(&self, other: &HideSet) -> HideSet
+	{
+		other.0.iter().fold(self.clone(), |hs, n| hs.with(n))
+	}
+	fn intersection(&self, other: &HideSet) -> HideSet
+	{
+		HideSet(::std::rc::Rc::new( self.0.iter().filter(|n| other.contains(n)).cloned().collect() ))
+	}
 }
 
-macro_rules! syntax_assert{ ($tok:expr, $pat:pat => $val:expr) => ({ let v = try!($tok); match v {
+macro_rules! syntax_assert{ ($self_:ident, $tok:expr, $pat:pat => $val:expr) => ({ let v = try!($tok); match v {
 	$pat => $val,
-	_ => panic!("TODO: Syntax errors, assert {}, got {:?}", stringify!($pat), v)
+	_ => return Err($self_.directive_error(DirectiveError::UnexpectedToken(v))),
 	}})}
 
 impl Preproc
 {
 	pub fn new(filename: Option<&::std::path::Path>, options: Options) -> Result<Preproc>
 	{
+		let file_id = FileId(0);
 		let lexer = if let Some(filename) = filename
 			{
-				lex::Lexer::new(box match ::std::fs::File::open(filename)
+				lex::Lexer::new(box match options.sources.open(filename)
 					{
 					Ok(f) => ::std::io::BufReader::new(f).chars(),
 					Err(e) => return Err(Error::IoError(e)),
-					})
+					}, file_id, options.lex_options())
 			}
 			else
 			{
-				lex::Lexer::new(box ::std::io::stdin().chars())
+				lex::Lexer::new(box ::std::io::stdin().chars(), file_id, options.lex_options())
+			};
+		// `__DATE__`/`__TIME__` use SOURCE_DATE_EPOCH if set (for reproducible builds)
+		let now = match ::std::env::var("SOURCE_DATE_EPOCH").ok().and_then(|v| v.parse().ok())
+			{
+			Some(v) => v,
+			None => ::std::time::SystemTime::now().duration_since(::std::time::UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0),
 			};
-		Ok(Preproc {
-			lexers: TokenSourceStack::new( lexer, filename.map(|x| x.to_owned()) ),
+		let (build_date, build_time) = predefined::format_date_time(now);
+		let mut rv = Preproc {
+			lexers: TokenSourceStack::new( lexer, filename.map(|x| x.to_owned()), options.sources.clone(), options.lex_options() ),
 			start_of_line: true,
+			in_if_expr: false,
+			in_argument: false,
 			saved_tok: None,
+			last_span: Span::start_of(file_id),
 			macros: Default::default(),
 			if_stack: Default::default(),
+			counter: ::std::cell::Cell::new(0),
+			build_date: build_date,
+			build_time: build_time,
+			diagnostics: Vec::new(),
+			conditional_start: None,
+			main_source: None,
 			options: options,
-			})
+			};
+		rv.add_predefined_macros();
+		rv.push_command_line();
+		Ok(rv)
+	}
+
+	/// Define the predefined macros for the selected target
+	fn add_predefined_macros(&mut self)
+	{
+		let file_id = self.lexers.add_file(Some("<built-in>".into()), None);
+		for (name, value) in self.options.target.predefined_macros()
+		{
+			let mut lex = lex::Lexer::new(box value.chars().map(Ok), file_id, self.options.lex_options());
+			let mut expansion = Vec::new();
+			loop
+			{
+				match lex.get_token().expect("BUG: Predefined macro value failed to lex").0
+				{
+				Token::EOF => break,
+				Token::Whitespace => {},
+				t => expansion.push(t),
+				}
+			}
+			self.macros.insert(name.to_owned(), MacroDefinition { span: Span::start_of(file_id), arg_names: None, expansion: expansion });
+		}
 	}
 
-	pub fn parse_define_str(&mut self, s: &str) -> Result<()>
+	/// Returns true if the named macro is defined (including built-in macros)
+	fn is_defined(&self, name: &str) -> bool
 	{
-		let mut lex = lex::Lexer::new(box s.chars().map(Ok));
+		self.macros.contains_key(name) || predefined::DYNAMIC_MACROS.contains(&name) || predefined::IF_OPERATORS.contains(&name)
+	}
 
-		let ident = syntax_assert!(lex.get_token(), Token::Ident(n) => n);
-		match lex.get_token()?
+	/// Returns true if including this file would have no effect (`#pragma once`, or its include guard is defined)
+	fn is_include_skipped(&self, path: &::std::path::Path) -> bool
+	{
+		let path = self.options.sources.canonicalise(path);
+		if self.lexers.once_files.contains(&path) {
+			return true;
+		}
+		match self.lexers.include_guards.get(&path)
 		{
-		Token::EOF => {
-			self.macros.insert(ident, MacroDefinition { arg_names: None, expansion: Vec::new() });
-			Ok( () )
-			},
-		Token::Assign => {
-			panic!("TODO: Define to a value from string");
-			},
-		Token::ParenOpen => {
-			panic!("TODO: Define function-like from a string");
-			},
-		t => panic!("TODO: error for bad token {:?} in define string", t),
+		Some(name) => self.is_defined(name),
+		None => false,
+		}
+	}
+
+	/// Locate an `#include`d file
+	///
+	/// For `#include_next` (`is_next`), the search continues from the directory after the one the current file was
+	/// found in. If the current file wasn't found using the search path, this is the same as `#include`.
+	fn find_include(&self, name: &str, was_angle: bool, is_next: bool) -> Option<FoundInclude>
+	{
+		let (dirs, bracket_start) = self.options.search_path();
+		let next_start = if is_next { self.lexers.cur_file().search_dir.map(|i| i + 1) } else { None };
+		let start = match next_start
+			{
+			Some(i) => i,
+			None if was_angle => bracket_start,
+			None => {
+				// `#include "foo"` checks the current file's directory first (and a header found there is a system
+				// header if the includer is)
+				let mut p = self.lexers.cur_path().and_then(|p| p.parent()).unwrap_or(::std::path::Path::new(".")).to_owned();
+				p.push(name);
+				if self.options.sources.is_file(&p) {
+					return Some(FoundInclude { path: p, search_dir: None, is_system: self.lexers.cur_is_system() });
+				}
+				0
+				},
+			};
+		// Search the include directories for the first entry that contains the specified file
+		dirs.iter().enumerate().skip(start)
+			.map(|(i, &(dir, is_system))| FoundInclude { path: dir.join(name), search_dir: Some(i), is_system: is_system })
+			.filter(|f| self.options.sources.is_file(&f.path))
+			.next()
+	}
+
+	/// Push the command-line `-D`/`-U`/`-include` options as a source buffer (named `<command-line>`, like GCC)
+	///
+	/// `-D` and `-U` are applied in the order given, then all `-include` files are included in order.
+	fn push_command_line(&mut self)
+	{
+		if self.options.command_line.is_empty() {
+			return ;
+		}
+		let mut buf = String::new();
+		for op in &self.options.command_line
+		{
+			match *op
+			{
+			CommandLineOp::Define(ref s) => {
+				buf.push_str(&Self::parse_define_str(s));
+				buf.push('\n');
+				},
+			CommandLineOp::Undefine(ref name) => {
+				buf.push_str(&format!("#undef {}\n", name));
+				},
+			CommandLineOp::Include(_) => {},
+			}
+		}
+		for op in &self.options.command_line
+		{
+			if let CommandLineOp::Include(ref p) = *op
+			{
+				// NOTE: The `"..."` form searches the working directory first (the buffer has no directory)
+				buf.push_str(&format!("#include {}\n", Token::String(p.display().to_string().into())));
+			}
+		}
+		debug!("Command-line buffer: {:?}", buf);
+		self.lexers.push_buffer("<command-line>".into(), buf);
+	}
+
+	/// Convert a `-D` argument (`NAME`, `NAME=value`, or `NAME(args)=body`) into a `#define` line
+	fn parse_define_str(s: &str) -> String
+	{
+		// Only the first line is used (as GCC does)
+		let s = s.lines().next().unwrap_or("");
+		match s.find('=')
+		{
+		Some(i) => format!("#define {} {}", &s[..i], &s[i+1..]),
+		None => format!("#define {} 1", s),
 		}
 	}
 
 	pub fn put_back(&mut self, tok: Token)
 	{
 		assert!( self.saved_tok.is_none() );
-		self.saved_tok = Some( tok );
+		self.saved_tok = Some( (tok, self.last_span.clone()) );
+	}
+
+	/// Location of the last token returned by `get_token`
+	pub fn span(&self) -> &Span
+	{
+		&self.last_span
+	}
+	/// Returns true if `span` is within the file being pre-processed (not an `#include`d or `-include` file)
+	pub fn is_main_file(&self, span: &Span) -> bool
+	{
+		self.lexers.files[span.file.0].source == FileId(0)
+	}
+	/// Location of the next token to be returned by `get_token`
+	pub fn peek_span(&mut self) -> Result<Span>
+	{
+		if self.saved_tok.is_none() {
+			let tok = self.get_token()?;
+			self.put_back(tok);
+		}
+		Ok( self.saved_tok.as_ref().unwrap().1.clone() )
+	}
+
+	/// Files read so far, for dependency output (the main file, then each `#include`d file in the order first included)
+	pub fn dependencies(&self) -> Vec<deps::Dependency>
+	{
+		let mut rv: Vec<deps::Dependency> = Vec::new();
+		for id in ::std::iter::once(FileId(0)).chain(self.lexers.included.iter().cloned())
+		{
+			let f = self.source_file(id);
+			if let Some(ref p) = f.path
+			{
+				if !rv.iter().any(|d| d.path == *p) {
+					rv.push(deps::Dependency { path: p.clone(), is_system: f.is_system_header });
+				}
+			}
+		}
+		rv
+	}
+	/// Information about an opened source file
+	pub fn source_file(&self, id: FileId) -> &SourceFile
+	{
+		&self.lexers.files[id.0]
+	}
+	/// Convert a span into a printable location
+	pub fn location_of(&self, span: &Span) -> Location
+	{
+		Location {
+			filename: self.source_file(span.file).path.clone(),
+			line: span.line,
+			column: span.column,
+			length: span.len,
+			}
+	}
+	/// Locations of the `#include`s that lead to the file containing `span` (innermost first)
+	pub fn include_stack(&self, span: &Span) -> Vec<Location>
+	{
+		let mut rv = Vec::new();
+		let mut file = span.file;
+		while let Some(ref inc) = self.source_file(file).included_from
+		{
+			rv.push( self.location_of(inc) );
+			file = inc.file;
+		}
+		rv
+	}
+	/// Create a diagnostic at the given span, including the include stack and macro expansion backtrace
+	pub fn diagnostic(&self, severity: ::diagnostics::Severity, code: &'static str, span: &Span, message: String) -> ::diagnostics::Diagnostic
+	{
+		let mut rv = ::diagnostics::Diagnostic::new(severity, code, message);
+		rv.location = Some( self.location_of(span) );
+		rv.include_stack = self.include_stack(span);
+		for exp in span.expansions()
+		{
+			let mut note = ::diagnostics::Diagnostic::new(::diagnostics::Severity::Note, "macro-expansion", format!("expanded from macro '{}'", exp.name));
+			note.location = Some( self.location_of(&exp.definition) );
+			rv.notes.push(note);
+		}
+		rv
 	}
 
 	fn eat_comments(&mut self) -> Result<Token>
@@ -198,6 +918,116 @@ impl Preproc
 		self.lexers.get_token_nospace()
 	}
 
+	fn directive_error(&self, kind: DirectiveError) -> Error
+	{
+		Error::Directive(self.lexers.last_span.clone(), kind)
+	}
+	/// Queue a warning (returned by `take_diagnostics`)
+	fn directive_warning(&mut self, span: &Span, kind: DirectiveError)
+	{
+		let d = self.diagnostic(::diagnostics::Severity::Warning, kind.code(), span, kind.to_string());
+		self.diagnostics.push(d);
+	}
+	/// Get the `Preprocessor::Conditional` token for the conditional directives just processed (and the groups they
+	/// skipped), once the following text is active
+	fn finish_conditional(&mut self) -> Option<(Token,Span,HideSet)>
+	{
+		if self.conditional_start.is_none() || !self.is_conditional_active() || self.in_if_expr {
+			return None;
+		}
+		let start = self.conditional_start.take().unwrap();
+		// The directive's newline was the last token read
+		let end = self.lexers.last_span.offset + self.lexers.last_span.len;
+		if self.main_source.is_none() {
+			let mut text = String::new();
+			let path = self.lexers.files[0].path.clone()?;
+			self.options.sources.open(&path).and_then(|mut f| ::std::io::Read::read_to_string(&mut f, &mut text)).ok()?;
+			self.main_source = Some(text);
+		}
+		let text = self.main_source.as_ref().unwrap().get(start.offset .. end)?.to_owned();
+		let span = Span { len: end - start.offset, .. start };
+		Some( (token::Preprocessor::Conditional(text).into(), span, HideSet::default()) )
+	}
+	/// Report problems found in literals (they're ignored in skipped groups)
+	fn report_literal_errors(&mut self, is_active: bool)
+	{
+		for (span, e) in ::std::mem::replace(&mut self.lexers.literal_errors, Vec::new())
+		{
+			if is_active {
+				let severity = if e.is_warning() { ::diagnostics::Severity::Warning } else { ::diagnostics::Severity::Error };
+				let d = self.diagnostic(severity, e.code(), &span, e.to_string());
+				self.diagnostics.push(d);
+			}
+		}
+	}
+	/// Take the (non-fatal) diagnostics raised since the last call
+	pub fn take_diagnostics(&mut self) -> Vec<::diagnostics::Diagnostic>
+	{
+		::std::mem::replace(&mut self.diagnostics, Vec::new())
+	}
+	/// Take the `#include`d files entered and left since the last call (used for `-E` linemarkers)
+	pub(super) fn take_file_changes(&mut self) -> Vec<FileChange>
+	{
+		::std::mem::replace(&mut self.lexers.file_changes, Vec::new())
+	}
+
+	/// Handle the body of a `#pragma` line or `_Pragma` operator
+	///
+	/// Returns the token to pass on for pragmas that aren't handled here (see `Options::propagate_pragmas`)
+	fn handle_pragma(&mut self, body: &str, span: &Span) -> Result<Option<Token>>
+	{
+		// Lex the body, keeping its spelling (with whitespace and comments reduced to single spaces)
+		let mut lex = lex::Lexer::new(box body.chars().collect::<Vec<_>>().into_iter().map(Ok), span.file, self.options.lex_options());
+		let mut tokens = Vec::new();
+		let mut text = String::new();
+		loop
+		{
+			match lex.get_token()?.0
+			{
+			Token::EOF => break,
+			Token::Whitespace | Token::EscapedNewline | Token::LineComment(_) | Token::BlockComment(_) => {
+				if ! text.is_empty() && ! text.ends_with(' ') {
+					text.push(' ');
+				}
+				},
+			t => {
+				text.push_str(&t.to_string());
+				tokens.push(t);
+				},
+			}
+		}
+		let text = text.trim_end().to_owned();
+
+		match tokens.first()
+		{
+		None => {},
+		Some(Token::Ident(ref name)) if name == "once" => {
+			if tokens.len() > 1 {
+				return Err(self.directive_error(DirectiveError::UnexpectedToken(tokens[1].clone())));
+			}
+			self.lexers.set_once();
+			// TODO: If enabled, propagate a token::Preprocessor::PragmaOnce
+			},
+		Some(_) if self.options.propagate_pragmas => {
+			return Ok(Some( token::Preprocessor::Pragma(text).into() ));
+			},
+		Some(_) => {
+			self.directive_warning(span, DirectiveError::UnknownPragma(text));
+			},
+		}
+		Ok( None )
+	}
+	/// Handle a `_Pragma ( "..." )` operator (the `_Pragma` has already been read)
+	fn handle_pragma_operator(&mut self, span: &Span) -> Result<Option<Token>>
+	{
+		syntax_assert!(self, self.lexers.get_token_nospace(), Token::ParenOpen => ());
+		let text = syntax_assert!(self, self.lexers.get_token_nospace(), Token::String(s) => s.to_string_lossy().into_owned());
+		syntax_assert!(self, self.lexers.get_token_nospace(), Token::ParenClose => ());
+
+		// De-stringify (escapes were already handled by the lexer) and handle as if it were a `#pragma` line
+		self.handle_pragma(&text, span)
+	}
+
 	fn is_conditional_active(&self) -> bool
 	{
 		self.if_stack.iter()
@@ -208,31 +1038,46 @@ impl Preproc
 	{
 		if self.saved_tok.is_some()
 		{
-			let tok = self.saved_tok.take().unwrap();
+			let (tok, span) = self.saved_tok.take().unwrap();
 			trace!("get_token = {:?} (saved)", tok);
+			self.last_span = span;
 			Ok( tok )
 		}
 		else
 		{
-			let tok = lex::map_keywords( self.get_token_int()? );
+			let (tok, _) = self.get_token_int()?;
+			let tok = lex::map_keywords(tok);
 			trace!("get_token = {:?} (new)", tok);
 			Ok(tok)
 		}
 	}
-	pub fn get_token_int(&mut self) -> Result<Token>
+	/// Get a token without mapping keywords and digraphs (e.g. for `-E`, where digraphs keep their spelling)
+	pub fn get_token_int(&mut self) -> Result<(Token,Span)>
+	{
+		let (tok, span, _) = self.get_token_hs()?;
+		self.last_span = span.clone();
+		Ok( (tok, span) )
+	}
+	/// Get a fully-expanded token, along with its location and hide set
+	fn get_token_hs(&mut self) -> Result<(Token,Span,HideSet)>
 	{
 		loop
 		{
+			if let Some(rv) = self.finish_conditional() {
+				return Ok(rv);
+			}
 			// ---
 			// Handle #if-ed out blocks (only used when internal handling is enabled)
 			// TODO: May want to propagate the ignored tokens if Internal+Propagate is enabled?
 			// ---
-			if ! self.is_conditional_active() {
-				match try!(self.lexers.get_token())
+			// NOTE: `#elif` expressions are expanded while in a skipped group
+			if ! self.is_conditional_active() && ! self.in_if_expr {
+				let tok = self.lexers.get_token()?;
+				self.report_literal_errors(false);
+				match tok
 				{
 				Token::EOF => {
-					panic!("Unexpected EOF - {:?}", self.if_stack);
-					//return Ok(Token::EOF);
+					return Err(self.directive_error(DirectiveError::UnterminatedConditional));
 					},
 				Token::Whitespace => {},
 				Token::EscapedNewline => {},
@@ -242,7 +1087,7 @@ impl Preproc
 				Token::LineComment(_) | Token::BlockComment(_) => {
 					// No need to handle comment propagation when handling disabled #if blocks
 					},
-				Token::Hash if self.start_of_line =>
+				Token::Hash | Token::Digraph(Digraph::Hash) if self.start_of_line =>
 					match self.eat_comments()?
 					{
 					Token::Ident(ref name) if name == "if" => {
@@ -260,36 +1105,40 @@ impl Preproc
 							t => tokens.push(t),
 							}
 						}
-						let is_true = self.parse_if_expr(tokens)?;
+						// The expression is only evaluated if this group could be selected
+						let outer_active = self.if_stack.iter().rev().skip(1).all(|v| v.is_active);
+						let is_true = match self.if_stack.last()
+							{
+							None => return Err(self.directive_error(DirectiveError::UnmatchedElif)),
+							Some(ref v) if v.is_else => return Err(self.directive_error(DirectiveError::ElifAfterElse)),
+							Some(v) if outer_active && !v.is_active && !v.has_run => self.parse_if_expr(tokens),
+							Some(_) => Ok(false),
+							};
 
-						match self.if_stack.last_mut()
 						{
-						None => panic!("{}TODO: Error for unmatched #elif", self),
-						Some(ref v) if v.is_else => panic!("TODO: Error for duplicate #elif"),
-						Some(v) => {
+							let v = self.if_stack.last_mut().unwrap();
 							if v.is_active {
 								v.is_active = false;
 								v.has_run = true;
 							}
 							else if !v.has_run {
-								v.is_active = is_true;
-							}
-							else {
+								v.is_active = *is_true.as_ref().unwrap_or(&false);
 							}
-							},
 						}
+						let depth = self.if_stack.len() - 1;
+						self.lexers.cur_file_mut().guard.else_branch(depth);
+						is_true?;
 						},
 					Token::Ident(ref name) if name == "ifdef" || name == "ifndef" => {
-						let _ident = syntax_assert!(self.eat_comments(), Token::Ident(s) => s);
-						syntax_assert!(self.eat_comments(), Token::Newline => ());
+						let _ident = syntax_assert!(self, self.eat_comments(), Token::Ident(s) => s);
+						syntax_assert!(self, self.eat_comments(), Token::Newline => ());
 						self.if_stack.push(Conditional::default());
 						},
 					Token::Ident(ref name) if name == "else" => {
-						syntax_assert!(self.eat_comments(), Token::Newline => ());
 						match self.if_stack.last_mut()
 						{
-						None => panic!("TODO: Error for unmatched #else"),
-						Some(ref v) if v.is_else => panic!("TODO: Error for duplicate #else"),
+						None => return Err(self.directive_error(DirectiveError::UnmatchedElse)),
+						Some(ref v) if v.is_else => return Err(self.directive_error(DirectiveError::DuplicateElse)),
 						Some(v) => {
 							v.is_else = true;
 							if !v.has_run {
@@ -297,14 +1146,17 @@ impl Preproc
 							}
 							},
 						}
+						let depth = self.if_stack.len() - 1;
+						self.lexers.cur_file_mut().guard.else_branch(depth);
+						syntax_assert!(self, self.eat_comments(), Token::Newline => ());
 						},
 					Token::Ident(ref name) if name == "endif" => {
-						syntax_assert!(self.eat_comments(), Token::Newline => ());
-						match self.if_stack.pop()
-						{
-						None => panic!("TODO: Error for unmatched #endif"),
-						_ => {},
+						if self.if_stack.pop().is_none() {
+							return Err(self.directive_error(DirectiveError::UnmatchedEndif));
 						}
+						let depth = self.if_stack.len();
+						self.lexers.cur_file_mut().guard.endif(depth);
+						syntax_assert!(self, self.eat_comments(), Token::Newline => ());
 						},
 					_ => {},
 					},
@@ -313,7 +1165,19 @@ impl Preproc
 				continue ;
 			}
 
-			match try!(self.lexers.get_token())
+			let tok = self.lexers.get_token()?;
+			self.report_literal_errors(true);
+			let span = self.lexers.last_span.clone();
+			let hideset = self.lexers.last_hideset.clone();
+			match tok
+			{
+			Token::Whitespace | Token::EscapedNewline | Token::Newline => {},
+			Token::LineComment(_) | Token::BlockComment(_) => {},
+			// NOTE: `#ifndef` and `#endif` update the guard state themselves
+			Token::Hash | Token::Digraph(Digraph::Hash) if self.start_of_line => {},
+			_ => self.lexers.cur_file_mut().guard.invalidate(),
+			}
+			match tok
 			{
 			Token::Whitespace => {},
 			Token::EscapedNewline => {},
@@ -322,15 +1186,29 @@ impl Preproc
 				},
 			t @ Token::LineComment(_) | t @ Token::BlockComment(_) => {
 				// Optionally propagate comments to caller
-				if self.options.return_most_comments {
-					return Ok(t);
+				if self.options.return_most_comments && !self.in_argument {
+					return Ok( (t, span, hideset) );
 				}
 				},
-			Token::Hash if self.start_of_line => {
-				match self.eat_comments()?
+			Token::Hash | Token::Digraph(Digraph::Hash) if self.start_of_line => {
+				let directive = self.eat_comments()?;
+				match directive
+				{
+				Token::Ident(ref name) if name == "ifndef" || name == "endif" => {},
+				_ => self.lexers.cur_file_mut().guard.invalidate(),
+				}
+				if let Token::Ident(ref name) = directive
+				{
+					let is_conditional = match &name[..] { "if" | "ifdef" | "ifndef" | "elif" | "else" | "endif" => true, _ => false };
+					if is_conditional && self.options.propagate_conditionals && !self.in_argument && self.is_main_file(&span) {
+						self.conditional_start = Some(span.clone());
+					}
+				}
+				match directive
 				{
 				// #include
-				Token::Ident(ref name) if name == "include" => {
+				Token::Ident(ref name) if name == "include" || name == "include_next" => {
+					let mut is_next = name == "include_next";
 					let (was_angle, path) = if let Some(s) = self.lexers.get_includestr()?
 						{
 							// `#include <foo>`
@@ -339,39 +1217,32 @@ impl Preproc
 						else
 						{
 							// String literals (maybe with pre-processor expansions?)
-							match self.lexers.get_token()?
+							match self.eat_comments()?
 							{
-							Token::String(s) => { (false, s) },
-							tok @ _ => panic!("TODO: Syntax error, unexpected {:?}", tok),
+							Token::String(s) => { (false, s.to_string_lossy().into_owned()) },
+							tok @ _ => return Err(self.directive_error(DirectiveError::BadInclude(tok))),
 							}
 						};
-					syntax_assert!(self.eat_comments(), Token::Newline => ());
+					syntax_assert!(self, self.eat_comments(), Token::Newline => ());
+					if is_next && self.lexers.cur_file().search_dir.is_none() && self.lexers.include_depth() == 0 {
+						self.directive_warning(&span, DirectiveError::IncludeNextInPrimarySource);
+						is_next = false;
+					}
 					if self.options.include_handling != Handling::PropagateOnly {
-						let file_path = if was_angle {
-								// Search the include directories for the first entry that contains the specified file
-								self.options.include_paths.iter()
-									.flat_map(|include_path| {
-										let mut p = include_path.to_owned();
-										p.push(&path);
-										if p.is_file() {
-											Some(p)
-										}
-										else {
-											None
-										}
-										})
-									.next()
-									.unwrap_or_else(|| panic!("{}: TODO: Proper error when `#include '<' {:?} '>' fails", self, path))
-							}
-							else {
-								let mut p = self.lexers.cur_path().parent().unwrap_or(::std::path::Path::new(".")).to_owned();
-								p.push(&path);
-								p
+						let found = match self.find_include(&path, was_angle, is_next)
+							{
+							Some(f) => f,
+							None => return Err(self.directive_error(DirectiveError::IncludeNotFound(path))),
 							};
-						self.lexers.push_file(file_path)?;
+						if self.is_include_skipped(&found.path) {
+							debug!("Skipping re-include of {}", found.path.display());
+						}
+						else {
+							self.lexers.push_file(found, span.clone())?;
+						}
 					}
 					if self.options.include_handling != Handling::InternalOnly {
-						return Ok(token::Preprocessor::Include { angle_brackets: was_angle, path: path }.into());
+						return Ok( (token::Preprocessor::Include { angle_brackets: was_angle, path: path }.into(), span, hideset) );
 					}
 					// Continue loop
 					},
@@ -382,15 +1253,22 @@ impl Preproc
 				// #if[n]def
 				Token::Ident(ref name) if name == "ifdef" || name == "ifndef" => {
 					let cnd = name == "ifdef";
-					let ident = syntax_assert!(self.eat_comments(), Token::Ident(s) => s);
-					syntax_assert!(self.eat_comments(), Token::Newline => ());
+					let ident = syntax_assert!(self, self.eat_comments(), Token::Ident(s) => s);
+					syntax_assert!(self, self.eat_comments(), Token::Newline => ());
 					// Push to #if stack, only pass tokens if entire #if stack is true
 					// - Requires handling to be active 
 					if self.options.define_handling != Handling::PropagateOnly {
-						self.if_stack.push(Conditional::new(self.macros.contains_key(&ident) == cnd));
+						if cnd {
+							self.lexers.cur_file_mut().guard.invalidate();
+						}
+						else {
+							let depth = self.if_stack.len();
+							self.lexers.cur_file_mut().guard.ifndef(&ident, depth);
+						}
+						self.if_stack.push(Conditional::new(self.is_defined(&ident) == cnd));
 					}
 					if self.options.define_handling != Handling::InternalOnly {
-						return Ok(token::Preprocessor::IfDef { is_not_defined: !cnd, ident: ident }.into());
+						return Ok( (token::Preprocessor::IfDef { is_not_defined: !cnd, ident: ident }.into(), span, hideset) );
 					}
 					},
 				// #if
@@ -406,11 +1284,13 @@ impl Preproc
 					}
 					if self.options.define_handling != Handling::PropagateOnly {
 						// Parse and evaluate the expression
-						let is_true = self.parse_if_expr(tokens.clone())?;
-						self.if_stack.push(Conditional::new(is_true));
+						// NOTE: If the expression is invalid, the group is skipped (so the matching `#endif` still pairs up)
+						let is_true = self.parse_if_expr(tokens.clone());
+						self.if_stack.push(Conditional::new(*is_true.as_ref().unwrap_or(&false)));
+						is_true?;
 					}
 					if self.options.define_handling != Handling::InternalOnly {
-						return Ok(token::Preprocessor::If { tokens: tokens }.into());
+						return Ok( (token::Preprocessor::If { tokens: tokens }.into(), span, hideset) );
 					}
 					},
 				// #elif
@@ -431,38 +1311,42 @@ impl Preproc
 
 						match self.if_stack.last_mut()
 						{
-						None => panic!("TODO: Error for unmatched #elif"),
-						Some(ref v) if v.is_else => panic!("TODO: Error for duplicate #elif"),
+						None => return Err(self.directive_error(DirectiveError::UnmatchedElif)),
+						Some(ref v) if v.is_else => return Err(self.directive_error(DirectiveError::ElifAfterElse)),
 						Some(v) => { v.has_run = true; v.is_active = false; },
 						}
+						let depth = self.if_stack.len() - 1;
+						self.lexers.cur_file_mut().guard.else_branch(depth);
 					}
 					if self.options.define_handling != Handling::InternalOnly {
-						return Ok(token::Preprocessor::ElseIf { tokens: tokens }.into());
+						return Ok( (token::Preprocessor::ElseIf { tokens: tokens }.into(), span, hideset) );
 					}
 					},
 				// #else
 				Token::Ident(ref name) if name == "else" => {
-					syntax_assert!(self.eat_comments(), Token::Newline => ());
 					if self.options.define_handling != Handling::PropagateOnly {
 						match self.if_stack.last_mut()
 						{
-						None => panic!("TODO: Error for unmatched #else"),
-						Some(ref v) if v.is_else => panic!("TODO: Error for duplicate #else"),
+						None => return Err(self.directive_error(DirectiveError::UnmatchedElse)),
+						Some(ref v) if v.is_else => return Err(self.directive_error(DirectiveError::DuplicateElse)),
 						Some(v) => { v.is_else = true; v.has_run = true; v.is_active = !v.is_active; },
 						}
+						let depth = self.if_stack.len() - 1;
+						self.lexers.cur_file_mut().guard.else_branch(depth);
 					}
+					syntax_assert!(self, self.eat_comments(), Token::Newline => ());
 					if self.options.define_handling != Handling::InternalOnly {
-						return Ok(token::Preprocessor::Else.into());
+						return Ok( (token::Preprocessor::Else.into(), span, hideset) );
 					}
 					},
 				// #endif
 				Token::Ident(ref name) if name == "endif" => {
-					syntax_assert!(self.eat_comments(), Token::Newline => ());
-					match self.if_stack.pop()
-					{
-					None => panic!("TODO: Error for unmatched #endif"),
-					_ => {},
+					if self.if_stack.pop().is_none() {
+						return Err(self.directive_error(DirectiveError::UnmatchedEndif));
 					}
+					let depth = self.if_stack.len();
+					self.lexers.cur_file_mut().guard.endif(depth);
+					syntax_assert!(self, self.eat_comments(), Token::Newline => ());
 					},
 
 				// ---
@@ -470,7 +1354,8 @@ impl Preproc
 				// ---
 				// #define
 				Token::Ident(ref name) if name == "define" => {
-						let ident = syntax_assert!(self.eat_comments(), Token::Ident(s) => s);
+						let ident = syntax_assert!(self, self.eat_comments(), Token::Ident(s) => s);
+						let def_span = self.lexers.last_span.clone();
 						let mut tokens = Vec::new();
 						let (cont, args, variable) =
 							match self.lexers.get_token()?
@@ -488,41 +1373,50 @@ impl Preproc
 									Token::Ident(s) => args.push(s),
 									Token::Vargs => {
 										variable = Some("__VA_ARGS__".to_owned());
-										syntax_assert!(self.eat_comments(), Token::ParenClose => ());
+										syntax_assert!(self, self.eat_comments(), Token::ParenClose => ());
 										break
 										},
-									tok @ _ => panic!("{}: TODO: Error, unexpected {:?} in macro argument list", self, tok),
+									tok @ _ => return Err(self.directive_error(DirectiveError::UnexpectedToken(tok))),
 									}
 									match self.eat_comments()?
 									{
 									Token::Vargs => {
-										syntax_assert!(self.eat_comments(), Token::ParenClose => ());
+										syntax_assert!(self, self.eat_comments(), Token::ParenClose => ());
 										variable = args.pop();
 										break
 										},
 									Token::ParenClose => break,
 									Token::Comma => {},
-									tok @ _ => panic!("{}: TODO: Error, unexpected {:?} in macro argument list", self, tok),
+									tok @ _ => return Err(self.directive_error(DirectiveError::UnexpectedToken(tok))),
 									}
 								}
 								(true, Some(args), variable)
 								},
-							tok @ _ => panic!("TODO: Unexpected {:?} after #define name", tok),
+							tok @ _ => return Err(self.directive_error(DirectiveError::UnexpectedToken(tok))),
 							};
 						if cont
 						{
 							loop
 							{
-								// TODO: Should whitespace be included in the expansion?
 								match self.lexers.get_token()?
 								{
-								Token::Whitespace => {},
-								Token::LineComment(_) => {},
-								Token::BlockComment(_) => {},
+								// Whitespace (needed for stringification) is kept as a single token between other tokens
+								Token::Whitespace | Token::EscapedNewline | Token::LineComment(_) | Token::BlockComment(_) => {
+									if tokens.len() > 0 && tokens.last() != Some(&Token::Whitespace) {
+										tokens.push(Token::Whitespace);
+									}
+									},
 								Token::Newline => break,
 								tok => tokens.push(tok),
 								}
 							}
+							if tokens.last() == Some(&Token::Whitespace) {
+								tokens.pop();
+							}
+						}
+						if let Err(e) = check_replacement_list(&tokens, args.as_ref(), variable.as_ref())
+						{
+							return Err(self.directive_error(e));
 						}
 
 						match self.options.define_handling
@@ -530,6 +1424,7 @@ impl Preproc
 						Handling::InternalOnly => {
 							info!("Define {} = {:?} {:?}", ident, args, tokens);
 							self.macros.insert(ident, MacroDefinition {
+								span: def_span,
 								arg_names: args.map(|v| MacroArgs { names: v, va_args_name: variable }),
 								expansion: tokens,
 								});
@@ -538,693 +1433,564 @@ impl Preproc
 						Handling::InternalAndPropagate => {
 							// - Clone into the local map
 							self.macros.insert(ident.clone(), MacroDefinition {
-								arg_names: args.clone().map(|v| MacroArgs { names: v, va_args_name: variable }),
+								span: def_span,
+								arg_names: args.clone().map(|v| MacroArgs { names: v, va_args_name: variable.clone() }),
 								expansion: tokens.clone(),
 								});
-							return Ok(token::Preprocessor::MacroDefine {
+							return Ok( (token::Preprocessor::MacroDefine {
 								name: ident,
 								arg_names: args,
+								variadic: variable,
 								expansion: tokens,
-								}.into());
+								}.into(), span, hideset) );
 							},
 						Handling::PropagateOnly => {
-							return Ok(token::Preprocessor::MacroDefine {
+							return Ok( (token::Preprocessor::MacroDefine {
 								name: ident,
 								arg_names: args,
+								variadic: variable,
 								expansion: tokens,
-								}.into());
+								}.into(), span, hideset) );
 							}
 						}
 					},
 				Token::Ident(ref name) if name == "undef" => {
-					let ident = syntax_assert!(self.eat_comments(), Token::Ident(s) => s);
+					let ident = syntax_assert!(self, self.eat_comments(), Token::Ident(s) => s);
 					// TODO: Do function-like macros need the parens when being un-defined?
-					syntax_assert!(self.eat_comments(), Token::Newline => ());
+					syntax_assert!(self, self.eat_comments(), Token::Newline => ());
 					if self.options.define_handling != Handling::PropagateOnly {
 						self.macros.remove(&ident);
 					}
 					if self.options.define_handling != Handling::InternalOnly {
-						return Ok(token::Preprocessor::MacroUndefine { name: ident }.into());
+						return Ok( (token::Preprocessor::MacroUndefine { name: ident }.into(), span, hideset) );
 					}
 					},
 				// #pragma
 				Token::Ident(ref name) if name == "pragma" => {
-					let ident = syntax_assert!(self.eat_comments(), Token::Ident(s) => s);
-					match &ident[..]
-					{
-					"once" => {
-						syntax_assert!(self.eat_comments(), Token::Newline => ());
-						// TODO: Ensure single-inclusion of this file
-						// TODO: If enabled, propagate a token::Preprocessor::PragmaOnce
-						},
-					n => panic!("{}: TODO: Unknown pragma `{}`", self, n),
+					// NOTE: The newline is left to be handled by the main loop
+					let body = self.lexers.get_restofline()?;
+					if let Some(tok) = self.handle_pragma(&body, &span)? {
+						return Ok( (tok, span, hideset) );
+					}
+					},
+				// #error and #warning
+				Token::Ident(ref name) if name == "error" || name == "warning" => {
+					let is_error = name == "error";
+					// NOTE: The newline is left to be handled by the main loop
+					let message = self.lexers.get_restofline()?;
+					if is_error {
+						return Err(Error::Directive(span, DirectiveError::ErrorDirective(message)));
 					}
+					self.directive_warning(&span, DirectiveError::WarningDirective(message));
+					},
+				// #line
+				Token::Ident(ref name) if name == "line" => {
+					let line = syntax_assert!(self, self.eat_comments(), Token::Integer(v, _, _) => v);
+					let filename = match self.eat_comments()?
+						{
+						Token::Newline => None,
+						Token::String(s) => {
+							syntax_assert!(self, self.eat_comments(), Token::Newline => ());
+							Some(s.to_string_lossy().into_owned().into())
+							},
+						tok @ _ => return Err(self.directive_error(DirectiveError::UnexpectedToken(tok))),
+						};
+					self.lexers.set_presumed_location(line as usize, filename, &[], &span);
 					},
 				// Unknown identifier
-				Token::Ident(name) => panic!("{}TODO: Preprocessor '{}'", self, name),
+				Token::Ident(name) => return Err(self.directive_error(DirectiveError::UnknownDirective(name))),
+				// Null directive
+				Token::Newline => {},
 				
 
-				//Token::Integer(line, _,_) => {
-				//	let file = syntax_assert!(self.lexers.get_token(), Token::String(s) => s);
-				//	if let InnerLexer::File(lexer_h) = self.lexers.last_mut()
-				//	{
-				//		lexer_h.filename = file;
-				//		lexer_h.line = line as usize;
-				//		debug!("Set locaion to \"{}\":{}", lexer_h.filename, line);
-				//	}
-				//	while try!(self.lexers.get_token()) != Token::Newline
-				//	{
-				//	}
-				//	},
+				// GCC-style linemarker (`# <line> "<file>" <flags>...`)
+				Token::Integer(line, _, _) => {
+					let mut flags = Vec::new();
+					let filename = match self.eat_comments()?
+						{
+						Token::Newline => None,
+						Token::String(s) => {
+							loop
+							{
+								match self.eat_comments()?
+								{
+								Token::Newline => break,
+								Token::Integer(v, _, _) => flags.push(v),
+								tok @ _ => return Err(self.directive_error(DirectiveError::UnexpectedToken(tok))),
+								}
+							}
+							Some(s.to_string_lossy().into_owned().into())
+							},
+						tok @ _ => return Err(self.directive_error(DirectiveError::UnexpectedToken(tok))),
+						};
+					self.lexers.set_presumed_location(line as usize, filename, &flags, &span);
+					},
 				tok @ _ => {
-					panic!("TODO: Unexpected token after # - {:?}", tok);
+					return Err(self.directive_error(DirectiveError::UnexpectedToken(tok)));
 					},
 				}
 				},
 			Token::Ident(v) => {
 				self.start_of_line = false;
 
-				match self.macros.get(&v) {
-				Some(macro_def) => {
-					let arg_mapping: HashMap<&str,Vec<Token>> = if let Some(ref args) = macro_def.arg_names {
-							match self.lexers.get_token_nospace()?
+				// An identifier in its own hide set has been "painted blue", and is never expanded
+				if hideset.contains(&v) {
+					trace!("get_token = {:?} (painted)", v);
+					return Ok( (Token::Ident(v), span, hideset) );
+				}
+				if self.in_if_expr {
+					if let Some(is_true) = self.eval_if_operator(&v)? {
+						let v = is_true as u64;
+						return Ok( (Token::Integer(v, ::types::IntClass::int(), v.to_string()), span, HideSet::default()) );
+					}
+				}
+				if let Some(tok) = self.expand_builtin(&v, &span) {
+					return Ok( (tok, span, HideSet::default()) );
+				}
+				if v == "_Pragma" {
+					if let Some(tok) = self.handle_pragma_operator(&span)? {
+						return Ok( (tok, span, HideSet::default()) );
+					}
+					continue ;
+				}
+
+				let macro_def = match self.macros.get(&v)
+					{
+					Some(m) => m.clone(),
+					None => {
+						let ret = Token::Ident(v);
+						trace!("get_token = {:?}", ret);
+						return Ok( (ret, span, hideset) );
+						},
+					};
+				let (arg_mapping, hideset) = if let Some(ref args) = macro_def.arg_names {
+						// Function-like macros are only expanded when followed by `(` (which can be on a following line)
+						let mut seen_newline = false;
+						let tok = loop
 							{
-							Token::ParenOpen => {},
-							tok => {
-								assert!( self.saved_tok.is_none() );
-								self.saved_tok = Some( lex::map_keywords(tok) );
-								return Ok(Token::Ident(v));
+								match self.lexers.get_token_nospace()?
+								{
+								Token::Newline => seen_newline = true,
+								tok => break tok,
 								}
+							};
+						match tok
+						{
+						Token::ParenOpen => {},
+						Token::EOF => return Ok( (Token::Ident(v), span, hideset) ),
+						tok => {
+							let (tok_span, tok_hideset) = (self.lexers.last_span.clone(), self.lexers.last_hideset.clone());
+							self.lexers.put_back(tok, tok_span, tok_hideset);
+							// If a newline was skipped, the next token can start a directive
+							self.start_of_line = seen_newline;
+							return Ok( (Token::Ident(v), span, hideset) );
 							}
-							debug!("Macro {} with args", v);
-							let l = &mut self.lexers;
-							Self::parse_macro_args(args, &mut || l.get_token())?
 						}
-						else {
-							HashMap::new()
-						};
-
-					debug!("Macro expansion {} args={:?}", v, arg_mapping);
-					let output_tokens = self.do_macro_expansion(macro_def, &arg_mapping);
-					debug!("=> output_tokens={:?}", output_tokens);
-
-					if self.options.wrap_define_expansion {
-						return Ok(token::Preprocessor::MacroInvocaton {
-							// - Re-create (a variant) the input tokens
-							input: {
-								let mut arg_mapping = arg_mapping;	// re-map as mutable, so we can remove stuff.
-								let mut input = Vec::new();
-								input.push(Token::Ident(v));
-								if let Some(ref args) = macro_def.arg_names
-								{
-									input.push(Token::ParenOpen);
-									let mut first = true;
-									for a in &args.names
-									{
-										if !first {
-											input.push(Token::Comma);
-										}
-										input.extend( arg_mapping.remove(&a[..]).unwrap().into_iter() );
-										first = false;
-									}
-									input.push(Token::ParenClose);
-								}
-								input
-								},
-							// - Include the previously-calculated output tokens
-							output: output_tokens,
-							}.into());
-					}
-					else if macro_def.expansion.len() > 0 {
-						// Push this macro as a new underlying lexer.
-						self.lexers.push_macro(v, output_tokens);
-						// Keep looping (next iteration will use the macro as a token source)
-						continue
+						debug!("Macro {} with args", v);
+						let l = &mut self.lexers;
+						let (arg_mapping, close_hideset) = Self::parse_macro_args(&v, &span, args, &mut || { let t = l.get_token()?; Ok( (t, l.last_hideset.clone()) ) })?;
+						// The result's hide set is the intersection of the name's and the closing paren's (C11 6.10.3.4
+						// example, `f(2)(9)`)
+						(arg_mapping, hideset.intersection(&close_hideset))
 					}
 					else {
-						// Empty macro, and wrapping disabled, keep looping
-						continue
-					}
-					},
-				_ => {
-					let ret = Token::Ident(v);
-					trace!("get_token = {:?}", ret);
-					return Ok( ret );
+						(HashMap::new(), hideset)
+					};
+				let hideset = hideset.with(&v);
+
+				// Fully expand the arguments that aren't operands of `#` or `##`
+				let mut expanded_args = HashMap::new();
+				for (name, tokens) in &arg_mapping
+				{
+					if is_plain_parameter(&macro_def.expansion, name) {
+						let e = self.expand_argument(tokens.clone(), span.clone())?;
+						expanded_args.insert(*name, e);
 					}
 				}
+
+				debug!("Macro expansion {} args={:?}", v, arg_mapping);
+				let output_tokens: Vec<_> = self.do_macro_expansion(&macro_def, &arg_mapping, &expanded_args)
+					.map_err(|e| Error::Directive(span.clone(), e))?
+					.into_iter()
+					.map(|(t, hs)| (t, hs.union(&hideset)))
+					.collect();
+				debug!("=> output_tokens={:?}", output_tokens);
+
+				if self.options.wrap_define_expansion && !self.in_argument {
+					// - Re-create (a variant) the input tokens
+					let input = {
+						let mut arg_mapping = arg_mapping;	// re-map as mutable, so we can remove stuff.
+						let mut input = Vec::new();
+						input.push(Token::Ident(v));
+						if let Some(ref args) = macro_def.arg_names
+						{
+							input.push(Token::ParenOpen);
+							let mut first = true;
+							for a in args.names.iter().chain(args.va_args_name.iter())
+							{
+								let toks = arg_mapping.remove(&a[..]).unwrap_or_default();
+								// An empty variable argument is left out (along with its comma)
+								if Some(a) == args.va_args_name.as_ref() && toks.is_empty() {
+									break;
+								}
+								if !first {
+									input.push(Token::Comma);
+								}
+								input.extend( toks.into_iter().map(|(t,_)| t).filter(|t| *t != Token::Whitespace) );
+								first = false;
+							}
+							input.push(Token::ParenClose);
+						}
+						input
+						};
+					// - Rescan the output on its own, so the caller gets the complete expansion
+					let output = self.expand_argument(output_tokens, span.clone())?;
+					return Ok( (token::Preprocessor::MacroInvocaton {
+						input: input,
+						output: output.into_iter().map(|(t,_)| lex::map_keywords(t)).collect(),
+						}.into(), span, hideset) );
+				}
+				else if output_tokens.len() > 0 {
+					// Push this macro as a new underlying lexer (rescanning happens as tokens are read from it)
+					// - All tokens from the expansion are given the location of the invocation
+					let exp_span = Span {
+						expansion: Some(::std::rc::Rc::new(Expansion {
+							name: v.clone(),
+							definition: macro_def.span.clone(),
+							parent: span.expansion.clone(),
+							})),
+						.. span
+						};
+					self.lexers.push_macro(v, output_tokens, exp_span);
+					// Keep looping (next iteration will use the macro as a token source)
+					continue
+				}
+				else {
+					// Empty macro, and wrapping disabled, keep looping
+					continue
+				}
 				},
 			tok @ _ => {
 				self.start_of_line = false;
 				trace!("get_token = {:?}", tok);
-				return Ok(tok)
+				return Ok( (tok, span, hideset) )
 				}
 			}
 		}
 	}
 
-	fn parse_macro_args<'a>(mac_args: &'a MacroArgs, get_token: &mut FnMut()->Result<Token>) -> Result<HashMap<&'a str,Vec<Token>>>
+	/// Expand a built-in macro (e.g. `__LINE__`), returns `None` if the name isn't a built-in
+	fn expand_builtin(&self, name: &str, span: &Span) -> Option<Token>
+	{
+		match name
+		{
+		"__LINE__" => Some(Token::Integer(span.line as u64, ::types::IntClass::int(), span.line.to_string())),
+		"__FILE__" => Some(Token::String(match self.source_file(span.file).path
+			{
+			Some(ref p) => p.display().to_string(),
+			None => "<stdin>".to_owned(),
+			}.into())),
+		"__DATE__" => Some(Token::String(self.build_date.clone().into())),
+		"__TIME__" => Some(Token::String(self.build_time.clone().into())),
+		"__COUNTER__" => {
+			let v = self.counter.get();
+			self.counter.set(v + 1);
+			Some(Token::Integer(v, ::types::IntClass::int(), v.to_string()))
+			},
+		"__INCLUDE_LEVEL__" => {
+			let v = self.lexers.include_depth() as u64;
+			Some(Token::Integer(v, ::types::IntClass::int(), v.to_string()))
+			},
+		_ => None,
+		}
+	}
+
+	/// Fully macro-expand an argument, as if it formed the rest of the file (C11 6.10.3.1p1)
+	fn expand_argument(&mut self, tokens: Vec<(Token,HideSet)>, span: Span) -> Result<Vec<(Token,HideSet)>>
+	{
+		let start_of_line = ::std::mem::replace(&mut self.start_of_line, false);
+		let in_argument = ::std::mem::replace(&mut self.in_argument, true);
+		self.lexers.push_argument(tokens, span);
+		let mut rv = Vec::new();
+		let res = loop
+			{
+				match self.get_token_hs()
+				{
+				Err(e) => break Err(e),
+				Ok( (Token::EOF, _, _) ) => break Ok( () ),
+				Ok( (t, _, hs) ) => rv.push( (t, hs) ),
+				}
+			};
+		self.lexers.pop_argument();
+		self.start_of_line = start_of_line;
+		self.in_argument = in_argument;
+		res.map(|_| rv)
+	}
+
+	/// Read the arguments to the function-like macro `name` (after the opening paren)
+	///
+	/// Returns the arguments (with newlines converted to whitespace), and the hide set of the closing paren. The wrong
+	/// number of arguments is reported at `span` (once the closing paren has been read).
+	fn parse_macro_args<'a>(name: &str, span: &Span, mac_args: &'a MacroArgs, get_token: &mut FnMut()->Result<(Token,HideSet)>) -> Result<(MacroArgTokens<'a>, HideSet)>
 	{
 		// Read tokens, handling nested parens
 		let mut args: HashMap<&str,_> = HashMap::new();
 		let mut cur_arg_idx: usize = 0;
 		let mut cur_arg_toks = Vec::new();
 		let mut paren_level: usize = 0;
+		// Commas between arguments (not counting those within the variable arguments)
+		let mut n_commas: usize = 0;
+		// Nothing between the parens
+		let mut is_empty = false;
 
-		Ok(loop
+		let hideset = loop
 		{
-			match get_token()?
+			let (tok, hideset) = get_token()?;
+			match tok
 			{
+			Token::EOF => return Err(Error::UnexpectedEof),
 			Token::ParenClose if paren_level == 0 => {
+				is_empty = n_commas == 0 && cur_arg_toks.is_empty();
 				if cur_arg_idx < mac_args.names.len() {
 					args.insert( &mac_args.names[cur_arg_idx], cur_arg_toks );
 					if let Some(v) = mac_args.va_args_name.as_ref() {
 						args.insert(v, vec![]);
 					}
 				}
-				else if cur_arg_idx == 0 && cur_arg_toks.is_empty() && mac_args.va_args_name.is_none() {
-				}
-				else {
-					if let Some(v) = mac_args.va_args_name.as_ref() {
-						args.insert( v, cur_arg_toks );
-					}
-					else {
-						panic!("Bugcheck: va_args_name is None, but out of arguments - args={:?}", args);
-					}
+				else if let Some(v) = mac_args.va_args_name.as_ref() {
+					args.insert( v, cur_arg_toks );
 				}
-				break args;
+				break hideset;
 				},
 			// TODO: Handle gcc-style named variadics?
 			Token::Comma if paren_level == 0 && cur_arg_idx < mac_args.names.len() => {
 				args.insert( &mac_args.names[cur_arg_idx], cur_arg_toks );
 				cur_arg_toks = Vec::new();
 				cur_arg_idx += 1;
-				if cur_arg_idx == mac_args.names.len() && mac_args.va_args_name.is_none() {
-					panic!("TODO: Error message when too many arguments are passed");
-				}
+				n_commas += 1;
+				},
+			// Too many arguments, keep going to the closing paren
+			Token::Comma if paren_level == 0 && mac_args.va_args_name.is_none() => {
+				n_commas += 1;
 				},
 			t @ Token::ParenOpen => {
 				paren_level += 1;
-				cur_arg_toks.push(t);
+				cur_arg_toks.push( (t, hideset) );
 				},
 			t @ Token::ParenClose => {
 				paren_level -= 1;
-				cur_arg_toks.push(t);
+				cur_arg_toks.push( (t, hideset) );
 				},
 			Token::Whitespace | Token::EscapedNewline | Token::Newline if cur_arg_toks.len() == 0 => {},
-			t => cur_arg_toks.push(t),
+			// Newlines within the arguments are just whitespace
+			Token::Newline => cur_arg_toks.push( (Token::Whitespace, hideset) ),
+			t => cur_arg_toks.push( (t, hideset) ),
 			}
-		})
-	}
+		};
 
-	fn do_macro_expansion(&self, macro_def: &MacroDefinition, arg_mapping: &HashMap<&str,Vec<Token>>) -> Vec<Token>
-	{
-		let mut output_tokens = Vec::new();
-		enum Mode {
-			Normal, Stringify, Concat,
-		}
-		let mut mode = Mode::Normal;
-		for tok in &macro_def.expansion
-		{
-			match tok
-			{
-			Token::Ident(i) =>
-				match ::std::mem::replace(&mut mode, Mode::Normal)
-				{
-				Mode::Normal =>
-					// No concat/stringify - expand tokens directly
-					match arg_mapping.get(&i[..])
-					{
-					Some(v) => output_tokens.extend( v.iter().cloned() ),
-					None => output_tokens.push( Token::Ident(i.clone()) ),
-					},
-				Mode::Stringify =>
-					match arg_mapping.get(&i[..])
-					{
-					Some(v) => {
-						let mut s = String::new();
-						for t in v.iter()
-						{
-							s.push_str(match t
-							{
-							&Token::EOF
-							| &Token::BlockComment(_)
-							| &Token::LineComment(_)
-								=> "",
-							&Token::Whitespace
-							| &Token::Newline
-							| &Token::EscapedNewline
-								=> " ",
-							&Token::Preprocessor(_) => panic!("{:?} in macro argument", t),
-							// -- Expression leaves
-							&Token::Integer(_, _, ref s) => s,
-							&Token::Float(_, _, ref s) => s,
-							&Token::Character(ch) => panic!("TODO: Stringify char constant {}", ch),
-							&Token::String(ref s) => panic!("TODO: Stringify string constant {:?}", s),
-							&Token::Ident(ref n) => n,
-							
-							// -- Symbols
-							&Token::Hash => "#",
-							&Token::Tilde => "~",
-							&Token::Exclamation => "!",
-							&Token::Period => ".",
-							&Token::DerefMember => "->",
-							&Token::Comma => ",",
-							&Token::Semicolon => ";",
-							&Token::Star => "*",
-							&Token::Slash => "/",
-							&Token::Backslash => "\\",
-							&Token::Vargs => "...",
-							&Token::QuestionMark => "?",
-							&Token::Colon => ":",
-
-							&Token::Assign => "=",
-							&Token::AssignAdd => "+=",
-							&Token::AssignSub => "-=",
-							&Token::AssignMul => "*=",
-							&Token::AssignDiv => "/=",
-							&Token::AssignMod => "%=",
-							&Token::AssignLogicOr => "||=",
-							&Token::AssignLogicAnd => "&&=",
-							&Token::AssignBitOr => "|=",
-							&Token::AssignBitAnd => "&=",
-							
-							&Token::ShiftRight => ">>",
-							&Token::ShiftLeft => "<<",
-							
-							&Token::Equality => "==",
-							&Token::NotEquals => "!=",
-							&Token::Lt => "<",
-							&Token::Gt => ">",
-							&Token::LtE => "<=",
-							&Token::GtE => ">=",
-							
-							&Token::Percent => "%",
-							&Token::Plus => "+",
-							&Token::Minus => "-",
-							&Token::DoublePlus => "++",	// ungood (bad joke, sorry)
-							&Token::DoubleMinus => "--",
-							
-							&Token::Ampersand => "&",
-							&Token::Pipe => "|",
-							&Token::Caret => "^",
-							&Token::DoubleAmpersand => "&&",
-							&Token::DoublePipe => "||",
-							
-							// -- Brackets
-							&Token::BraceOpen => "{",
-							&Token::BraceClose => "}",
-							&Token::ParenOpen => "(",
-							&Token::ParenClose => ")",
-							&Token::SquareOpen => "[",
-							&Token::SquareClose => "]",
-							
-							// -- Reserved Words
-							// - Storage classes
-							&Token::Rword_typedef
-							| &Token::Rword_auto
-							| &Token::Rword_extern
-							| &Token::Rword_static
-							| &Token::Rword_register
-							| &Token::Rword_inline
-							| &Token::Rword_const
-							| &Token::Rword_volatile
-							| &Token::Rword_restrict
-							| &Token::Rword_void
-							| &Token::Rword_Bool
-							| &Token::Rword_signed
-							| &Token::Rword_unsigned
-							| &Token::Rword_char
-							| &Token::Rword_short
-							| &Token::Rword_int
-							| &Token::Rword_long
-							| &Token::Rword_float
-							| &Token::Rword_double
-							| &Token::Rword_enum
-							| &Token::Rword_union
-							| &Token::Rword_struct
-							| &Token::Rword_if
-							| &Token::Rword_else
-							| &Token::Rword_while
-							| &Token::Rword_do
-							| &Token::Rword_for
-							| &Token::Rword_switch
-							| &Token::Rword_goto
-							| &Token::Rword_continue
-							| &Token::Rword_break
-							| &Token::Rword_return
-							| &Token::Rword_case
-							| &Token::Rword_default
-							| &Token::Rword_sizeof
-								=> panic!("Reserved word mapped to token variant in preprocessor, should still be an ident"),
-							});
-						}
-						output_tokens.push(Token::String(s))
-						},
-					None => output_tokens.push( Token::String(i.clone()) ),
-					},
-				Mode::Concat =>
-					match output_tokens.last_mut()
-					{
-					Some(Token::Ident(prev)) =>
-						match arg_mapping.get(&i[..])
-						{
-						Some(v) =>
-							match &v[..]
-							{
-							&[Token::Ident(ref i)] => prev.push_str(i),
-							&[Token::Integer(_v, _, ref s)] => prev.push_str(s),
-							_ => panic!("{}: TODO: Concat `{}` with expanded - {:?}", self, prev, v),
-							},
-						None => {
-							prev.push_str(i);
-							},
-						},
-					Some(Token::Comma) =>
-						match arg_mapping.get(&i[..])
-						{
-						Some(v) if v.len() == 0 => { output_tokens.pop(); },
-						Some(v) => output_tokens.extend( v.iter().cloned() ),
-						None =>
-							panic!("TODO: ', ## {}' - no expansion for {}", i, i),
-						}
-					opt_t => {
-						panic!("TODO: Concat after non-ident - {:?}", opt_t);
-						},
-					},
-				},
-			Token::Hash => match mode
-				{
-				Mode::Normal => { mode = Mode::Stringify; },
-				Mode::Stringify => { mode = Mode::Concat; },
-				Mode::Concat => { panic!("TODO: Error on `###` in stream"); }
-				},
-				//{
-				//	// Mode change to do a concatenation
-				//}
-			tok => {
-				match mode
-				{
-				Mode::Normal => { },
-				Mode::Stringify => { panic!("TODO: Error for # followed by non-ident?"); },
-				Mode::Concat => { panic!("TODO: Error for ## followed by non-ident?"); },
-				}
-				output_tokens.push(tok.clone());
-				}
-			}
+		// NOTE: A single parameter can be given an empty argument, and the variable arguments can be left out
+		let expected = mac_args.names.len();
+		let given = if is_empty && expected == 0 { 0 } else { n_commas + 1 };
+		if given < expected || (given > expected && mac_args.va_args_name.is_none()) {
+			return Err(Error::Directive(span.clone(), DirectiveError::MacroArgumentCount { name: name.to_owned(), expected, given }));
 		}
-		output_tokens
+		Ok( (args, hideset) )
 	}
 
-	fn parse_if_expr(&self, tokens: Vec<Token>) -> Result<bool>
+	/// Substitute arguments into a macro's replacement list, handling `#`, `##`, and `__VA_OPT__`
+	///
+	/// `expanded_args` contains the fully macro-expanded arguments, used for parameters that aren't operands of `#`
+	/// or `##` (see `is_plain_parameter`)
+	fn do_macro_expansion(&self, macro_def: &MacroDefinition, arg_mapping: &MacroArgTokens, expanded_args: &MacroArgTokens) -> ::std::result::Result<Vec<(Token,HideSet)>,DirectiveError>
 	{
-		// TODO: Pass expansion over the tokens first? (Macro expansion rules aren't trivial)
-		struct Parser<'a>
-		{
-			self_: &'a Preproc,
-			tokens: ::std::vec::IntoIter<Token>,
-			macro_expansions: Vec<(usize, ::std::vec::IntoIter<Token>)>,
-			
-			tmp: Option<Token>,
-		}
-		impl<'a> Parser<'a>
-		{
-			fn peek_tok<'b>(&'b mut self) -> Option<&'b Token> {
-				if self.tmp.is_none() {
-					self.tmp = self.get_tok();
-				}
-				self.tmp.as_ref()
-			}
-			fn get_tok(&mut self) -> Option<Token> {
-				let rv = if self.tmp.is_some() {
-						self.tmp.take()
-					}
-					else {
-						loop
-						{
-							match self.get_tok_int()
-							{
-							Some(Token::EscapedNewline) => {},
-							v => break v,
-							}
-						}
-					};
-				//debug!("if::Parser::get_tok: {:?}", rv);
-				rv
-			}
-			fn get_tok_int(&mut self) -> Option<Token> {
-				loop
-				{
-					if let Some(&mut (ref mut pos, ref mut me)) = self.macro_expansions.last_mut()
-					{
-						*pos += 1;
-						if let Some(v) = me.next() {
-							return Some(v);
-						}
-						else if *pos == 1 {
-							// HACK: Empty macros expand to `1` in #if
-							return Some(Token::Integer(1, ::types::IntClass::int(), String::new()));
-						}
-					}
-
-					// If there was a expansion being processed (which has to have just reached the end)
-					if let Some(_) = self.macro_expansions.pop() {
-						// Loop again
-						continue ;
-					}
+		let va_args_name = macro_def.arg_names.as_ref().and_then(|a| a.va_args_name.as_ref()).map(|v| &v[..]);
+		self.substitute(&macro_def.expansion, macro_def.arg_names.is_some(), va_args_name, arg_mapping, expanded_args)
+	}
+	fn substitute(&self, body: &[Token], is_function: bool, va_args_name: Option<&str>, arg_mapping: &MacroArgTokens, expanded_args: &MacroArgTokens) -> ::std::result::Result<Vec<(Token,HideSet)>,DirectiveError>
+	{
+		let mut output_tokens: Vec<(Token,HideSet)> = Vec::new();
+		// A `##` was seen, so the next operand is pasted onto the end of the output
+		let mut paste = false;
+		// The previous operand was empty (a placemarker)
+		let mut prev_empty = true;
+		// The previous operand was a comma from the replacement list (for GNU `, ## __VA_ARGS__`)
+		let mut prev_comma = false;
 
-					return match self.tokens.next()
-						{
-						None => None,
-						Some(rv) => {
-							if let Token::Ident(n) = rv
-							{
-								if let Some(md) = self.self_.macros.get(&n)
-								{
-									let args = if let Some(ref args) = md.arg_names {
-											if self.peek_tok() != Some(&Token::ParenOpen) {
-												return Some(Token::Ident(n));
-											}
-											match Preproc::parse_macro_args(args, &mut || self.tokens.next().ok_or(Error::UnexpectedEof))
-											{
-											Err(e) => panic!("TODO: Error when failing to parse macro args {:?}", e),
-											Ok(v) => v,
-											}
-										}
-										else {
-											HashMap::new()
-										};
-									debug!("{}: Expand `{}` to {:?} with args={:?}", self.self_, n, md.expansion, args);
-									let exp = self.self_.do_macro_expansion(md, &args);
-									// TODO: Calculate expansion, with recursion
-									self.macro_expansions.push( (0, exp.into_iter()) );
-									continue ;
-								}
-								else
-								{
-									return Some(Token::Ident(n));
-								}
-							}
-							Some(rv)
-							}
-						};
+		let mut i = 0;
+		while i < body.len()
+		{
+			if let Some(next) = paste_operator_at(body, i) {
+				while output_tokens.last().map(|t| &t.0) == Some(&Token::Whitespace) {
+					output_tokens.pop();
 				}
+				paste = true;
+				i = next;
+				continue ;
 			}
-
-			fn get_tok_noexpand(&mut self) -> Option<Token>
-			{
-				if self.tmp.is_some() {
-					self.tmp.take()
-				}
-				else if self.macro_expansions.len() > 0 {
-					panic!("{}TODO: `defined` in macro exapnsion, valid?", self.self_);
-				}
-				else if let Some(t) = self.tokens.next() {
-					Some(t)
-				}
-				else {
-					None
+			if body[i] == Token::Whitespace {
+				// Whitespace around `##` is dropped
+				if !paste {
+					output_tokens.push( (Token::Whitespace, HideSet::default()) );
 				}
+				i += 1;
+				continue ;
 			}
-		}
 
-		// TODO: use a value enum to handle quirks?
-		//enum Value {
-		//	Int(i64),
-		//	Name(String),
-		//	EmptyExpansion,
-		//}
-		type Value = i64;
-		type Result = crate::preproc::Result<Value>;
-		impl<'a> Parser<'a>
-		{
-			fn evaluate_expr_value(&mut self) -> Result {
-				Ok(match self.get_tok()
+			let mut is_va_param = false;
+			let is_comma = body[i] == Token::Comma;
+			let operand = match body[i]
 				{
-				Some(Token::Integer(v,_,_)) => v as Value,
-				Some(Token::Ident(ref n)) if n == "defined" => {
-					let i = match self.get_tok_noexpand()
+				// `#param` (only an operator in function-like macros)
+				Token::Hash | Token::Digraph(Digraph::Hash) if is_function => {
+					i = skip_whitespace(body, i + 1);
+					let text = match body.get(i)
 						{
-						Some(Token::Ident(i)) => i,
-						Some(Token::ParenOpen) =>
-							match self.get_tok_noexpand()
-							{
-							Some(Token::Ident(i)) =>
-								match self.get_tok_noexpand()
-								{
-								Some(Token::ParenClose) => i,
-								t => panic!("{}TODO: Error in #if parsing - `defined(NAME` followed by invalid token - {:?}", self.self_, t), 
-								},
-							t => panic!("{}TODO: Error in #if parsing - `defined(` followed by invalid token - {:?}", self.self_, t), 
+						Some(Token::Ident(ref name)) if va_args_name.is_some() && name == "__VA_OPT__" => {
+							let (content, next) = va_opt_content(body, i)?;
+							i = next;
+							stringify( &self.substitute(content, is_function, va_args_name, arg_mapping, expanded_args)? )
+							},
+						Some(Token::Ident(ref name)) if arg_mapping.contains_key(&name[..]) => {
+							i += 1;
+							stringify(&arg_mapping[&name[..]])
 							},
-						t => panic!("{}TODO: Error in #if parsing - defined followed by invalid token - {:?}", self.self_, t), 
+						_ => return Err(DirectiveError::StringifyNonParameter),
 						};
-					debug!("> defined {:?}", i);
-					if self.self_.macros.contains_key(&i) {
-						1
+					vec![ (Token::String(text.into()), HideSet::default()) ]
+					},
+				// `__VA_OPT__(...)`, expands to the contents if the variable arguments are non-empty
+				Token::Ident(ref name) if va_args_name.is_some() && name == "__VA_OPT__" => {
+					let (content, next) = va_opt_content(body, i)?;
+					i = next;
+					let has_va_args = arg_mapping.get(va_args_name.unwrap()).map(|v| !trim_whitespace(v).is_empty()).unwrap_or(false);
+					if has_va_args {
+						trim_whitespace(&self.substitute(content, is_function, va_args_name, arg_mapping, expanded_args)?).to_vec()
 					}
 					else {
-						0
-					}
-					},
-				Some(Token::Ident(n)) => {
-					// TODO: `#if foo == foo` should be true, but `#if foo == bar` should be false?
-					warn!("{}: Undefined identifier {}, evaluating to 0", self.self_, n);
-					if self.peek_tok() == Some(&Token::ParenOpen) {
-						error!("{}Was {} meant to be a function-like macro?", self.self_, n);
+						Vec::new()
 					}
-					0
 					},
-				Some(Token::ParenOpen) => {
-					let rv = self.evaluate_expr()?;
-					match self.get_tok().ok_or(Error::UnexpectedEof)?
+				Token::Ident(ref name) if arg_mapping.contains_key(&name[..]) => {
+					i += 1;
+					is_va_param = Some(&name[..]) == va_args_name;
+					// Operands of `##` use the argument as written, otherwise the expanded version is used
+					let next_is_paste = paste_operator_at(body, skip_whitespace(body, i)).is_some();
+					match expanded_args.get(&name[..])
 					{
-					Token::ParenClose => {},
-					t => panic!("{}TODO: Error in #if parsing - Expected ')' got '{:?}' - {:?}", self.self_, t, self.tokens),
+					Some(v) if !paste && !next_is_paste => trim_whitespace(v).to_vec(),
+					_ => trim_whitespace(&arg_mapping[&name[..]]).to_vec(),
 					}
-					rv
 					},
-				t @ _ => panic!("{}TODO: Error in #if parsing - unexpected token {:?}", self.self_, t),
-				})
-			}
-			fn evaluate_expr_unary(&mut self) -> Result {
-				let cur = Self::evaluate_expr_unary;
-				let next = Self::evaluate_expr_value;
-				match self.peek_tok()
-				{
-				Some(Token::Exclamation) => { self.get_tok(); Ok( (cur(self)? == 0) as _ ) },
-				Some(Token::Minus) => { self.get_tok(); Ok( -cur(self)? ) },
-				_ => next(self),
-				}
-			}
-			fn evaluate_expr_muldiv(&mut self) -> Result {
-				let next = Self::evaluate_expr_unary;
-				let mut v = next(self)?;
-				loop
-				{
-					v = match self.peek_tok()
-						{
-						Some(Token::Star ) => { self.get_tok(); v * next(self)? },
-						Some(Token::Slash) => { self.get_tok(); v / next(self)? },
-						_ => return Ok(v),
-						};
-				}
-			}
-			fn evaluate_expr_shift(&mut self) -> Result {
-				let next = Self::evaluate_expr_muldiv;
-				let mut v = next(self)?;
-				loop
-				{
-					v = match self.peek_tok()
-						{
-						Some(Token::ShiftLeft) => { self.get_tok(); v << next(self)? },
-						Some(Token::ShiftRight) => { self.get_tok(); v >> next(self)? },
-						_ => return Ok(v),
-						};
-				}
-			}
-			fn evaluate_expr_addsub(&mut self) -> Result {
-				let next = Self::evaluate_expr_shift;
-				let mut v = next(self)?;
-				loop
-				{
-					v = match self.peek_tok()
-						{
-						Some(Token::Plus ) => { self.get_tok(); v + next(self)? },
-						Some(Token::Minus) => { self.get_tok(); v - next(self)? },
-						_ => return Ok(v),
-						//t @ _ => { debug!("evaluate_expr_addsub: t={:?} v={}", t, v); return Ok(v) },
-						};
-				}
-			}
-			fn evaluate_expr_cmp(&mut self) -> Result {
-				let next = Self::evaluate_expr_addsub;
-				let mut v = next(self)?;
-				loop
-				{
-					v = match self.peek_tok()
-						{
-						Some(Token::Lt ) => { self.get_tok(); (v <  next(self)?) as _ },
-						Some(Token::LtE) => { self.get_tok(); (v <= next(self)?) as _ },
-						Some(Token::Gt ) => { self.get_tok(); (v >  next(self)?) as _ },
-						Some(Token::GtE) => { self.get_tok(); (v >= next(self)?) as _ },
-						Some(Token::Equality) => { self.get_tok(); (v == next(self)?) as _ },
-						Some(Token::NotEquals) => { self.get_tok(); (v != next(self)?) as _ },
-						_ => return Ok(v),
-						//t @ _ => { debug!("evaluate_expr_cmp: t={:?} v={}", t, v); return Ok(v) },
-						};
+				ref t => {
+					i += 1;
+					vec![ (t.clone(), HideSet::default()) ]
+					},
+				};
+
+			if ::std::mem::replace(&mut paste, false)
+			{
+				if prev_comma && is_va_param {
+					// GNU extension: `, ## __VA_ARGS__` deletes the comma if there are no variable arguments, and
+					// otherwise doesn't paste.
+					if operand.is_empty() {
+						output_tokens.pop();
+					}
 				}
-			}
-			fn evaluate_expr_bool(&mut self) -> Result {
-				debug!("evaluate_expr_bool");
-				let next = Self::evaluate_expr_cmp;
-				let mut v = next(self)?;
-				loop
-				{
-					v = match self.peek_tok()
-						{
-						Some(Token::DoubleAmpersand) => { self.get_tok(); let oth = next(self)?; (oth != 0 && v != 0) as _ },
-						Some(Token::DoublePipe) => { self.get_tok(); let oth = next(self)?; (oth != 0 || v != 0) as _ },
-						//t @ _ => { debug!("evaluate_expr_bool: t={:?} v={}", t, v); return Ok(v) },
-						_ => return Ok(v),
-						};
+				else if operand.is_empty() {
+					// Pasting a placemarker leaves the other operand unchanged
+					continue ;
 				}
-			}
-			fn evaluate_expr_ternary(&mut self) -> Result {
-				let next = Self::evaluate_expr_bool;
-				let mut v = next(self)?;
-				loop
-				{
-					v = match self.peek_tok()
-						{
-						Some(Token::QuestionMark) => {
-							self.get_tok();
-							let a = next(self)?;
-							match self.get_tok()
-							{
-							Some(Token::Colon) => {},
-							t => panic!("{:?}", t),
-							}
-							let b = next(self)?;
-							if v != 0 { a } else { b }
-							},
-						_ => return Ok(v),
-						}
+				else if !prev_empty {
+					let lhs = output_tokens.pop().expect("BUG: Non-empty paste operand, but no output");
+					let mut it = operand.iter().cloned();
+					let rhs = it.next().unwrap();
+					output_tokens.push( paste_tokens(lhs, rhs, self.options.lex_options())? );
+					output_tokens.extend(it);
+					prev_comma = false;
+					continue ;
 				}
 			}
-			fn evaluate_expr(&mut self) -> Result {
-				self.evaluate_expr_ternary()
-			}
+			prev_empty = operand.is_empty();
+			prev_comma = is_comma;
+			output_tokens.extend(operand);
 		}
+		Ok(output_tokens)
+	}
+
+	fn parse_if_expr(&mut self, tokens: Vec<Token>) -> Result<bool>
+	{
+		// Expand macros (with `defined` and the `__has_*` operators handled as they're seen), then evaluate (C11 6.10.1p4)
+		let span = self.lexers.last_span.clone();
+		let pre_expansion = tokens.iter().map(|t| (t.clone(), HideSet::default())).collect();
+		self.in_if_expr = true;
+		let expanded = self.expand_argument(pre_expansion, span);
+		self.in_if_expr = false;
+		let expanded: Vec<Token> = expanded?.into_iter().map(|(t,_)| t).collect();
 
-		let mut p = Parser { self_: self, tokens: tokens.clone().into_iter(), tmp: None, macro_expansions: Vec::new() };
-		let rv = p.evaluate_expr()?;
-		match p.get_tok()
+		match expr::evaluate(expanded, self.options.target.char_is_signed())
 		{
-		Some(t) => panic!("{}: TODO: Error in PP if/elif - Unhandled token {:?}", self, t),
-		None => {},
+		Ok(v) => {
+			debug!("{}: Parsed and evaluated #if/#elif expression - {:?} = {}", self, tokens, v);
+			Ok(v.is_true())
+			},
+		Err(e) => Err(self.directive_error(e)),
 		}
-		debug!("{}: Parsed and evaluated #if/#elif expression - {:?} = {}", self, tokens, rv);
-		Ok(rv != 0)
+	}
+
+	/// Evaluate an operator that's only valid in `#if` (returns `None` if `name` isn't one)
+	fn eval_if_operator(&mut self, name: &str) -> Result<Option<bool>>
+	{
+		Ok(Some(match name
+		{
+		// `defined NAME` or `defined ( NAME )`
+		"defined" => {
+			let name = match self.lexers.get_token_nospace()?
+				{
+				Token::Ident(i) => i,
+				Token::ParenOpen => {
+					let i = syntax_assert!(self, self.lexers.get_token_nospace(), Token::Ident(i) => i);
+					syntax_assert!(self, self.lexers.get_token_nospace(), Token::ParenClose => ());
+					i
+					},
+				tok => return Err(self.directive_error(DirectiveError::UnexpectedToken(tok))),
+				};
+			debug!("> defined {:?}", name);
+			self.is_defined(&name)
+			},
+		"__has_include" | "__has_include_next" => {
+			syntax_assert!(self, self.lexers.get_token_nospace(), Token::ParenOpen => ());
+			let (was_angle, path) = match self.lexers.get_token_nospace()?
+				{
+				Token::String(s) => (false, s.to_string_lossy().into_owned()),
+				// `<file>` has been lexed as tokens, so re-join them
+				Token::Lt => {
+					let mut s = String::new();
+					loop
+					{
+						match self.lexers.get_token_nospace()?
+						{
+						Token::Gt => break,
+						tok @ Token::EOF => return Err(self.directive_error(DirectiveError::BadInclude(tok))),
+						t => s.push_str(&t.to_string()),
+						}
+					}
+					(true, s)
+					},
+				tok => return Err(self.directive_error(DirectiveError::BadInclude(tok))),
+				};
+			syntax_assert!(self, self.lexers.get_token_nospace(), Token::ParenClose => ());
+			self.find_include(&path, was_angle, name == "__has_include_next").is_some()
+			},
+		"__has_attribute" => {
+			syntax_assert!(self, self.lexers.get_token_nospace(), Token::ParenOpen => ());
+			let name = syntax_assert!(self, self.lexers.get_token_nospace(), Token::Ident(i) => i);
+			syntax_assert!(self, self.lexers.get_token_nospace(), Token::ParenClose => ());
+			// `__foo__` is the same attribute as `foo`
+			let name = if name.len() > 4 && name.starts_with("__") && name.ends_with("__") { &name[2..name.len()-2] } else { &name[..] };
+			predefined::KNOWN_ATTRIBUTES.contains(&name)
+			},
+		_ => return Ok(None),
+		}))
 	}
 }
 
@@ -1267,38 +2033,116 @@ impl InnerLexer
 
 impl TokenSourceStack
 {
-	fn new(lexer: lex::Lexer<'static>, filename: Option<::std::path::PathBuf>) -> TokenSourceStack
+	fn new(lexer: lex::Lexer<'static>, filename: Option<::std::path::PathBuf>, sources: ::std::rc::Rc<source::SourceProvider>, lex_options: lex::Options) -> TokenSourceStack
 	{
 		TokenSourceStack {
+			files: vec![ SourceFile { path: filename.clone(), included_from: None, is_system_header: false, source: FileId(0) } ],
+			included: Vec::new(),
+			last_span: Span::start_of(FileId(0)),
+			last_hideset: HideSet::default(),
+			saved: None,
+			once_files: HashSet::new(),
+			include_guards: HashMap::new(),
 			lexers: vec![ InnerLexer::File(LexHandle {
 				lexer: lexer,
+				canonical_path: filename.as_ref().map(|p| sources.canonicalise(p)),
+				path: filename.clone(),
 				filename: filename,
 				line: 1,
-				}) ]
+				guard: IncludeGuard::Start,
+				search_dir: None,
+				}) ],
+			sources: sources,
+			lex_options: lex_options,
+			literal_errors: Vec::new(),
+			file_changes: Vec::new(),
 			}
 	}
 
-	fn push_file(&mut self, path: ::std::path::PathBuf) -> Result<()>
+	fn add_file(&mut self, path: Option<::std::path::PathBuf>, included_from: Option<Span>) -> FileId
+	{
+		let id = FileId(self.files.len());
+		self.files.push(SourceFile { path: path, included_from: included_from, is_system_header: false, source: id });
+		id
+	}
+
+	fn push_file(&mut self, found: FoundInclude, included_from: Span) -> Result<()>
 	{
+		let path = found.path;
+		let f = match self.sources.open(&path)
+			{
+			Ok(f) => f,
+			Err(e) => return Err(Error::IoError(e)),
+			};
+		let file_id = self.add_file(Some(path.clone()), Some(included_from));
+		self.files[file_id.0].is_system_header = found.is_system;
+		self.included.push(file_id);
+		self.file_changes.push(FileChange::Enter(file_id));
 		self.lexers.push(InnerLexer::File(LexHandle {
-			lexer: lex::Lexer::new(box match ::std::fs::File::open(&path)
-				{
-				Ok(f) => ::std::io::BufReader::new(f).chars(),
-				Err(e) => return Err(Error::IoError(e)),
-				}),
+			lexer: lex::Lexer::new(box ::std::io::BufReader::new(f).chars(), file_id, self.lex_options),
+			canonical_path: Some(self.sources.canonicalise(&path)),
+			path: Some(path.clone()),
 			filename: Some(path),
 			line: 1,
+			guard: IncludeGuard::Start,
+			search_dir: found.search_dir,
 			}));
 		Ok( () )
 	}
-	fn push_macro(&mut self, name: String, tokens: Vec<Token>)
+	/// Push an in-memory source buffer (e.g. the command-line options)
+	fn push_buffer(&mut self, name: ::std::path::PathBuf, contents: String)
+	{
+		let file_id = self.add_file(Some(name.clone()), None);
+		let chars: Vec<_> = contents.chars().collect();
+		self.lexers.push(InnerLexer::File(LexHandle {
+			lexer: lex::Lexer::new(box chars.into_iter().map(Ok), file_id, self.lex_options),
+			path: None,
+			filename: Some(name),
+			line: 1,
+			canonical_path: None,
+			guard: IncludeGuard::Unguarded,
+			search_dir: None,
+			}));
+	}
+	fn push_macro(&mut self, name: String, tokens: Vec<(Token,HideSet)>, span: Span)
 	{
 		self.lexers.push(InnerLexer::MacroExpansion(MacroExpansion {
 			name: name,
+			span: span,
 			tokens: tokens.into_iter(),
 			idx: 0,
+			is_argument: false,
 			}));
 	}
+	/// Push a macro argument to be pre-expanded (`get_token` returns EOF at its end, until `pop_argument` is called)
+	fn push_argument(&mut self, tokens: Vec<(Token,HideSet)>, span: Span)
+	{
+		assert!( self.saved.is_none() );
+		self.lexers.push(InnerLexer::MacroExpansion(MacroExpansion {
+			name: String::new(),
+			span: span,
+			tokens: tokens.into_iter(),
+			idx: 0,
+			is_argument: true,
+			}));
+	}
+	/// Remove the argument pushed by `push_argument` (and anything above it)
+	fn pop_argument(&mut self)
+	{
+		self.saved = None;
+		while let Some(l) = self.lexers.pop()
+		{
+			if let InnerLexer::MacroExpansion(MacroExpansion { is_argument: true, .. }) = l {
+				break;
+			}
+		}
+	}
+	/// Return a token to be read again by the next `get_token`
+	fn put_back(&mut self, tok: Token, span: Span, hideset: HideSet)
+	{
+		assert!( self.saved.is_none() );
+		self.saved = Some( (tok, span, hideset) );
+	}
 
 	fn last(&self) -> &InnerLexer {
 		assert!( self.lexers.len() >= 1 );
@@ -1309,36 +2153,159 @@ impl TokenSourceStack
 		self.lexers.last_mut().unwrap()
 	}
 
-	fn cur_path(&self) -> &::std::path::Path
+	fn cur_file(&self) -> &LexHandle
 	{
-		match self.last()
-		{
-		InnerLexer::File(h) => h.filename.as_ref().expect("TODO: Error when using include in stdin"),
-		InnerLexer::MacroExpansion(_) => panic!(""),
+		self.lexers.iter().rev()
+			.filter_map(|l| match l { InnerLexer::File(h) => Some(h), _ => None })
+			.next()
+			.expect("BUG: No file lexers on the stack")
+	}
+	/// Number of nested `#include`s currently being processed
+	fn include_depth(&self) -> usize
+	{
+		self.lexers.iter().filter(|l| match l { InnerLexer::File(_) => true, _ => false }).count() - 1
+	}
+	fn cur_file_mut(&mut self) -> &mut LexHandle
+	{
+		self.lexers.iter_mut().rev()
+			.filter_map(|l| match l { InnerLexer::File(h) => Some(h), _ => None })
+			.next()
+			.expect("BUG: No file lexers on the stack")
+	}
+	/// Path of the current file (`None` if reading from stdin)
+	fn cur_path(&self) -> Option<&::std::path::Path>
+	{
+		self.cur_file().path.as_ref().map(|p| p.as_path())
+	}
+	/// Returns true if the current file is a system header
+	fn cur_is_system(&self) -> bool
+	{
+		self.files[self.cur_file().lexer.file().0].is_system_header
+	}
+	/// Mark the current file as only being included once (`#pragma once`)
+	fn set_once(&mut self)
+	{
+		if let Some(p) = self.cur_file().canonical_path.clone() {
+			self.once_files.insert(p);
+		}
+	}
+
+	/// Set the presumed location of the next line (`#line` and linemarkers)
+	///
+	/// `flags` are the linemarker flags (1 = start of an included file, 2 = return to the including file), and `span`
+	/// is the location of the directive.
+	fn set_presumed_location(&mut self, line: usize, filename: Option<::std::path::PathBuf>, flags: &[u64], span: &Span)
+	{
+		let cur_id = self.cur_file().lexer.file();
+		let n_files = self.files.len();
+		let new_id = match filename
+			{
+			None => cur_id,
+			Some(ref path) if flags.contains(&1) => {
+				self.add_file(Some(path.clone()), Some(span.clone()))
+				},
+			Some(ref path) if flags.contains(&2) => {
+				// Find the includer with this name, so the include stack stays correct
+				let mut id = cur_id;
+				loop
+				{
+					match self.files[id.0].included_from
+					{
+					Some(ref inc) => id = inc.file,
+					None => break self.add_file(Some(path.clone()), None),
+					}
+					if self.files[id.0].path.as_ref() == Some(path) {
+						break id;
+					}
+				}
+				},
+			Some(ref path) if self.files[cur_id.0].path.as_ref() == Some(path) => cur_id,
+			Some(ref path) => {
+				let included_from = self.files[cur_id.0].included_from.clone();
+				self.add_file(Some(path.clone()), included_from)
+				},
+			};
+		debug!("Set location to {:?}:{} ({:?})", filename, line, new_id);
+		// A newly named file's text is still read from the current file
+		if new_id.0 >= n_files {
+			self.files[new_id.0].source = self.files[cur_id.0].source;
+		}
+		// Flag 3 marks the following text as coming from a system header
+		if filename.is_some() {
+			self.files[new_id.0].is_system_header = flags.contains(&3);
+		}
+
+		let h = self.cur_file_mut();
+		h.line = line;
+		h.lexer.set_file(new_id);
+		if filename.is_some() {
+			h.filename = filename;
 		}
 	}
 
+	/// Get a token from the top of the stack (location is saved in `last_span`, and hide set in `last_hideset`)
 	fn get_token(&mut self) -> Result<Token>
 	{
+		if let Some( (t, span, hideset) ) = self.saved.take()
+		{
+			self.last_span = span;
+			self.last_hideset = hideset;
+			return Ok(t);
+		}
 		loop
 		{
-			let t = match self.lexers.last_mut()
+			let (t, span, hideset) = match self.lexers.last_mut()
 				{
 				None => return Ok(Token::EOF),
-				Some(InnerLexer::File(h)) => h.get_token()?,
+				Some(InnerLexer::File(h)) => {
+					let (t, span) = h.get_token()?;
+					let errors = h.lexer.take_literal_errors();
+					self.literal_errors.extend( errors.into_iter().map(|e| (span.clone(), e)) );
+					(t, span, HideSet::default())
+					},
 				Some(InnerLexer::MacroExpansion(h)) => h.get_token()?,
 				};
 			match t
 			{
+			// The end of a pre-expanded argument isn't popped (see `pop_argument`)
+			Token::EOF if self.is_argument() => {
+				self.last_span = span;
+				self.last_hideset = hideset;
+				return Ok(Token::EOF);
+				},
 			Token::EOF if self.lexers.len() > 1 => {
 				// EOF on inner parse: Pop and continue
 				// TODO: Return a marker token that indicates the end of a file?
-				self.lexers.pop();
+				if let Some(InnerLexer::File(h)) = self.lexers.pop()
+				{
+					// NOTE: Only `#include`d files have a path (source buffers don't)
+					if h.path.is_some() {
+						let includer = self.cur_file();
+						self.file_changes.push(FileChange::Leave(includer.lexer.file(), includer.line));
+					}
+					if let (Some(path), IncludeGuard::Closed(name)) = (h.canonical_path, h.guard)
+					{
+						debug!("{} is guarded by {}", path.display(), name);
+						self.include_guards.insert(path, name);
+					}
+				}
+				},
+			t => {
+				self.last_span = span;
+				self.last_hideset = hideset;
+				return Ok(t);
 				},
-			t => return Ok(t),
 			}
 		}
 	}
+	fn is_argument(&self) -> bool
+	{
+		match self.lexers.last()
+		{
+		Some(InnerLexer::MacroExpansion(h)) => h.is_argument,
+		_ => false,
+		}
+	}
 	fn get_token_nospace(&mut self) -> Result<Token>
 	{
 		loop
@@ -1354,6 +2321,15 @@ impl TokenSourceStack
 		}
 	}
 
+	/// Get the (raw) remainder of the current directive line, for `#error`/`#warning`
+	fn get_restofline(&mut self) -> Result<String>
+	{
+		match self.last_mut()
+		{
+		InnerLexer::File(h) => h.lexer.get_token_restofline(),
+		InnerLexer::MacroExpansion(_) => Ok(String::new()),
+		}
+	}
 	fn get_includestr(&mut self) -> Result<Option<String>>
 	{
 		match self.last_mut()
@@ -1363,35 +2339,209 @@ impl TokenSourceStack
 		}
 	}
 }
+/// Check a macro's replacement list for misplaced `#`, `##`, and `__VA_OPT__` (C11 6.10.3.2p1 and 6.10.3.3p1)
+fn check_replacement_list(body: &[Token], args: Option<&Vec<String>>, va_args_name: Option<&String>) -> ::std::result::Result<(),DirectiveError>
+{
+	if paste_operator_at(body, 0).is_some() || (body.len() >= 2 && body[body.len()-2].is_hash() && body[body.len()-1].is_hash()) {
+		return Err(DirectiveError::PasteAtEdge);
+	}
+	let args = match args
+		{
+		Some(v) => v,
+		None => return Ok( () ),	// `#` isn't an operator in object-like macros
+		};
+	let mut i = 0;
+	while i < body.len()
+	{
+		if let Some(next) = paste_operator_at(body, i) {
+			i = next;
+			continue ;
+		}
+		match body[i]
+		{
+		Token::Hash | Token::Digraph(Digraph::Hash) => {
+			i = skip_whitespace(body, i + 1);
+			match body.get(i)
+			{
+			Some(Token::Ident(ref n)) if args.contains(n) || va_args_name == Some(n) => {},
+			Some(Token::Ident(ref n)) if va_args_name.is_some() && n == "__VA_OPT__" => {},
+			_ => return Err(DirectiveError::StringifyNonParameter),
+			}
+			},
+		Token::Ident(ref n) if va_args_name.is_some() && n == "__VA_OPT__" => {
+			va_opt_content(body, i)?;
+			i += 1;
+			},
+		_ => i += 1,
+		}
+	}
+	Ok( () )
+}
+/// If there's a `##` at `i`, return the index after it (and after any following whitespace)
+fn paste_operator_at(body: &[Token], i: usize) -> Option<usize>
+{
+	if body.len() >= i + 2 && body[i].is_hash() && body[i+1].is_hash() {
+		Some( skip_whitespace(body, i + 2) )
+	}
+	else {
+		None
+	}
+}
+fn skip_whitespace(body: &[Token], mut i: usize) -> usize
+{
+	while body.get(i) == Some(&Token::Whitespace) {
+		i += 1;
+	}
+	i
+}
+fn trim_whitespace(tokens: &[(Token,HideSet)]) -> &[(Token,HideSet)]
+{
+	let is_space = |t: &(Token,HideSet)| match t.0 { Token::Whitespace | Token::Newline | Token::EscapedNewline | Token::LineComment(_) | Token::BlockComment(_) => true, _ => false };
+	let start = tokens.iter().position(|t| !is_space(t)).unwrap_or(tokens.len());
+	let end = tokens.iter().rposition(|t| !is_space(t)).map(|i| i + 1).unwrap_or(start);
+	&tokens[start .. end]
+}
+/// Returns true if the parameter `name` is used in `body` other than as an operand of `#` or `##` (so the argument
+/// needs to be fully expanded)
+fn is_plain_parameter(body: &[Token], name: &str) -> bool
+{
+	let mut prev: Option<usize> = None;
+	for (i, t) in body.iter().enumerate()
+	{
+		match *t
+		{
+		Token::Whitespace => continue,
+		Token::Ident(ref n) if n == name => {
+			// NOTE: `##` is two `#` tokens, so either way the previous token is a `#`
+			let after_operator = prev.map(|p| body[p].is_hash()).unwrap_or(false);
+			let before_paste = paste_operator_at(body, skip_whitespace(body, i + 1)).is_some();
+			if !after_operator && !before_paste {
+				return true;
+			}
+			},
+		_ => {},
+		}
+		prev = Some(i);
+	}
+	false
+}
+/// Get the contents of the `__VA_OPT__(...)` at `i`, and the index after it
+fn va_opt_content(body: &[Token], i: usize) -> ::std::result::Result<(&[Token], usize),DirectiveError>
+{
+	let start = skip_whitespace(body, i + 1);
+	if body.get(start) != Some(&Token::ParenOpen) {
+		return Err(DirectiveError::BadVaOpt);
+	}
+	let mut level = 0;
+	for (j, t) in body.iter().enumerate().skip(start)
+	{
+		match *t
+		{
+		Token::ParenOpen => level += 1,
+		Token::ParenClose => {
+			level -= 1;
+			if level == 0 {
+				return Ok( (&body[start+1 .. j], j + 1) );
+			}
+			},
+		_ => {},
+		}
+	}
+	Err(DirectiveError::BadVaOpt)
+}
+/// Stringify a macro argument (C11 6.10.3.2p2)
+///
+/// Whitespace between tokens becomes a single space. The result is the (unescaped) spelling of the tokens, so the
+/// quotes and backslashes in string and character constants are escaped when the string is written back out.
+fn stringify(tokens: &[(Token,HideSet)]) -> String
+{
+	let mut rv = String::new();
+	let mut space = false;
+	for &(ref t, _) in trim_whitespace(tokens)
+	{
+		match *t
+		{
+		Token::Whitespace | Token::Newline | Token::EscapedNewline | Token::LineComment(_) | Token::BlockComment(_) => {
+			space = true;
+			},
+		_ => {
+			if ::std::mem::replace(&mut space, false) {
+				rv.push(' ');
+			}
+			rv.push_str(&t.to_string());
+			},
+		}
+	}
+	rv
+}
+/// Paste two tokens (`##`) by re-lexing their combined spelling
+///
+/// The result must be a single token spelled by all of the text (`##` and `%:%:` being lexed as two `#`s).
+fn paste_tokens(lhs: (Token,HideSet), rhs: (Token,HideSet), lex_options: lex::Options) -> ::std::result::Result<(Token,HideSet),DirectiveError>
+{
+	let hideset = lhs.1.union(&rhs.1);
+	let (lhs, rhs) = (lhs.0.to_string(), rhs.0.to_string());
+	let text = format!("{}{}", lhs, rhs);
+	let mut lex = lex::Lexer::new(box text.chars().collect::<Vec<_>>().into_iter().map(Ok), FileId(0), lex_options);
+	let mut tokens = Vec::new();
+	let mut end = 0;
+	loop
+	{
+		match lex.get_token()
+		{
+		Ok((Token::EOF, _)) => break,
+		Ok((tok, span)) => {
+			end = span.offset + span.len;
+			tokens.push(tok);
+			},
+		Err(_) => return Err(DirectiveError::InvalidPaste(lhs, rhs)),
+		}
+	}
+	let tok = match tokens[..]
+		{
+		[Token::Whitespace] | [Token::LineComment(_)] | [Token::BlockComment(_)] => None,
+		[ref tok] => Some(tok.clone()),
+		[Token::Hash, Token::Hash] => Some(Token::DoubleHash),
+		[Token::Digraph(Digraph::Hash), Token::Digraph(Digraph::Hash)] => Some(Token::Digraph(Digraph::DoubleHash)),
+		_ => None,
+		};
+	match tok
+	{
+	Some(tok) if end == text.len() => Ok( (tok, hideset) ),
+	_ => Err(DirectiveError::InvalidPaste(lhs, rhs)),
+	}
+}
+
 impl LexHandle
 {
-	fn get_token(&mut self) -> Result<Token>
+	fn get_token(&mut self) -> Result<(Token,Span)>
 	{
-		match self.lexer.get_token()?
+		let (tok, mut span) = self.lexer.get_token()?;
+		span.line = self.line;
+		match tok
 		{
-		t @ Token::Newline | t @ Token::EscapedNewline => {
+		Token::Newline | Token::EscapedNewline => {
 			self.line += 1;
-			Ok(t)
 			},
 		// - Line comments still return the trailing newline
 		// - Block comments can have newlines.
-		Token::BlockComment(bc) => {
+		Token::BlockComment(ref bc) => {
 			self.line += bc.bytes().filter(|x| *x == b'\n').count();
-			Ok( Token::BlockComment(bc) )
 			},
-		t => Ok(t),
+		_ => {},
 		}
+		Ok( (tok, span) )
 	}
 }
 impl MacroExpansion
 {
-	fn get_token(&mut self) -> Result<Token>
+	fn get_token(&mut self) -> Result<(Token,Span,HideSet)>
 	{
 		Ok(match self.tokens.next()
 			{
-			None => Token::EOF,
-			Some(Token::EOF) => panic!("How did an EOF end up in a macro expansion?"),
-			Some(t) => { self.idx += 1; t }
+			None => (Token::EOF, self.span.clone(), HideSet::default()),
+			Some((Token::EOF, _)) => panic!("How did an EOF end up in a macro expansion?"),
+			Some((t, hs)) => { self.idx += 1; (t, self.span.clone(), hs) }
 			})
 	}
 }
@@ -0,0 +1,203 @@
+/*!
+ * Built-in and predefined macros
+ */
+
+/// Macros with values computed when they're expanded (handled by `Preproc::expand_builtin`)
+pub(super) const DYNAMIC_MACROS: &[&str] = &[
+	"__FILE__",
+	"__LINE__",
+	"__DATE__",
+	"__TIME__",
+	"__COUNTER__",
+	"__INCLUDE_LEVEL__",
+	];
+
+/// Operators only valid in `#if`/`#elif` (reported as defined, so they can be tested for with `#ifdef`)
+pub(super) const IF_OPERATORS: &[&str] = &[
+	"__has_include",
+	"__has_include_next",
+	"__has_attribute",
+	];
+
+/// GCC attributes that the parser accepts (`__has_attribute` is true for these)
+pub(super) const KNOWN_ATTRIBUTES: &[&str] = &[
+	"deprecated",
+	"noreturn",
+	"packed",
+	"section",
+	"unused",
+	"warn_unused_result",
+	];
+
+/// Target profile, selects the predefined macros (`--target`)
+#[derive(Debug,Copy,Clone,PartialEq)]
+pub enum Target
+{
+	/// x86_64-linux-gnu (LP64)
+	X86_64Linux,
+	/// i686-linux-gnu (ILP32)
+	I686Linux,
+	/// aarch64-linux-gnu (LP64, unsigned char)
+	Aarch64Linux,
+}
+impl Default for Target
+{
+	fn default() -> Self {
+		Target::X86_64Linux
+	}
+}
+impl ::std::str::FromStr for Target
+{
+	type Err = String;
+	fn from_str(s: &str) -> Result<Self,String>
+	{
+		match s
+		{
+		"x86_64" | "x86_64-linux-gnu" | "x86_64-unknown-linux-gnu" => Ok(Target::X86_64Linux),
+		"i386" | "i686" | "i686-linux-gnu" | "i686-unknown-linux-gnu" => Ok(Target::I686Linux),
+		"aarch64" | "aarch64-linux-gnu" | "aarch64-unknown-linux-gnu" => Ok(Target::Aarch64Linux),
+		_ => Err(format!("Unknown target '{}', expected x86_64-linux-gnu/i686-linux-gnu/aarch64-linux-gnu", s)),
+		}
+	}
+}
+impl Target
+{
+	pub(super) fn is_64bit(&self) -> bool
+	{
+		match *self
+		{
+		Target::X86_64Linux => true,
+		Target::I686Linux => false,
+		Target::Aarch64Linux => true,
+		}
+	}
+
+	/// Returns true if plain `char` is signed
+	pub fn char_is_signed(&self) -> bool
+	{
+		match *self
+		{
+		Target::X86_64Linux => true,
+		Target::I686Linux => true,
+		Target::Aarch64Linux => false,
+		}
+	}
+
+	/// Get the predefined macros for this target, as `(name, value)` pairs
+	pub fn predefined_macros(&self) -> Vec<(&'static str, &'static str)>
+	{
+		let mut rv = vec![
+			// - Language
+			("__STDC__", "1"),
+			("__STDC_VERSION__", "201112L"),
+			("__STDC_HOSTED__", "1"),
+			// - Type sizes/limits
+			("__CHAR_BIT__", "8"),
+			("__SIZEOF_SHORT__", "2"),
+			("__SIZEOF_INT__", "4"),
+			("__SIZEOF_LONG_LONG__", "8"),
+			("__SIZEOF_FLOAT__", "4"),
+			("__SIZEOF_DOUBLE__", "8"),
+			("__SCHAR_MAX__", "0x7f"),
+			("__SHRT_MAX__", "0x7fff"),
+			("__INT_MAX__", "0x7fffffff"),
+			("__LONG_LONG_MAX__", "0x7fffffffffffffffLL"),
+			// - Byte order
+			("__ORDER_LITTLE_ENDIAN__", "1234"),
+			("__ORDER_BIG_ENDIAN__", "4321"),
+			("__BYTE_ORDER__", "__ORDER_LITTLE_ENDIAN__"),
+			// - OS
+			("__linux__", "1"),
+			("__linux", "1"),
+			("__gnu_linux__", "1"),
+			("__unix__", "1"),
+			("__unix", "1"),
+			("__ELF__", "1"),
+			];
+
+		if self.is_64bit() {
+			rv.extend_from_slice(&[
+				("_LP64", "1"),
+				("__LP64__", "1"),
+				("__SIZEOF_LONG__", "8"),
+				("__SIZEOF_POINTER__", "8"),
+				("__SIZEOF_SIZE_T__", "8"),
+				("__SIZEOF_LONG_DOUBLE__", "16"),
+				("__LONG_MAX__", "0x7fffffffffffffffL"),
+				("__SIZE_MAX__", "0xffffffffffffffffUL"),
+				("__SIZE_TYPE__", "long unsigned int"),
+				("__PTRDIFF_TYPE__", "long int"),
+				("__INTPTR_TYPE__", "long int"),
+				("__INTMAX_TYPE__", "long int"),
+				("__UINTMAX_TYPE__", "long unsigned int"),
+				]);
+		}
+		else {
+			rv.extend_from_slice(&[
+				("_ILP32", "1"),
+				("__ILP32__", "1"),
+				("__SIZEOF_LONG__", "4"),
+				("__SIZEOF_POINTER__", "4"),
+				("__SIZEOF_SIZE_T__", "4"),
+				("__SIZEOF_LONG_DOUBLE__", "12"),
+				("__LONG_MAX__", "0x7fffffffL"),
+				("__SIZE_MAX__", "0xffffffffU"),
+				("__SIZE_TYPE__", "unsigned int"),
+				("__PTRDIFF_TYPE__", "int"),
+				("__INTPTR_TYPE__", "int"),
+				("__INTMAX_TYPE__", "long long int"),
+				("__UINTMAX_TYPE__", "long long unsigned int"),
+				]);
+		}
+
+		match *self
+		{
+		Target::X86_64Linux => rv.extend_from_slice(&[
+			("__x86_64__", "1"),
+			("__x86_64", "1"),
+			("__amd64__", "1"),
+			("__amd64", "1"),
+			("__WCHAR_TYPE__", "int"),
+			]),
+		Target::I686Linux => rv.extend_from_slice(&[
+			("__i386__", "1"),
+			("__i386", "1"),
+			("__i686__", "1"),
+			("__WCHAR_TYPE__", "long int"),
+			]),
+		Target::Aarch64Linux => rv.extend_from_slice(&[
+			("__aarch64__", "1"),
+			("__CHAR_UNSIGNED__", "1"),
+			("__WCHAR_TYPE__", "unsigned int"),
+			]),
+		}
+		rv
+	}
+}
+
+/// Format a time (seconds since the UNIX epoch, UTC) as the `__DATE__` and `__TIME__` strings
+pub(super) fn format_date_time(secs: u64) -> (String, String)
+{
+	const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+	let days = (secs / 86400) as i64;
+	let time_of_day = secs % 86400;
+
+	// Convert days since 1970-01-01 to a civil (proleptic Gregorian) date
+	let z = days + 719468;
+	let era = z / 146097;
+	let doe = z - era * 146097;
+	let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+	let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+	let mp = (5 * doy + 2) / 153;
+	let day = doy - (153 * mp + 2) / 5 + 1;
+	let month = if mp < 10 { mp + 3 } else { mp - 9 };
+	let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+	(
+		format!("{} {:2} {}", MONTHS[(month - 1) as usize], day, year),
+		format!("{:02}:{:02}:{:02}", time_of_day / 3600, time_of_day / 60 % 60, time_of_day % 60),
+	)
+}
+
+// vim: ft=rust
@@ -0,0 +1,327 @@
+/*!
+ * Pre-process only output (`-E`)
+ *
+ * Writes the fully-expanded token stream back out as C source, with GCC-style `# <line> "<file>"` linemarkers
+ */
+use std::io::Write;
+use super::{Preproc,Token,Span,FileId,FileChange};
+use diagnostics::{Diagnostic,Severity};
+
+/// What `preprocess` writes
+#[derive(Debug,Copy,Clone,PartialEq)]
+pub enum Output
+{
+	/// The expanded source (optionally with linemarkers)
+	Source {
+		linemarkers: bool,
+	},
+	/// `#define`s for the final macro table (`-dM`)
+	Macros,
+	/// Nothing (`-M`, where only the dependencies are wanted)
+	Nothing,
+}
+
+/// Maximum number of blank lines emitted before a linemarker is used instead
+const MAX_BLANK_LINES: usize = 8;
+
+/// Pre-process a file, writing the expanded source (or macro table) to `out`
+///
+/// Returns all diagnostics raised (pre-processing continues past non-fatal errors). The files read are stored in
+/// `deps`.
+pub fn preprocess(out: &mut Write, filename: &::std::path::Path, options: super::Options, output: Output, deps: &mut Vec<super::deps::Dependency>) -> Vec<Diagnostic>
+{
+	let mut pp = match Preproc::new(Some(filename), options)
+		{
+		Ok(v) => v,
+		Err(e) => return vec![ Diagnostic::new(Severity::Error, e.code(), format!("{}: {}", filename.display(), e)) ],
+		};
+	let mut diagnostics = Vec::new();
+
+	let linemarkers = match output
+		{
+		Output::Source { linemarkers } => linemarkers,
+		Output::Macros | Output::Nothing => false,
+		};
+	let mut printer = Printer {
+		out: out,
+		linemarkers: linemarkers,
+		cur_file: FileId(0),
+		cur_line: 1,
+		at_line_start: true,
+		last_end: None,
+		};
+	if linemarkers
+	{
+		if let Err(e) = printer.write_linemarker(&pp, 1, "")
+		{
+			diagnostics.push( Diagnostic::new(Severity::Error, "io-error", format!("Error writing output: {}", e)) );
+			return diagnostics;
+		}
+	}
+	loop
+	{
+		let tok = pp.get_token_int();
+		diagnostics.extend( pp.take_diagnostics() );
+		if let Output::Source { .. } = output
+		{
+			for change in pp.take_file_changes()
+			{
+				if let Err(e) = printer.change_file(&pp, change)
+				{
+					diagnostics.push( Diagnostic::new(Severity::Error, "io-error", format!("Error writing output: {}", e)) );
+					return diagnostics;
+				}
+			}
+		}
+		let tok = match tok
+			{
+			Ok((Token::EOF, _)) => break,
+			Ok((t, _)) => t,
+			Err(e) => {
+				let span = match e
+					{
+					super::Error::Directive(ref s, _) => s.clone(),
+					_ => pp.span().clone(),
+					};
+				diagnostics.push( pp.diagnostic(Severity::Error, e.code(), &span, e.to_string()) );
+				if e.is_fatal() {
+					break;
+				}
+				continue;
+				},
+			};
+		match output
+		{
+		Output::Source { .. } => {},
+		Output::Macros | Output::Nothing => continue,
+		}
+		if let Err(e) = printer.write_token(&pp, &tok, pp.span())
+		{
+			diagnostics.push( Diagnostic::new(Severity::Error, "io-error", format!("Error writing output: {}", e)) );
+			return diagnostics;
+		}
+	}
+	let rv = if output == Output::Macros {
+			write_macros(printer.out, &pp)
+		}
+		else if ! printer.at_line_start {
+			printer.out.write_all(b"\n")
+		}
+		else {
+			Ok( () )
+		};
+	if let Err(e) = rv
+	{
+		diagnostics.push( Diagnostic::new(Severity::Error, "io-error", format!("Error writing output: {}", e)) );
+	}
+	*deps = pp.dependencies();
+
+	diagnostics
+}
+
+struct Printer<'a>
+{
+	out: &'a mut Write,
+	linemarkers: bool,
+	/// File (and line within that file) of the current output line
+	cur_file: FileId,
+	cur_line: usize,
+	/// Output is at the start of `cur_line`
+	at_line_start: bool,
+	/// End offset of the previous token (if it came directly from a file, not a macro expansion)
+	last_end: Option<(FileId, usize)>,
+}
+impl<'a> Printer<'a>
+{
+	fn write_token(&mut self, pp: &Preproc, tok: &Token, span: &Span) -> ::std::io::Result<()>
+	{
+		if let Token::Preprocessor(super::token::Preprocessor::Pragma(ref text)) = *tok {
+			return self.write_pragma(pp, text, span);
+		}
+		// NOTE: `#include`s are handled by `change_file`, this is for `#line` (and the end of `-include`d files)
+		if self.cur_file != span.file
+		{
+			self.end_line()?;
+			self.cur_file = span.file;
+			self.cur_line = span.line;
+			if self.linemarkers {
+				self.write_linemarker(pp, span.line, "")?;
+			}
+		}
+		else
+		{
+			self.move_to_line(pp, span.line)?;
+		}
+
+		if self.at_line_start {
+			// Keep the original indentation
+			for _ in 1 .. span.column {
+				self.out.write_all(b" ")?;
+			}
+		}
+		else if self.last_end != Some( (span.file, span.offset) ) || span.expansion.is_some() {
+			// Tokens that weren't adjacent in the source (or came from a macro) are separated by a space, this also
+			// ensures that two tokens are never pasted together.
+			self.out.write_all(b" ")?;
+		}
+		write!(self.out, "{}", tok)?;
+		self.at_line_start = false;
+		self.last_end = if span.expansion.is_none() { Some( (span.file, span.offset + span.len) ) } else { None };
+
+		match *tok
+		{
+		// A line comment extends to the end of the line
+		Token::LineComment(_) => self.end_line()?,
+		// Comments (kept with `-C`) can span multiple lines
+		Token::BlockComment(ref s) => self.cur_line += s.matches('\n').count(),
+		_ => {},
+		}
+		Ok( () )
+	}
+
+	/// Write a pragma passed on by the pre-processor (on a line of its own)
+	fn write_pragma(&mut self, pp: &Preproc, text: &str, span: &Span) -> ::std::io::Result<()>
+	{
+		// A `_Pragma` can be in the middle of a line, in which case the line is split around it (as GCC does)
+		let mid_line = span.file != self.cur_file || span.line < self.cur_line || (span.line == self.cur_line && ! self.at_line_start);
+		if mid_line {
+			self.end_line()?;
+			self.cur_file = span.file;
+			if self.linemarkers {
+				self.write_linemarker(pp, span.line, "")?;
+			}
+		}
+		else {
+			self.move_to_line(pp, span.line)?;
+		}
+		writeln!(self.out, "#pragma {}", text)?;
+		self.cur_line = span.line + 1;
+		self.last_end = None;
+		if mid_line {
+			if self.linemarkers {
+				self.write_linemarker(pp, span.line, "")?;
+			}
+			self.cur_line = span.line;
+		}
+		Ok( () )
+	}
+
+	/// Start or end of an `#include`d file
+	fn change_file(&mut self, pp: &Preproc, change: FileChange) -> ::std::io::Result<()>
+	{
+		// Flag 1 = entering an include, 2 = returning to the includer
+		let (file, line, flag) = match change
+			{
+			FileChange::Enter(file) => {
+				// Output moves to the `#include` line first (as GCC does)
+				if let Some(ref inc) = pp.source_file(file).included_from {
+					if inc.file == self.cur_file {
+						self.move_to_line(pp, inc.line)?;
+					}
+				}
+				(file, 1, " 1")
+				},
+			FileChange::Leave(file, line) => (file, line, " 2"),
+			};
+		self.end_line()?;
+		self.cur_file = file;
+		self.cur_line = line;
+		self.last_end = None;
+		if self.linemarkers {
+			self.write_linemarker(pp, line, flag)?;
+		}
+		Ok( () )
+	}
+
+	/// Move output down to a later line of the current file
+	fn move_to_line(&mut self, pp: &Preproc, line: usize) -> ::std::io::Result<()>
+	{
+		if line > self.cur_line
+		{
+			self.end_line()?;
+			let blank_lines = line.saturating_sub(self.cur_line);
+			if ! self.linemarkers {
+				// Blank lines are only needed to keep line numbers right
+			}
+			else if blank_lines > MAX_BLANK_LINES {
+				self.write_linemarker(pp, line, "")?;
+			}
+			else {
+				for _ in 0 .. blank_lines {
+					self.out.write_all(b"\n")?;
+				}
+			}
+			self.cur_line = line;
+		}
+		Ok( () )
+	}
+
+	fn end_line(&mut self) -> ::std::io::Result<()>
+	{
+		if ! self.at_line_start {
+			self.out.write_all(b"\n")?;
+			self.at_line_start = true;
+			self.cur_line += 1;
+		}
+		Ok( () )
+	}
+
+	fn write_linemarker(&mut self, pp: &Preproc, line: usize, flag: &str) -> ::std::io::Result<()>
+	{
+		let file = pp.source_file(self.cur_file);
+		let path = match file.path
+			{
+			Some(ref p) => p.display().to_string(),
+			None => "<stdin>".to_owned(),
+			};
+		// Flag 3 = system header
+		let system_flag = if file.is_system_header { " 3" } else { "" };
+		writeln!(self.out, "# {} {}{}{}", line, Token::String(path.into()), flag, system_flag)
+	}
+}
+
+/// Write the macro table as `#define` lines (sorted by name)
+fn write_macros(out: &mut Write, pp: &Preproc) -> ::std::io::Result<()>
+{
+	let mut names: Vec<&String> = pp.macros.keys().collect();
+	names.sort();
+	for name in names
+	{
+		let m = &pp.macros[name];
+		write!(out, "#define {}", name)?;
+		if let Some(ref args) = m.arg_names
+		{
+			write!(out, "(")?;
+			for (i, a) in args.names.iter().enumerate()
+			{
+				if i > 0 { write!(out, ",")?; }
+				write!(out, "{}", a)?;
+			}
+			match args.va_args_name
+			{
+			None => {},
+			Some(ref v) => {
+				if args.names.len() > 0 { write!(out, ",")?; }
+				if v != "__VA_ARGS__" { write!(out, "{}", v)?; }
+				write!(out, "...")?;
+				},
+			}
+			write!(out, ")")?;
+		}
+		if m.expansion.len() > 0 {
+			write!(out, " ")?;
+		}
+		for t in &m.expansion
+		{
+			match *t
+			{
+			Token::Whitespace => write!(out, " ")?,
+			ref t => write!(out, "{}", t)?,
+			}
+		}
+		writeln!(out, "")?;
+	}
+	Ok( () )
+}
+
+// vim: ft=rust
@@ -0,0 +1,127 @@
+/*!
+ * Source providers, supplying the contents of files to the pre-processor
+ *
+ * By default files are read from disk (`FileSystem`), an `Overlay` allows in-memory contents (e.g. unsaved editor
+ * buffers) to replace or add to those files.
+ */
+use std::collections::HashMap;
+use std::path::{Path,PathBuf};
+
+/// Supplies the contents of source files (set with `Options::sources`)
+pub trait SourceProvider
+{
+	/// Returns true if `path` names a readable file (used when searching for `#include`s)
+	fn is_file(&self, path: &Path) -> bool;
+	/// Open a file for reading
+	fn open(&self, path: &Path) -> ::std::io::Result<Box<::std::io::Read>>;
+	/// Canonical form of a path, used to identify files for `#pragma once` and include guards
+	fn canonicalise(&self, path: &Path) -> PathBuf;
+}
+
+/// Reads files from disk
+pub struct FileSystem;
+impl SourceProvider for FileSystem
+{
+	fn is_file(&self, path: &Path) -> bool
+	{
+		path.is_file()
+	}
+	fn open(&self, path: &Path) -> ::std::io::Result<Box<::std::io::Read>>
+	{
+		Ok( box ::std::fs::File::open(path)? )
+	}
+	fn canonicalise(&self, path: &Path) -> PathBuf
+	{
+		// Falls back to the path as given if it can't be resolved
+		::std::fs::canonicalize(path).unwrap_or_else(|_| path.to_owned())
+	}
+}
+
+/// In-memory file contents, layered over another provider
+///
+/// Paths are made absolute (relative to the current directory) and lexically normalised (removing `.` components and
+/// resolving `..`), so `dir/../foo.h` finds an entry added as `foo.h`.
+pub struct Overlay
+{
+	files: HashMap<PathBuf, String>,
+	/// Provider used for files not in the overlay (`None` for only in-memory files)
+	base: Option<Box<SourceProvider>>,
+}
+impl Overlay
+{
+	/// Create an empty overlay, with `base` used for any file not added to it
+	pub fn new(base: Option<Box<SourceProvider>>) -> Overlay
+	{
+		Overlay {
+			files: HashMap::new(),
+			base: base,
+			}
+	}
+	/// Add (or replace) a file
+	pub fn add_file<P: AsRef<Path>>(&mut self, path: P, contents: String)
+	{
+		self.files.insert(absolute(path.as_ref()), contents);
+	}
+}
+impl SourceProvider for Overlay
+{
+	fn is_file(&self, path: &Path) -> bool
+	{
+		self.files.contains_key(&absolute(path)) || self.base.as_ref().map(|b| b.is_file(path)).unwrap_or(false)
+	}
+	fn open(&self, path: &Path) -> ::std::io::Result<Box<::std::io::Read>>
+	{
+		match self.files.get(&absolute(path))
+		{
+		Some(c) => Ok( box ::std::io::Cursor::new(c.clone().into_bytes()) ),
+		None => match self.base
+			{
+			Some(ref b) => b.open(path),
+			None => Err(::std::io::Error::new(::std::io::ErrorKind::NotFound, format!("{}: not in the source overlay", path.display()))),
+			},
+		}
+	}
+	fn canonicalise(&self, path: &Path) -> PathBuf
+	{
+		// NOTE: Overlay entries are already absolute, as the base's canonical paths are
+		let p = absolute(path);
+		match self.base
+		{
+		Some(ref b) if !self.files.contains_key(&p) => b.canonicalise(path),
+		_ => p,
+		}
+	}
+}
+
+/// Make a path absolute (relative to the current directory) and lexically normalise it
+fn absolute(path: &Path) -> PathBuf
+{
+	match ::std::env::current_dir()
+	{
+	Ok(cwd) => normalise(&cwd.join(path)),
+	Err(_) => normalise(path),
+	}
+}
+/// Lexically normalise a path (remove `.` components, and resolve `..` where possible)
+fn normalise(path: &Path) -> PathBuf
+{
+	use std::path::Component;
+	let mut rv = PathBuf::new();
+	for c in path.components()
+	{
+		match c
+		{
+		Component::CurDir => {},
+		Component::ParentDir => match rv.components().next_back()
+			{
+			Some(Component::Normal(_)) => { rv.pop(); },
+			Some(Component::RootDir) => {},
+			_ => rv.push(".."),
+			},
+		c => rv.push(c.as_os_str()),
+		}
+	}
+	rv
+}
+
+// vim: ft=rust
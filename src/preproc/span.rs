@@ -0,0 +1,84 @@
+//! Source locations attached to tokens
+use std::rc::Rc;
+
+/// Index of a file in the pre-processor's file list (see `Preproc::source_file`)
+#[derive(Debug,Copy,Clone,PartialEq,Eq,Hash)]
+pub struct FileId(pub(super) usize);
+
+/// Information about a file that has been opened by the pre-processor
+#[derive(Debug)]
+pub struct SourceFile
+{
+	/// Path to the file (`None` for stdin)
+	pub path: Option<::std::path::PathBuf>,
+	/// Location of the `#include` that opened this file
+	pub included_from: Option<Span>,
+	/// The file was found in a system include directory (`-isystem`/`-idirafter`), or marked as a system header by a
+	/// linemarker
+	pub is_system_header: bool,
+	/// File whose text is read under this name (itself, unless it was named by a `#line` directive or linemarker)
+	pub source: FileId,
+}
+
+/// A range of source code that a token (or AST node) came from
+#[derive(Debug,Clone,PartialEq)]
+pub struct Span
+{
+	pub file: FileId,
+	/// Byte offset of the first character
+	pub offset: usize,
+	/// Length in bytes
+	pub len: usize,
+	pub line: usize,
+	/// Column of the first character (one-based, in characters)
+	pub column: usize,
+	/// The macro expansion that produced this token (if any)
+	///
+	/// NOTE: Tokens from a macro expansion use the location of the outermost invocation
+	pub expansion: Option<Rc<Expansion>>,
+}
+/// A macro expansion, forming a chain back to the source file
+#[derive(Debug,PartialEq)]
+pub struct Expansion
+{
+	/// Name of the macro that was expanded
+	pub name: String,
+	/// Location of the macro's name in its definition
+	pub definition: Span,
+	/// Expansion that the invocation was within
+	pub parent: Option<Rc<Expansion>>,
+}
+
+impl Span
+{
+	/// Create a zero-length span at the start of a file
+	pub fn start_of(file: FileId) -> Span
+	{
+		Span {
+			file: file,
+			offset: 0,
+			len: 0,
+			line: 1,
+			column: 1,
+			expansion: None,
+			}
+	}
+
+	/// Iterate the macro expansion chain (innermost first)
+	pub fn expansions(&self) -> Expansions
+	{
+		Expansions(self.expansion.as_ref().map(|v| &**v))
+	}
+}
+
+pub struct Expansions<'a>(Option<&'a Expansion>);
+impl<'a> Iterator for Expansions<'a>
+{
+	type Item = &'a Expansion;
+	fn next(&mut self) -> Option<&'a Expansion>
+	{
+		let rv = self.0;
+		self.0 = rv.and_then(|e| e.parent.as_ref().map(|v| &**v));
+		rv
+	}
+}
@@ -17,13 +17,15 @@ pub enum Token
 
 	// -- Expression leaves
 	Integer(u64, ::types::IntClass, String),
-	Float(f64, ::types::FloatClass, String),
-	Character(u64),
-	String(String),
+	Float(FloatValue, ::types::FloatClass, String),
+	Character(CharLit),
+	String(StringLit),
 	Ident(String),
 	
 	// -- Symbols
 	Hash,
+	/// `##` made by pasting two `#`s (the lexer gives two `Hash`es, as `##` is only an operator in a macro body)
+	DoubleHash,
 	Tilde,
 	Exclamation,
 	Period,
@@ -36,6 +38,8 @@ pub enum Token
 	Vargs,
 	QuestionMark,
 	Colon,
+	/// Alternative spelling of a punctuator (the pre-processor passes it on as the punctuator it spells)
+	Digraph(Digraph),
 	
 	Assign,
 	AssignAdd,
@@ -43,10 +47,11 @@ pub enum Token
 	AssignMul,
 	AssignDiv,
 	AssignMod,
-	AssignLogicOr,
-	AssignLogicAnd,
 	AssignBitOr,
 	AssignBitAnd,
+	AssignBitXor,
+	AssignShiftLeft,
+	AssignShiftRight,
 	
 	ShiftRight,
 	ShiftLeft,
@@ -121,7 +126,549 @@ pub enum Token
 	Rword_default,
 	// - Meta
 	Rword_sizeof,
+	Rword_Generic,
 }
+/// Formats the token as C source (e.g. for `-E` output)
+///
+/// NOTE: Non-source tokens (EOF, whitespace, and `Preprocessor`) format as nothing
+impl ::std::fmt::Display for Token
+{
+	fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result
+	{
+		f.write_str(match *self
+			{
+			Token::EOF => "",
+			Token::Whitespace => "",
+			Token::LineComment(ref s) => return write!(f, "//{}", s),
+			Token::BlockComment(ref s) => return write!(f, "/*{}*/", s),
+			Token::Newline => "",
+			Token::EscapedNewline => "",
+			Token::Preprocessor(_) => "",
+
+			Token::Integer(_, _, ref s) => s,
+			Token::Float(_, _, ref s) => s,
+			Token::Character(ref c) => return write!(f, "{}", c),
+			Token::String(ref s) => return write!(f, "{}", s),
+			Token::Ident(ref s) => s,
+
+			Token::Hash => "#",
+			Token::DoubleHash => "##",
+			Token::Tilde => "~",
+			Token::Exclamation => "!",
+			Token::Period => ".",
+			Token::DerefMember => "->",
+			Token::Comma => ",",
+			Token::Semicolon => ";",
+			Token::Star => "*",
+			Token::Slash => "/",
+			Token::Backslash => "\\",
+			Token::Vargs => "...",
+			Token::Digraph(d) => d.spelling(),
+			Token::QuestionMark => "?",
+			Token::Colon => ":",
+
+			Token::Assign => "=",
+			Token::AssignAdd => "+=",
+			Token::AssignSub => "-=",
+			Token::AssignMul => "*=",
+			Token::AssignDiv => "/=",
+			Token::AssignMod => "%=",
+			Token::AssignBitOr => "|=",
+			Token::AssignBitAnd => "&=",
+			Token::AssignBitXor => "^=",
+			Token::AssignShiftLeft => "<<=",
+			Token::AssignShiftRight => ">>=",
+
+			Token::ShiftRight => ">>",
+			Token::ShiftLeft => "<<",
+
+			Token::Equality => "==",
+			Token::NotEquals => "!=",
+			Token::Lt => "<",
+			Token::Gt => ">",
+			Token::LtE => "<=",
+			Token::GtE => ">=",
+
+			Token::Percent => "%",
+			Token::Plus => "+",
+			Token::Minus => "-",
+			Token::DoublePlus => "++",
+			Token::DoubleMinus => "--",
+
+			Token::Ampersand => "&",
+			Token::Pipe => "|",
+			Token::Caret => "^",
+			Token::DoubleAmpersand => "&&",
+			Token::DoublePipe => "||",
+
+			Token::BraceOpen => "{",
+			Token::BraceClose => "}",
+			Token::ParenOpen => "(",
+			Token::ParenClose => ")",
+			Token::SquareOpen => "[",
+			Token::SquareClose => "]",
+
+			Token::Rword_typedef => "typedef",
+			Token::Rword_auto => "auto",
+			Token::Rword_extern => "extern",
+			Token::Rword_static => "static",
+			Token::Rword_register => "register",
+			Token::Rword_inline => "inline",
+			Token::Rword_const => "const",
+			Token::Rword_volatile => "volatile",
+			Token::Rword_restrict => "restrict",
+			Token::Rword_void => "void",
+			Token::Rword_Bool => "_Bool",
+			Token::Rword_signed => "signed",
+			Token::Rword_unsigned => "unsigned",
+			Token::Rword_char => "char",
+			Token::Rword_short => "short",
+			Token::Rword_int => "int",
+			Token::Rword_long => "long",
+			Token::Rword_float => "float",
+			Token::Rword_double => "double",
+			Token::Rword_enum => "enum",
+			Token::Rword_union => "union",
+			Token::Rword_struct => "struct",
+			Token::Rword_if => "if",
+			Token::Rword_else => "else",
+			Token::Rword_while => "while",
+			Token::Rword_do => "do",
+			Token::Rword_for => "for",
+			Token::Rword_switch => "switch",
+			Token::Rword_goto => "goto",
+			Token::Rword_continue => "continue",
+			Token::Rword_break => "break",
+			Token::Rword_return => "return",
+			Token::Rword_case => "case",
+			Token::Rword_default => "default",
+			Token::Rword_sizeof => "sizeof",
+			Token::Rword_Generic => "_Generic",
+			})
+	}
+}
+/// Digraph spelling of a punctuator (C11 6.4.6p3), kept so that `-E` and stringification use the source spelling
+///
+/// NOTE: `%:%:` is lexed as two `%:`s, as `##` is two `#`s
+#[derive(Debug,PartialEq,Copy,Clone)]
+pub enum Digraph
+{
+	/// `<:`
+	SquareOpen,
+	/// `:>`
+	SquareClose,
+	/// `<%`
+	BraceOpen,
+	/// `%>`
+	BraceClose,
+	/// `%:`
+	Hash,
+	/// `%:%:` (only made by pasting, see `Token::DoubleHash`)
+	DoubleHash,
+}
+impl Digraph
+{
+	/// The punctuator this is an alternative spelling of
+	pub fn token(&self) -> Token
+	{
+		match *self
+		{
+		Digraph::SquareOpen => Token::SquareOpen,
+		Digraph::SquareClose => Token::SquareClose,
+		Digraph::BraceOpen => Token::BraceOpen,
+		Digraph::BraceClose => Token::BraceClose,
+		Digraph::Hash => Token::Hash,
+		Digraph::DoubleHash => Token::DoubleHash,
+		}
+	}
+	pub fn spelling(&self) -> &'static str
+	{
+		match *self
+		{
+		Digraph::SquareOpen => "<:",
+		Digraph::SquareClose => ":>",
+		Digraph::BraceOpen => "<%",
+		Digraph::BraceClose => "%>",
+		Digraph::Hash => "%:",
+		Digraph::DoubleHash => "%:%:",
+		}
+	}
+}
+impl Token
+{
+	/// Returns true for `#` (either spelling)
+	pub fn is_hash(&self) -> bool
+	{
+		match *self
+		{
+		Token::Hash | Token::Digraph(Digraph::Hash) => true,
+		_ => false,
+		}
+	}
+}
+
+/// Write a character within a C string/character literal, escaping as required
+fn write_escaped_char(f: &mut ::std::fmt::Formatter, c: u64, quote: char) -> ::std::fmt::Result
+{
+	match ::std::char::from_u32(c as u32)
+	{
+	Some(ch) if c <= 0x10FFFF => match ch
+		{
+		'\\' => f.write_str("\\\\"),
+		'\n' => f.write_str("\\n"),
+		'\r' => f.write_str("\\r"),
+		'\t' => f.write_str("\\t"),
+		_ if ch == quote => write!(f, "\\{}", ch),
+		' ' ... '~' => write!(f, "{}", ch),
+		_ if c >= 0x80 => write!(f, "{}", ch),
+		_ => write!(f, "\\{:03o}", c),
+		},
+	_ => write!(f, "\\x{:x}", c),
+	}
+}
+/// Write the code units of a literal (without the prefix and quotes)
+fn write_units(f: &mut ::std::fmt::Formatter, encoding: Encoding, units: &[u32], quote: char) -> ::std::fmt::Result
+{
+	match encoding
+	{
+	Encoding::Plain | Encoding::Utf8 => {
+		let bytes: Vec<u8> = units.iter().map(|&v| v as u8).collect();
+		let mut rest = &bytes[..];
+		while rest.len() > 0
+		{
+			// Valid UTF-8 is written as characters, and any other bytes as octal escapes
+			let (valid, invalid) = match ::std::str::from_utf8(rest)
+				{
+				Ok(s) => (s, None),
+				Err(e) => (::std::str::from_utf8(&rest[..e.valid_up_to()]).unwrap(), Some(rest[e.valid_up_to()])),
+				};
+			for c in valid.chars() {
+				write_escaped_char(f, c as u64, quote)?;
+			}
+			rest = &rest[valid.len()..];
+			if let Some(b) = invalid {
+				write!(f, "\\{:03o}", b)?;
+				rest = &rest[1..];
+			}
+		}
+		Ok( () )
+		},
+	Encoding::Utf16 | Encoding::Wide | Encoding::Utf32 => {
+		let chars: Vec<Result<char,u32>> = if encoding == Encoding::Utf16 {
+				let u16s: Vec<u16> = units.iter().map(|&v| v as u16).collect();
+				::std::char::decode_utf16(u16s.into_iter()).map(|r| r.map_err(|e| e.unpaired_surrogate() as u32)).collect()
+			}
+			else {
+				units.iter().map(|&v| ::std::char::from_u32(v).ok_or(v)).collect()
+			};
+		// A hex escape continues while there are hex digits, so digits after one are also escaped
+		let mut after_hex = false;
+		for c in chars
+		{
+			match c
+			{
+			Ok(c) if !(after_hex && c.is_digit(16)) => {
+				write_escaped_char(f, c as u64, quote)?;
+				after_hex = false;
+				},
+			Ok(c) => write!(f, "\\x{:x}", c as u32)?,
+			Err(v) => {
+				write!(f, "\\x{:x}", v)?;
+				after_hex = true;
+				},
+			}
+		}
+		Ok( () )
+		},
+	}
+}
+
+/// Encoding prefix of a character constant or string literal
+#[derive(Debug,PartialEq,Copy,Clone)]
+pub enum Encoding
+{
+	/// No prefix, `char` elements
+	Plain,
+	/// `u8`, UTF-8 encoded `char` elements
+	Utf8,
+	/// `L`, `wchar_t` elements (a 32-bit `int`, as on Linux)
+	Wide,
+	/// `u`, UTF-16 encoded `char16_t` elements
+	Utf16,
+	/// `U`, UTF-32 encoded `char32_t` elements
+	Utf32,
+}
+impl Default for Encoding {
+	fn default() -> Self { Encoding::Plain }
+}
+impl Encoding
+{
+	/// Get the encoding for a literal prefix
+	pub fn from_prefix(prefix: &str) -> Option<Encoding>
+	{
+		match prefix
+		{
+		"u8" => Some(Encoding::Utf8),
+		"L" => Some(Encoding::Wide),
+		"u" => Some(Encoding::Utf16),
+		"U" => Some(Encoding::Utf32),
+		_ => None,
+		}
+	}
+	pub fn prefix(&self) -> &'static str
+	{
+		match *self
+		{
+		Encoding::Plain => "",
+		Encoding::Utf8 => "u8",
+		Encoding::Wide => "L",
+		Encoding::Utf16 => "u",
+		Encoding::Utf32 => "U",
+		}
+	}
+	/// Type of each element (code unit)
+	#[allow(dead_code)]
+	pub fn element_type(&self) -> ::types::IntClass
+	{
+		match *self
+		{
+		Encoding::Plain | Encoding::Utf8 => ::types::IntClass::char(),
+		Encoding::Wide => ::types::IntClass::Int(::types::Signedness::Signed),
+		Encoding::Utf16 => ::types::IntClass::Short(::types::Signedness::Unsigned),
+		Encoding::Utf32 => ::types::IntClass::Int(::types::Signedness::Unsigned),
+		}
+	}
+	/// Largest value of a single code unit
+	pub fn max_unit(&self) -> u64
+	{
+		match *self
+		{
+		Encoding::Plain | Encoding::Utf8 => 0xFF,
+		Encoding::Utf16 => 0xFFFF,
+		Encoding::Wide | Encoding::Utf32 => 0xFFFF_FFFF,
+		}
+	}
+	/// Append the code units for a character
+	pub fn encode(&self, ch: char, dst: &mut Vec<u32>)
+	{
+		match *self
+		{
+		Encoding::Plain | Encoding::Utf8 => {
+			let mut buf = [0; 4];
+			dst.extend( ch.encode_utf8(&mut buf).bytes().map(|b| b as u32) );
+			},
+		Encoding::Utf16 => {
+			let mut buf = [0; 2];
+			dst.extend( ch.encode_utf16(&mut buf).iter().map(|&v| v as u32) );
+			},
+		Encoding::Wide | Encoding::Utf32 => dst.push(ch as u32),
+		}
+	}
+}
+
+/// Value of a floating constant, correctly rounded to its type
+#[derive(Debug,PartialEq,Copy,Clone)]
+pub enum FloatValue
+{
+	/// `mantissa * 2^exponent`
+	Finite {
+		mantissa: u64,
+		exponent: i32,
+	},
+	/// Too large for the type
+	Infinity,
+}
+impl FloatValue
+{
+	pub const ZERO: FloatValue = FloatValue::Finite { mantissa: 0, exponent: 0 };
+
+	pub fn is_zero(&self) -> bool
+	{
+		match *self
+		{
+		FloatValue::Finite { mantissa, .. } => mantissa == 0,
+		FloatValue::Infinity => false,
+		}
+	}
+	/// Convert to a `f64` (exact for `float` and `double` constants, rounded again for `long double`)
+	pub fn to_f64(&self) -> f64
+	{
+		match *self
+		{
+		FloatValue::Finite { mantissa, exponent } => {
+			// Scale in steps that can't overflow or underflow (each power of two is exact)
+			let mut rv = mantissa as f64;
+			let mut exponent = exponent;
+			while exponent != 0
+			{
+				let step = ::std::cmp::max(-1000, ::std::cmp::min(1000, exponent));
+				rv *= f64::from_bits( ((1023 + step) as u64) << 52 );
+				exponent -= step;
+			}
+			rv
+			},
+		FloatValue::Infinity => ::std::f64::INFINITY,
+		}
+	}
+}
+
+/// A character constant (with escapes processed)
+///
+/// Characters are stored as code units in the constant's encoding (so a non-ASCII character in a plain constant is a
+/// multi-character constant, as with GCC). Formats as a C character constant.
+#[derive(Debug,PartialEq,Clone)]
+pub struct CharLit
+{
+	pub encoding: Encoding,
+	pub units: Vec<u32>,
+	/// Source text (including the prefix and quotes), written back as-is
+	pub spelling: Option<String>,
+}
+impl CharLit
+{
+	/// Returns true if this has more than one code unit (a plain multi-character constant has type `int`)
+	pub fn is_multichar(&self) -> bool
+	{
+		self.units.len() > 1
+	}
+	/// Value of the constant, as GCC computes it
+	///
+	/// - A single `char` is sign-extended if `char` is signed
+	/// - A plain multi-character constant is the `int` formed from the last four characters (the first being the
+	///   most significant byte)
+	/// - Otherwise the last code unit is used, sign-extended for `wchar_t`
+	pub fn value(&self, char_is_signed: bool) -> i64
+	{
+		let last = self.units.last().cloned().unwrap_or(0);
+		match self.encoding
+		{
+		Encoding::Plain if self.is_multichar() => {
+			let v = self.units.iter().fold(0u32, |v, &u| (v << 8) | (u & 0xFF));
+			v as i32 as i64
+			},
+		Encoding::Plain if char_is_signed => last as u8 as i8 as i64,
+		Encoding::Wide => last as i32 as i64,
+		_ => last as i64,
+		}
+	}
+}
+impl ::std::fmt::Display for CharLit
+{
+	fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result
+	{
+		if let Some(ref s) = self.spelling {
+			return f.write_str(s);
+		}
+		write!(f, "{}'", self.encoding.prefix())?;
+		write_units(f, self.encoding, &self.units, '\'')?;
+		f.write_str("'")
+	}
+}
+
+/// Contents of a string literal (with escapes processed)
+///
+/// Stored as code units in the literal's encoding. Octal and hex escapes can give any unit value, so a plain literal
+/// isn't always valid UTF-8. Formats as a C string literal.
+#[derive(PartialEq,Clone,Default)]
+pub struct StringLit
+{
+	pub encoding: Encoding,
+	pub units: Vec<u32>,
+	/// Source text (including the prefix and quotes), `None` once the literal has been changed (e.g. concatenated)
+	pub spelling: Option<String>,
+}
+impl StringLit
+{
+	/// Contents as text (invalid code units are replaced), e.g. for file names
+	pub fn to_string_lossy(&self) -> ::std::borrow::Cow<str>
+	{
+		match self.encoding
+		{
+		Encoding::Plain | Encoding::Utf8 => String::from_utf8_lossy(&self.units.iter().map(|&v| v as u8).collect::<Vec<_>>()).into_owned().into(),
+		Encoding::Utf16 => String::from_utf16_lossy(&self.units.iter().map(|&v| v as u16).collect::<Vec<_>>()).into(),
+		Encoding::Wide | Encoding::Utf32 => self.units.iter().map(|&v| ::std::char::from_u32(v).unwrap_or('\u{FFFD}')).collect::<String>().into(),
+		}
+	}
+
+	/// Append an adjacent literal (C11 6.4.5p5)
+	///
+	/// If either has a prefix the result has that prefix, the characters of a plain literal being re-encoded.
+	/// Returns `false` (leaving `self` unchanged) if both have different prefixes, which isn't supported.
+	pub fn append(&mut self, other: StringLit) -> bool
+	{
+		if other.encoding == self.encoding || other.encoding == Encoding::Plain {
+			let other = other.convert(self.encoding);
+			self.units.extend(other.units);
+			self.spelling = None;
+			true
+		}
+		else if self.encoding == Encoding::Plain {
+			let this = ::std::mem::replace(self, StringLit::default()).convert(other.encoding);
+			*self = this;
+			self.units.extend(other.units);
+			true
+		}
+		else {
+			false
+		}
+	}
+	/// Re-encode a plain literal (escaped values are kept as-is)
+	fn convert(self, encoding: Encoding) -> StringLit
+	{
+		if self.encoding != Encoding::Plain || encoding == Encoding::Plain || encoding == Encoding::Utf8 {
+			return StringLit { encoding: encoding, units: self.units, spelling: None };
+		}
+		let bytes: Vec<u8> = self.units.iter().map(|&v| v as u8).collect();
+		let mut units = Vec::new();
+		let mut rest = &bytes[..];
+		while rest.len() > 0
+		{
+			let valid_len = match ::std::str::from_utf8(rest)
+				{
+				Ok(s) => s.len(),
+				Err(e) => e.valid_up_to(),
+				};
+			for c in ::std::str::from_utf8(&rest[..valid_len]).unwrap().chars() {
+				encoding.encode(c, &mut units);
+			}
+			rest = &rest[valid_len..];
+			if rest.len() > 0 {
+				units.push(rest[0] as u32);
+				rest = &rest[1..];
+			}
+		}
+		StringLit { encoding: encoding, units: units, spelling: None }
+	}
+}
+impl From<String> for StringLit {
+	fn from(v: String) -> StringLit {
+		StringLit {
+			encoding: Encoding::Plain,
+			units: v.bytes().map(|b| b as u32).collect(),
+			spelling: None,
+			}
+	}
+}
+impl ::std::fmt::Debug for StringLit
+{
+	fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result
+	{
+		write!(f, "{}{:?}", self.encoding.prefix(), self.to_string_lossy())
+	}
+}
+impl ::std::fmt::Display for StringLit
+{
+	fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result
+	{
+		if let Some(ref s) = self.spelling {
+			return f.write_str(s);
+		}
+		write!(f, "{}\"", self.encoding.prefix())?;
+		write_units(f, self.encoding, &self.units, '"')?;
+		f.write_str("\"")
+	}
+}
+
 impl From<Preprocessor> for Token {
 	fn from(v: Preprocessor) -> Token {
 		Token::Preprocessor(v)
@@ -155,18 +702,27 @@ pub enum Preprocessor
 	MacroDefine {
 		name: String,
 		arg_names: Option< Vec<String> >,
+		/// Name of the variable argument (`__VA_ARGS__` for a bare `...`)
+		variadic: Option<String>,
 		expansion: Vec<Token>,
 	},
 	/// Macro un-definition
 	MacroUndefine {
 		name: String,
 	},
+	/// A `#pragma` (or `_Pragma`) not handled by the pre-processor, with the text after `pragma`
+	/// NOTE: Only emitted if `Options::propagate_pragmas` is set
+	Pragma(String),
+	/// Conditional directives in the main file (from the `#` to the end of the line), along with the text of any
+	/// groups they skip
+	/// NOTE: Only emitted if `Options::propagate_conditionals` is set
+	Conditional(String),
 	/// A macro invocation/expansion
 	/// NOTE: This only gets emitted if macros are being handled by the pre-processor
 	MacroInvocaton {
 		/// Input tokens (with whitespace stripped)
 		input: Vec<Token>,
-		/// Output tokens
+		/// Output tokens (fully expanded)
 		output: Vec<Token>,
 	},
 }
@@ -55,7 +55,11 @@ impl ArraySizeExpr {
 }
 impl PartialEq for ArraySizeExpr {
 	fn eq(&self, v: &Self) -> bool {
-		panic!("TODO: eq for ArraySizeExpr - {:?} == {:?}", self.0, v.0);
+		match (self.0.literal_integer(), v.0.literal_integer())
+		{
+		(Some(a), Some(b)) => a == b,
+		_ => panic!("TODO: eq for ArraySizeExpr - {:?} == {:?}", self.0, v.0),
+		}
 	}
 }
 impl ::std::ops::Deref for ArraySizeExpr {
@@ -210,6 +214,19 @@ pub struct Struct
 {
 	pub name: String,
 	pub items: Option<Vec<(TypeRef,String)>>,
+	/// Round-trip mode: Comments within the definition
+	pub comments: Vec<MemberComment>,
+}
+/// Round-trip mode: A comment within a struct definition
+#[derive(Debug,PartialEq)]
+pub struct MemberComment
+{
+	/// Index of the member the comment is attached to (`items.len()` for comments before the closing brace)
+	pub index: usize,
+	/// The comment follows the member on the same line (otherwise it's on a line before the member)
+	pub trailing: bool,
+	/// Comment text (including the delimiters)
+	pub text: String,
 }
 
 #[derive(Debug,PartialEq)]
@@ -291,6 +308,7 @@ impl Struct
 		RcRefCellPtrEq::new(Struct {
 			name: name.to_string(),
 			items: None,
+			comments: Vec::new(),
 			})
 	}
 	
@@ -303,6 +321,12 @@ impl Struct
 		assert!( self.items.is_none() );
 		self.items = Some(items);
 	}
+	/// Undo `set_items` (for a reverted speculative parse)
+	pub fn clear_items(&mut self)
+	{
+		self.items = None;
+		self.comments.clear();
+	}
 }
 
 impl Union
@@ -324,6 +348,11 @@ impl Union
 		assert!( self.items.is_none() );
 		self.items = Some(items);
 	}
+	/// Undo `set_items` (for a reverted speculative parse)
+	pub fn clear_items(&mut self)
+	{
+		self.items = None;
+	}
 }
 
 impl Enum
@@ -345,6 +374,11 @@ impl Enum
 		assert!( self.items.is_none() );
 		self.items = Some(items);
 	}
+	/// Undo `set_items` (for a reverted speculative parse)
+	pub fn clear_items(&mut self)
+	{
+		self.items = None;
+	}
 }
 
 // vim: ft=rust